@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use vk_test::bf::{BfImage, load_bf_from_bytes, load_geometry_from_bytes, load_vfs_from_bytes};
+
+// Loads the input as whole BF file and parses the payload as every kind.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(file) = load_bf_from_bytes(data) {
+        let _ = BfImage::new(&file);
+        if let Ok(payload) = file.payload() {
+            let _ = load_geometry_from_bytes(&payload);
+            let _ = load_vfs_from_bytes(&payload);
+        }
+    }
+});
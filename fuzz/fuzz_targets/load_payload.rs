@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use vk_test::bf::{load_geometry_from_bytes, load_vfs_from_bytes};
+
+// Parses the input directly as decompressed payload of each kind.
+fuzz_target!(|data: &[u8]| {
+    let _ = load_geometry_from_bytes(data);
+    let _ = load_vfs_from_bytes(data);
+});
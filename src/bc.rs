@@ -0,0 +1,1329 @@
+//! Encoders and decoders of BC (DXT) block compressed formats.
+//!
+//! All encoders take pixels with four components (RGBA) and produce blocks in
+//! row-major order. Images with size that is not multiple of four are padded by
+//! repeating the edge pixels. Decoders produce pixels with four components and
+//! crop the padding.
+
+use half::f16;
+
+/// Interpolation weights of 4-bit indices used by BC6H and BC7.
+const WEIGHTS4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Writer of bit fields in the order used by BC6H and BC7 (least significant bit first).
+struct BitWriter {
+    bits: u128,
+    offset: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { bits: 0, offset: 0 }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        let mask = (1u128 << count) - 1;
+        self.bits |= (value as u128 & mask) << self.offset;
+        self.offset += count;
+    }
+
+    fn finish(self, out: &mut [u8]) {
+        debug_assert_eq!(self.offset, 128);
+        out.copy_from_slice(&self.bits.to_le_bytes());
+    }
+}
+
+/// Reads 4x4 block of RGBA pixels with top-left corner at pixel `(x, y)`.
+fn read_block<T: Copy + Default>(rgba: &[T], width: usize, height: usize, x: usize, y: usize) -> [[T; 4]; 16] {
+    let mut block = [[T::default(); 4]; 16];
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let px = (x + i % 4).min(width - 1);
+        let py = (y + i / 4).min(height - 1);
+        let offset = (py * width + px) * 4;
+        pixel.copy_from_slice(&rgba[offset..offset + 4]);
+    }
+    block
+}
+
+/// Splits the image into blocks and encodes each block with `encode`.
+fn encode_blocks<T, F>(rgba: &[T], width: usize, height: usize, block_size: usize, encode: F) -> Vec<u8>
+    where T: Copy + Default, F: Fn(&[[T; 4]; 16], &mut [u8]) {
+    assert_eq!(rgba.len(), width * height * 4, "expected four components per pixel");
+
+    let (blocks_x, blocks_y) = (width.div_ceil(4).max(1), height.div_ceil(4).max(1));
+    let mut out = vec![0u8; blocks_x * blocks_y * block_size];
+    for (i, chunk) in out.chunks_exact_mut(block_size).enumerate() {
+        let block = read_block(rgba, width, height, (i % blocks_x) * 4, (i / blocks_x) * 4);
+        encode(&block, chunk);
+    }
+    out
+}
+
+/// Returns the index of the palette entry closest to the value.
+fn nearest<T: Copy, F: Fn(T) -> u32>(palette: &[T], distance: F) -> usize {
+    (0..palette.len())
+        .min_by_key(|&i| distance(palette[i]))
+        .unwrap()
+}
+
+/// Returns the eight values of BC4 block with specified endpoints.
+pub(crate) fn bc4_palette(r0: u8, r1: u8) -> [u8; 8] {
+    let (a, b) = (r0 as u32, r1 as u32);
+    let mut palette = [r0, r1, 0, 0, 0, 0, 0, 255];
+    if r0 > r1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a + i as u32 * b) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a + i as u32 * b) / 5) as u8;
+        }
+    }
+    palette
+}
+
+fn encode_bc4_block(values: [u8; 16], out: &mut [u8]) {
+    let max = *values.iter().max().unwrap();
+    let min = *values.iter().min().unwrap();
+    let palette = bc4_palette(max, min);
+
+    let mut indices = 0u64;
+    for (i, &v) in values.iter().enumerate() {
+        let index = nearest(&palette, |p| (p as i32 - v as i32).pow(2) as u32);
+        indices |= (index as u64) << (3 * i);
+    }
+
+    out[0] = max;
+    out[1] = min;
+    out[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+}
+
+fn channel(block: &[[u8; 4]; 16], c: usize) -> [u8; 16] {
+    let mut values = [0u8; 16];
+    for (v, pixel) in values.iter_mut().zip(block.iter()) {
+        *v = pixel[c];
+    }
+    values
+}
+
+/// Encodes red channel of RGBA8 image to BC4.
+pub fn encode_bc4(rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    encode_blocks(rgba, width, height, 8, |block, out| {
+        encode_bc4_block(channel(block, 0), out);
+    })
+}
+
+/// Encodes red and green channels of RGBA8 image to BC5.
+pub fn encode_bc5(rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    encode_blocks(rgba, width, height, 16, |block, out| {
+        encode_bc4_block(channel(block, 0), &mut out[0..8]);
+        encode_bc4_block(channel(block, 1), &mut out[8..16]);
+    })
+}
+
+/// Returns the two endpoints of the line that fits the points best. The line goes
+/// through the mean of the points in direction of their principal axis.
+fn principal_endpoints(points: &[[f32; 4]]) -> ([f32; 4], [f32; 4]) {
+    const N: usize = 4;
+    let mut mean = [0f32; N];
+    for p in points.iter() {
+        for c in 0..N { mean[c] += p[c] / points.len() as f32; }
+    }
+
+    let mut covariance = [[0f32; N]; N];
+    for p in points.iter() {
+        for i in 0..N {
+            for j in 0..N {
+                covariance[i][j] += (p[i] - mean[i]) * (p[j] - mean[j]);
+            }
+        }
+    }
+
+    // power iteration starting with the diagonal of bounding box
+    let mut axis = [0f32; N];
+    for (c, a) in axis.iter_mut().enumerate() {
+        let max = points.iter().map(|p| p[c]).fold(f32::MIN, f32::max);
+        let min = points.iter().map(|p| p[c]).fold(f32::MAX, f32::min);
+        *a = max - min;
+    }
+    for _ in 0..8 {
+        let mut next = [0f32; N];
+        for i in 0..N {
+            for j in 0..N { next[i] += covariance[i][j] * axis[j]; }
+        }
+        let length = next.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if length < 1e-6 { break; }
+        for c in 0..N { axis[c] = next[c] / length; }
+    }
+
+    let length = axis.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if length < 1e-6 {
+        return (mean, mean);
+    }
+
+    let project = |p: &[f32; 4]| (0..N).map(|c| (p[c] - mean[c]) * axis[c] / length).sum::<f32>();
+    let min = points.iter().map(project).fold(f32::MAX, f32::min);
+    let max = points.iter().map(project).fold(f32::MIN, f32::max);
+
+    let mut e0 = mean;
+    let mut e1 = mean;
+    for c in 0..N {
+        e0[c] += min * axis[c] / length;
+        e1[c] += max * axis[c] / length;
+    }
+    (e0, e1)
+}
+
+/// Returns the points of the block which belong to the subset of the partition.
+fn subset_points(points: &[[f32; 4]; 16], subsets: usize, partition: usize, s: usize) -> Vec<[f32; 4]> {
+    (0..16)
+        .filter(|&i| subset(subsets, partition, i) == s)
+        .map(|i| points[i])
+        .collect()
+}
+
+/// Number of partitions (with the lowest estimated error) fully encoded for
+/// each mode with more subsets.
+const PARTITION_CANDIDATES: usize = 4;
+
+/// Estimates the error of encoding the points with the partition as the sum of
+/// squared distances of the points from the lines that fit their subsets. That
+/// is the variance of each subset which is not along its principal axis.
+fn partition_error(points: &[[f32; 4]; 16], subsets: usize, partition: usize) -> f32 {
+    let mut counts = [0f32; 3];
+    let mut sums = [[0f32; 4]; 3];
+    let mut products = [[[0f32; 4]; 4]; 3];
+    for (i, p) in points.iter().enumerate() {
+        let s = subset(subsets, partition, i);
+        counts[s] += 1.0;
+        for a in 0..4 {
+            sums[s][a] += p[a];
+            for b in 0..4 { products[s][a][b] += p[a] * p[b]; }
+        }
+    }
+
+    let mut error = 0.0;
+    for s in 0..subsets {
+        let mut covariance = [[0f32; 4]; 4];
+        for a in 0..4 {
+            for b in 0..4 {
+                covariance[a][b] = products[s][a][b] - sums[s][a] * sums[s][b] / counts[s];
+            }
+        }
+        let trace = (0..4).map(|c| covariance[c][c]).sum::<f32>();
+
+        // power iteration starting with the channel of the largest variance
+        let start = (0..4).max_by(|a, b| covariance[*a][*a].total_cmp(&covariance[*b][*b])).unwrap();
+        let mut axis = covariance[start];
+        let mut eigenvalue = 0.0;
+        for _ in 0..8 {
+            let length = axis.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if length < 1e-6 { break; }
+            let mut next = [0f32; 4];
+            for a in 0..4 {
+                for b in 0..4 { next[a] += covariance[a][b] * axis[b] / length; }
+            }
+            eigenvalue = next.iter().map(|x| x * x).sum::<f32>().sqrt();
+            axis = next;
+        }
+        error += (trace - eigenvalue).max(0.0);
+    }
+    error
+}
+
+/// Returns the first `count` partitions of blocks with two or three subsets
+/// ordered by their estimated error (see `partition_error`).
+fn best_partitions(points: &[[f32; 4]; 16], subsets: usize, count: usize) -> Vec<usize> {
+    let mut partitions: Vec<(f32, usize)> = (0..count)
+        .map(|p| (partition_error(points, subsets, p), p))
+        .collect();
+    partitions.sort_by(|a, b| a.0.total_cmp(&b.0));
+    partitions.into_iter().take(PARTITION_CANDIDATES).map(|(_, p)| p).collect()
+}
+
+/// Quantized endpoint of BC7 block: the stored values of the channels, p-bit and
+/// the 8-bit color the endpoint decodes to.
+#[derive(Debug, Copy, Clone, Default)]
+struct Bc7Endpoint {
+    values: [u32; 4],
+    pbit: u32,
+    color: [u32; 4],
+}
+
+/// Quantizes 8-bit value to `bits` bits which are followed by `pbit` (if any).
+/// Returns the stored value and the value it decodes to.
+fn quantize_bc7_channel(value: f32, bits: u32, pbit: Option<u32>) -> (u32, u32) {
+    let decoded = |q: u32| match pbit {
+        Some(p) => expand(q << 1 | p, bits + 1),
+        None => expand(q, bits),
+    };
+    let max = (1 << bits) - 1;
+    let estimate = (value.clamp(0.0, 255.0) / 255.0 * max as f32) as u32;
+    let distance = |q: &u32| (decoded(*q) as f32 - value).abs();
+    let q = (estimate.saturating_sub(1)..=(estimate + 1).min(max))
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap();
+    (q, decoded(q))
+}
+
+/// Quantizes 8-bit endpoint with precision of the mode and returns the squared
+/// error of its color. Channels which are not stored decode to 255.
+fn quantize_bc7_endpoint(e: [f32; 4], mode: &Bc7Mode, pbit: Option<u32>) -> (Bc7Endpoint, f32) {
+    let mut endpoint = Bc7Endpoint { pbit: pbit.unwrap_or(0), ..Bc7Endpoint::default() };
+    let mut error = 0.0;
+    for (c, &value) in e.iter().enumerate() {
+        let bits = if c < 3 { mode.color_bits } else { mode.alpha_bits };
+        if bits == 0 {
+            endpoint.color[c] = 255;
+            continue;
+        }
+        let (stored, color) = quantize_bc7_channel(value, bits, pbit);
+        endpoint.values[c] = stored;
+        endpoint.color[c] = color;
+        error += (color as f32 - value).powi(2);
+    }
+    (endpoint, error)
+}
+
+/// Quantizes both endpoints of a subset choosing the p-bits with the lowest error.
+fn quantize_bc7_endpoints(e0: [f32; 4], e1: [f32; 4], mode: &Bc7Mode) -> [Bc7Endpoint; 2] {
+    let with_pbit = |e: [f32; 4], p: u32| quantize_bc7_endpoint(e, mode, Some(p));
+    let best = |a: (Bc7Endpoint, f32), b: (Bc7Endpoint, f32)| if b.1 < a.1 { b } else { a };
+    if mode.shared_pbits {
+        let pair = |p: u32| {
+            let (q0, error0) = with_pbit(e0, p);
+            let (q1, error1) = with_pbit(e1, p);
+            ([q0, q1], error0 + error1)
+        };
+        let (a, b) = (pair(0), pair(1));
+        if b.1 < a.1 { b.0 } else { a.0 }
+    } else if mode.endpoint_pbits {
+        [best(with_pbit(e0, 0), with_pbit(e0, 1)).0, best(with_pbit(e1, 0), with_pbit(e1, 1)).0]
+    } else {
+        [quantize_bc7_endpoint(e0, mode, None).0, quantize_bc7_endpoint(e1, mode, None).0]
+    }
+}
+
+/// Encodes block using BC7 mode (without rotation and index selection) and partition.
+fn encode_bc7_mode(block: &[[u8; 4]; 16], mode: usize, partition: usize, out: &mut [u8]) {
+    let desc = &BC7_MODES[mode];
+    let mut points = [[0f32; 4]; 16];
+    for (p, pixel) in points.iter_mut().zip(block.iter()) {
+        for c in 0..4 { p[c] = pixel[c] as f32; }
+        // modes without alpha decode as opaque, alpha must not affect the endpoints
+        if desc.alpha_bits == 0 { p[3] = 255.0; }
+    }
+
+    let mut endpoints = [[Bc7Endpoint::default(); 2]; 3];
+    for (s, subset) in endpoints.iter_mut().enumerate().take(desc.subsets) {
+        let (e0, e1) = principal_endpoints(&subset_points(&points, desc.subsets, partition, s));
+        *subset = quantize_bc7_endpoints(e0, e1, desc);
+    }
+
+    let mut indices = [0u32; 16];
+    for (i, (index, pixel)) in indices.iter_mut().zip(block.iter()).enumerate() {
+        let [e0, e1] = endpoints[subset(desc.subsets, partition, i)];
+        *index = nearest(weights(desc.index_bits), |w| {
+            (0..4).map(|c| (interpolate(e0.color[c], e1.color[c], w) as i32 - pixel[c] as i32).pow(2) as u32).sum()
+        }) as u32;
+    }
+
+    // the most significant bit of the index of anchor pixels is implicitly zero
+    let max_index = (1 << desc.index_bits) - 1;
+    for (s, subset_endpoints) in endpoints.iter_mut().enumerate().take(desc.subsets) {
+        let in_subset = |i: &usize| subset(desc.subsets, partition, *i) == s;
+        let anchor = (0..16).filter(in_subset).find(|&i| is_anchor(desc.subsets, partition, i)).unwrap();
+        if indices[anchor] > max_index / 2 {
+            subset_endpoints.swap(0, 1);
+            for i in (0..16).filter(in_subset) {
+                indices[i] = max_index - indices[i];
+            }
+        }
+    }
+
+    // endpoints are stored by channel, then by subset
+    let mut writer = BitWriter::new();
+    writer.write(1 << mode, mode as u32 + 1);
+    writer.write(partition as u32, desc.partition_bits);
+    for c in 0..4 {
+        let bits = if c < 3 { desc.color_bits } else { desc.alpha_bits };
+        for subset in endpoints.iter().take(desc.subsets) {
+            for endpoint in subset.iter() {
+                writer.write(endpoint.values[c], bits);
+            }
+        }
+    }
+    for subset in endpoints.iter().take(desc.subsets) {
+        if desc.shared_pbits {
+            writer.write(subset[0].pbit, 1);
+        } else if desc.endpoint_pbits {
+            writer.write(subset[0].pbit, 1);
+            writer.write(subset[1].pbit, 1);
+        }
+    }
+    for (i, &index) in indices.iter().enumerate() {
+        writer.write(index, desc.index_bits - is_anchor(desc.subsets, partition, i) as u32);
+    }
+    writer.finish(out);
+}
+
+/// Modes tried by the BC7 encoder: one subset with 4-bit indices and two subsets
+/// with 3-bit or 2-bit indices (without and with alpha).
+const BC7_ENCODER_MODES: [usize; 4] = [6, 1, 3, 7];
+
+fn encode_bc7_block(block: &[[u8; 4]; 16], out: &mut [u8]) {
+    let mut points = [[0f32; 4]; 16];
+    for (p, pixel) in points.iter_mut().zip(block.iter()) {
+        for c in 0..4 { p[c] = pixel[c] as f32; }
+    }
+
+    // all the modes with two subsets have 64 partitions
+    let partitions = best_partitions(&points, 2, 64);
+
+    let mut best = (u32::MAX, [0u8; 16]);
+    let mut candidate = [0u8; 16];
+    for &mode in BC7_ENCODER_MODES.iter() {
+        let candidates = if BC7_MODES[mode].subsets == 2 { &partitions[..] } else { &[0][..] };
+        for &partition in candidates {
+            encode_bc7_mode(block, mode, partition, &mut candidate);
+            let error = decode_bc7_block(&candidate).iter()
+                .zip(block.iter())
+                .flat_map(|(d, p)| (0..4).map(move |c| (d[c] as i32 - p[c] as i32).pow(2) as u32))
+                .sum();
+            if error < best.0 {
+                best = (error, candidate);
+            }
+        }
+    }
+    out.copy_from_slice(&best.1);
+}
+
+/// Encodes RGBA8 image to BC7. Each block is encoded with the mode (6, 1, 3 or 7)
+/// and partition which decodes with the lowest error. Only a few partitions with
+/// the lowest estimated error are encoded for each mode.
+pub fn encode_bc7(rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    encode_blocks(rgba, width, height, 16, encode_bc7_block)
+}
+
+/// Converts non-negative float to the 16-bit domain in which BC6H interpolates.
+fn bc6h_domain(value: f32) -> u32 {
+    let half = f16::from_f32(value.clamp(0.0, 65504.0)).to_bits() as u32;
+    ((half << 6) / 31).min(0xFFFF)
+}
+
+/// Quantizes value in the interpolation domain to endpoint with `precision` bits.
+fn quantize_bc6h(value: f32, precision: u32) -> u32 {
+    let max = (1 << precision) - 1;
+    let value = value.clamp(0.0, 65535.0);
+    let estimate = ((value / 65536.0 * (max + 1) as f32) as u32).min(max);
+    let distance = |q: &u32| (bc6h_unquantize(*q, precision) as f32 - value).abs();
+    (estimate.saturating_sub(1)..=(estimate + 1).min(max))
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap()
+}
+
+/// Returns indices of the points to palettes of their regions. Anchor pixels
+/// are restricted to the first half of the palette when `anchors` is set.
+fn bc6h_indices(points: &[[f32; 4]; 16], endpoints: &[[u32; 3]; 4], regions: usize, partition: usize, anchors: bool) -> [u32; 16] {
+    let palette = weights(if regions == 2 { 3 } else { 4 });
+    let mut indices = [0u32; 16];
+    for (i, (index, point)) in indices.iter_mut().zip(points.iter()).enumerate() {
+        let region = subset(regions, partition, i);
+        let (e0, e1) = (endpoints[region * 2], endpoints[region * 2 + 1]);
+        let candidates = if anchors && is_anchor(regions, partition, i) { &palette[..palette.len() / 2] } else { palette };
+        *index = nearest(candidates, |w| {
+            (0..3).map(|c| (interpolate(e0[c], e1[c], w) as f32 - point[c]).powi(2) as u32).sum()
+        }) as u32;
+    }
+    indices
+}
+
+/// Encodes block with points in the interpolation domain using BC6H mode (index
+/// to `BC6H_MODES`) and partition.
+fn encode_bc6h_mode(points: &[[f32; 4]; 16], mode: usize, partition: usize, out: &mut [u8]) {
+    let desc = &BC6H_MODES[mode];
+    let regions = desc.regions;
+    let endpoint_count = regions * 2;
+
+    let mut endpoints = [[0u32; 3]; 4];
+    for region in 0..regions {
+        let (e0, e1) = principal_endpoints(&subset_points(points, regions, partition, region));
+        for c in 0..3 {
+            endpoints[region * 2][c] = quantize_bc6h(e0[c], desc.precision);
+            endpoints[region * 2 + 1][c] = quantize_bc6h(e1[c], desc.precision);
+        }
+    }
+
+    // the most significant bit of the index of anchor pixels is implicitly zero
+    let unquantize = |endpoints: &[[u32; 3]; 4]| {
+        let mut unquantized = [[0u32; 3]; 4];
+        for (u, e) in unquantized.iter_mut().zip(endpoints.iter()).take(endpoint_count) {
+            for c in 0..3 { u[c] = bc6h_unquantize(e[c], desc.precision); }
+        }
+        unquantized
+    };
+    let indices = bc6h_indices(points, &unquantize(&endpoints), regions, partition, false);
+    let half = if regions == 2 { 4 } else { 8 };
+    for region in 0..regions {
+        let anchor = (0..16).find(|&i| subset(regions, partition, i) == region && is_anchor(regions, partition, i)).unwrap();
+        if indices[anchor] >= half {
+            endpoints.swap(region * 2, region * 2 + 1);
+        }
+    }
+
+    // transformed endpoints are stored as signed deltas from the first endpoint,
+    // deltas which do not fit are clamped
+    let mut stored = [0u32; 12];
+    for e in 0..endpoint_count {
+        for c in 0..3 {
+            stored[e * 3 + c] = if e == 0 || !desc.transformed {
+                endpoints[e][c]
+            } else {
+                let bits = desc.deltas[c];
+                let limit = 1 << (bits - 1);
+                let delta = (endpoints[e][c] as i32 - endpoints[0][c] as i32).clamp(-limit, limit - 1);
+                endpoints[e][c] = (endpoints[0][c] as i32 + delta) as u32;
+                delta as u32 & ((1 << bits) - 1)
+            };
+        }
+    }
+    let indices = bc6h_indices(points, &unquantize(&endpoints), regions, partition, true);
+
+    let mut writer = BitWriter::new();
+    writer.write(desc.value, if desc.value < 2 { 2 } else { 5 });
+    for &(field, a, b) in desc.fields {
+        if a >= b {
+            for bit in b..=a { writer.write(stored[field] >> bit & 1, 1); }
+        } else {
+            for bit in (a..=b).rev() { writer.write(stored[field] >> bit & 1, 1); }
+        }
+    }
+    if regions == 2 {
+        writer.write(partition as u32, 5);
+    }
+    let index_bits = if regions == 2 { 3 } else { 4 };
+    for (i, &index) in indices.iter().enumerate() {
+        writer.write(index, index_bits - is_anchor(regions, partition, i) as u32);
+    }
+    writer.finish(out);
+}
+
+/// Converts RGB channels of the block to the interpolation domain, the fourth
+/// component stays zero (alpha is not stored).
+fn bc6h_points(block: &[[f32; 4]; 16]) -> [[f32; 4]; 16] {
+    let mut points = [[0f32; 4]; 16];
+    for (p, pixel) in points.iter_mut().zip(block.iter()) {
+        for c in 0..3 { p[c] = bc6h_domain(pixel[c]) as f32; }
+    }
+    points
+}
+
+fn encode_bc6h_block(block: &[[f32; 4]; 16], out: &mut [u8]) {
+    let points = bc6h_points(block);
+    let partitions = best_partitions(&points, 2, 32);
+
+    let mut best = (f32::MAX, [0u8; 16]);
+    let mut candidate = [0u8; 16];
+    for (mode, desc) in BC6H_MODES.iter().enumerate() {
+        let candidates = if desc.regions == 2 { &partitions[..] } else { &[0][..] };
+        for &partition in candidates {
+            encode_bc6h_mode(&points, mode, partition, &mut candidate);
+            let decoded = bc6h_points(&decode_bc6h_block(&candidate));
+            let error = decoded.iter()
+                .zip(points.iter())
+                .flat_map(|(d, p)| (0..3).map(move |c| (d[c] - p[c]).powi(2)))
+                .sum::<f32>();
+            if error < best.0 {
+                best = (error, candidate);
+            }
+        }
+    }
+    out.copy_from_slice(&best.1);
+}
+
+/// Encodes RGB channels of floating point RGBA image to BC6H (unsigned). Negative
+/// values are clamped to zero. Each block is encoded with the mode and partition
+/// which decodes with the lowest error (compared as half floats). Only a few
+/// partitions with the lowest estimated error are encoded for each mode.
+pub fn encode_bc6h(rgba: &[f32], width: usize, height: usize) -> Vec<u8> {
+    encode_blocks(rgba, width, height, 16, encode_bc6h_block)
+}
+
+/// Converts floating point RGBA image to RGBA16F (little-endian half floats).
+pub fn encode_rgba16f(rgba: &[f32]) -> Vec<u8> {
+    rgba.iter()
+        .flat_map(|x| f16::from_f32(*x).to_bits().to_le_bytes().to_vec())
+        .collect()
+}
+
+/// Reader of bit fields in the order used by BC6H and BC7 (least significant bit first).
+struct BitReader {
+    bits: u128,
+}
+
+impl BitReader {
+    fn new(block: &[u8]) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&block[..16]);
+        BitReader { bits: u128::from_le_bytes(bytes) }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits & ((1u128 << count) - 1)) as u32;
+        self.bits >>= count;
+        value
+    }
+}
+
+/// Interpolation weights of 2-bit and 3-bit indices used by BC6H and BC7.
+const WEIGHTS2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+
+fn weights(bits: u32) -> &'static [u32] {
+    match bits {
+        2 => &WEIGHTS2,
+        3 => &WEIGHTS3,
+        _ => &WEIGHTS4,
+    }
+}
+
+/// Partitions of blocks with two subsets. Bit `i` is set when pixel `i` belongs
+/// to the second subset.
+const PARTITIONS2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// Partitions of blocks with three subsets. Each pixel has two bits containing
+/// its subset, pixel 0 is stored in the least significant bits.
+const PARTITIONS3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8,
+    0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090,
+    0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0,
+    0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400,
+    0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424,
+    0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0,
+    0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600,
+    0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000,
+    0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+/// Index of the anchor pixel of the second subset in blocks with two subsets.
+const ANCHORS2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Indices of the anchor pixels of the second and third subset in blocks with
+/// three subsets.
+const ANCHORS3: [[u8; 2]; 64] = [
+    [3, 15], [3, 8], [15, 8], [15, 3], [8, 15], [3, 15], [15, 3], [15, 8],
+    [8, 15], [8, 15], [6, 15], [6, 15], [6, 15], [5, 15], [3, 15], [3, 8],
+    [3, 15], [3, 8], [8, 15], [15, 3], [3, 15], [3, 8], [6, 15], [10, 8],
+    [5, 3], [8, 15], [8, 6], [6, 10], [8, 15], [5, 15], [15, 10], [15, 8],
+    [8, 15], [15, 3], [3, 15], [5, 10], [6, 10], [10, 8], [8, 9], [15, 10],
+    [15, 6], [3, 15], [15, 8], [5, 15], [15, 3], [15, 6], [15, 6], [15, 8],
+    [3, 15], [15, 3], [5, 15], [5, 15], [5, 15], [8, 15], [5, 15], [10, 15],
+    [5, 15], [10, 15], [8, 15], [13, 15], [15, 3], [12, 15], [3, 15], [3, 8],
+];
+
+/// Returns the subset of pixel in specified partition of block with `subsets` subsets.
+fn subset(subsets: usize, partition: usize, pixel: usize) -> usize {
+    match subsets {
+        2 => (PARTITIONS2[partition] >> pixel & 1) as usize,
+        3 => (PARTITIONS3[partition] >> (2 * pixel) & 3) as usize,
+        _ => 0,
+    }
+}
+
+/// Returns true if the pixel is anchor of its subset (its index is stored
+/// with one bit less).
+fn is_anchor(subsets: usize, partition: usize, pixel: usize) -> bool {
+    pixel == 0 || match subsets {
+        2 => ANCHORS2[partition] as usize == pixel,
+        3 => ANCHORS3[partition].contains(&(pixel as u8)),
+        _ => false,
+    }
+}
+
+/// Interpolates between two endpoints using 6-bit weight.
+fn interpolate(e0: u32, e1: u32, weight: u32) -> u32 {
+    ((64 - weight) * e0 + weight * e1 + 32) >> 6
+}
+
+/// Decodes blocks of the image with `decode` and returns RGBA pixels of the image.
+fn decode_blocks<T, F>(data: &[u8], width: usize, height: usize, block_size: usize, decode: F) -> Vec<T>
+    where T: Copy + Default, F: Fn(&[u8]) -> [[T; 4]; 16] {
+    let blocks_x = width.div_ceil(4).max(1);
+    let mut out = vec![T::default(); width * height * 4];
+    for (i, block) in data.chunks_exact(block_size).enumerate() {
+        let (bx, by) = ((i % blocks_x) * 4, (i / blocks_x) * 4);
+        if by >= height {
+            break;
+        }
+
+        // pixels outside of the image are ignored
+        for (p, pixel) in decode(block).iter().enumerate() {
+            let (x, y) = (bx + p % 4, by + p / 4);
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                out[offset..offset + 4].copy_from_slice(pixel);
+            }
+        }
+    }
+    out
+}
+
+/// Returns the four colors of BC1 color block. When `transparent` is true and
+/// the first endpoint is not greater than the second one, the block uses three
+/// colors and transparent black.
+fn bc1_colors(block: &[u8], transparent: bool) -> [[u8; 4]; 4] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let rgb = |c: u16| {
+        let (r, g, b) = ((c >> 11) as u32 & 31, (c >> 5) as u32 & 63, c as u32 & 31);
+        [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+    };
+    let (e0, e1) = (rgb(c0), rgb(c1));
+
+    let mut colors = [[0u8; 4]; 4];
+    for c in 0..3 {
+        colors[0][c] = e0[c] as u8;
+        colors[1][c] = e1[c] as u8;
+        if c0 > c1 || !transparent {
+            colors[2][c] = ((2 * e0[c] + e1[c]) / 3) as u8;
+            colors[3][c] = ((e0[c] + 2 * e1[c]) / 3) as u8;
+        } else {
+            colors[2][c] = ((e0[c] + e1[c]) / 2) as u8;
+        }
+    }
+    colors[0][3] = 255;
+    colors[1][3] = 255;
+    colors[2][3] = 255;
+    colors[3][3] = if c0 > c1 || !transparent { 255 } else { 0 };
+    colors
+}
+
+fn decode_bc1_block(block: &[u8], transparent: bool) -> [[u8; 4]; 16] {
+    let colors = bc1_colors(block, transparent);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let mut pixels = [[0u8; 4]; 16];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = colors[(indices >> (2 * i) & 3) as usize];
+    }
+    pixels
+}
+
+/// Returns the sixteen values of BC4 block.
+fn decode_bc4_values(block: &[u8]) -> [u8; 16] {
+    let palette = bc4_palette(block[0], block[1]);
+    let mut indices = [0u8; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+
+    let mut values = [0u8; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(indices >> (3 * i) & 7) as usize];
+    }
+    values
+}
+
+/// Decodes DXT1 (BC1) image to RGBA8.
+pub fn decode_bc1(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    decode_blocks(data, width, height, 8, |block| decode_bc1_block(block, true))
+}
+
+/// Decodes DXT3 (BC2) image to RGBA8.
+pub fn decode_bc2(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    decode_blocks(data, width, height, 16, |block| {
+        let mut pixels = decode_bc1_block(&block[8..16], false);
+        let alpha = u64::from_le_bytes([block[0], block[1], block[2], block[3], block[4], block[5], block[6], block[7]]);
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            pixel[3] = (alpha >> (4 * i) & 15) as u8 * 17;
+        }
+        pixels
+    })
+}
+
+/// Decodes DXT5 (BC3) image to RGBA8.
+pub fn decode_bc3(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    decode_blocks(data, width, height, 16, |block| {
+        let mut pixels = decode_bc1_block(&block[8..16], false);
+        for (pixel, alpha) in pixels.iter_mut().zip(decode_bc4_values(&block[0..8]).iter()) {
+            pixel[3] = *alpha;
+        }
+        pixels
+    })
+}
+
+/// Decodes BC4 image to RGBA8. The value is stored in the red channel.
+pub fn decode_bc4(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    decode_blocks(data, width, height, 8, |block| {
+        let mut pixels = [[0, 0, 0, 255]; 16];
+        for (pixel, red) in pixels.iter_mut().zip(decode_bc4_values(block).iter()) {
+            pixel[0] = *red;
+        }
+        pixels
+    })
+}
+
+/// Decodes BC5 image to RGBA8. The values are stored in the red and green channels.
+pub fn decode_bc5(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    decode_blocks(data, width, height, 16, |block| {
+        let mut pixels = [[0, 0, 0, 255]; 16];
+        let red = decode_bc4_values(&block[0..8]);
+        let green = decode_bc4_values(&block[8..16]);
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            pixel[0] = red[i];
+            pixel[1] = green[i];
+        }
+        pixels
+    })
+}
+
+/// Properties of BC7 block mode.
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index_bits2: u32,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+];
+
+/// Expands value with `bits` bits to 8 bits by replicating its highest bits.
+fn expand(value: u32, bits: u32) -> u32 {
+    let value = value << (8 - bits);
+    value | (value >> bits)
+}
+
+fn decode_bc7_block(block: &[u8]) -> [[u8; 4]; 16] {
+    // reserved mode 8 decodes to transparent black
+    let mode = match block[0].trailing_zeros() {
+        m if m < 8 => m as usize,
+        _ => return [[0u8; 4]; 16],
+    };
+    let desc = &BC7_MODES[mode];
+
+    let mut reader = BitReader::new(block);
+    reader.read(mode as u32 + 1);
+    let partition = reader.read(desc.partition_bits) as usize;
+    let rotation = reader.read(desc.rotation_bits);
+    let index_selection = reader.read(desc.index_selection_bits);
+
+    // endpoints are stored by channel, then by subset
+    let mut endpoints = [[[0u32; 4]; 2]; 3];
+    for c in 0..4 {
+        let bits = if c < 3 { desc.color_bits } else { desc.alpha_bits };
+        for subset in endpoints.iter_mut().take(desc.subsets) {
+            for endpoint in subset.iter_mut() {
+                endpoint[c] = reader.read(bits);
+            }
+        }
+    }
+
+    // p-bits extend the precision of all channels by one bit
+    let mut bits = [desc.color_bits, desc.color_bits, desc.color_bits, desc.alpha_bits];
+    if desc.endpoint_pbits || desc.shared_pbits {
+        for subset in endpoints.iter_mut().take(desc.subsets) {
+            let shared = if desc.shared_pbits { Some(reader.read(1)) } else { None };
+            for endpoint in subset.iter_mut() {
+                let pbit = shared.unwrap_or_else(|| reader.read(1));
+                for value in endpoint.iter_mut() {
+                    *value = (*value << 1) | pbit;
+                }
+            }
+        }
+        for b in bits.iter_mut().filter(|b| **b > 0) { *b += 1; }
+    }
+
+    for subset in endpoints.iter_mut() {
+        for endpoint in subset.iter_mut() {
+            for (value, bits) in endpoint.iter_mut().zip(bits.iter()) {
+                *value = if *bits == 0 { 255 } else { expand(*value, *bits) };
+            }
+        }
+    }
+
+    let mut indices = [0u32; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(desc.subsets, partition, i);
+        *index = reader.read(desc.index_bits - anchor as u32);
+    }
+    let mut indices2 = [0u32; 16];
+    if desc.index_bits2 > 0 {
+        for (i, index) in indices2.iter_mut().enumerate() {
+            *index = reader.read(desc.index_bits2 - (i == 0) as u32);
+        }
+    }
+
+    let mut pixels = [[0u8; 4]; 16];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let [e0, e1] = endpoints[subset(desc.subsets, partition, i)];
+
+        // modes with secondary indices interpolate alpha separately
+        let (color, alpha) = match (desc.index_bits2, index_selection) {
+            (0, _) => (weights(desc.index_bits)[indices[i] as usize], weights(desc.index_bits)[indices[i] as usize]),
+            (_, 0) => (weights(desc.index_bits)[indices[i] as usize], weights(desc.index_bits2)[indices2[i] as usize]),
+            _ => (weights(desc.index_bits2)[indices2[i] as usize], weights(desc.index_bits)[indices[i] as usize]),
+        };
+
+        for c in 0..4 {
+            let weight = if c < 3 { color } else { alpha };
+            pixel[c] = interpolate(e0[c], e1[c], weight) as u8;
+        }
+
+        // rotation swaps alpha with one of the color channels
+        if rotation > 0 {
+            pixel.swap(3, rotation as usize - 1);
+        }
+    }
+    pixels
+}
+
+/// Decodes BC7 image to RGBA8.
+pub fn decode_bc7(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    decode_blocks(data, width, height, 16, decode_bc7_block)
+}
+
+/// Properties of BC6H block mode. Fields describe where the bits of endpoints
+/// are stored: `(endpoint * 3 + channel, a, b)` means bits `b` to `a` (in this
+/// order) of the value are read from the stream.
+struct Bc6hMode {
+    value: u32,
+    regions: usize,
+    transformed: bool,
+    precision: u32,
+    deltas: [u32; 3],
+    fields: &'static [(usize, u32, u32)],
+}
+
+const BC6H_MODES: [Bc6hMode; 14] = [
+    // mode 1
+    Bc6hMode { value: 0, regions: 2, transformed: true, precision: 10, deltas: [5, 5, 5],
+        fields: &[(7, 4, 4), (8, 4, 4), (11, 4, 4), (0, 9, 0), (1, 9, 0), (2, 9, 0), (3, 4, 0), (10, 4, 4), (7, 3, 0), (4, 4, 0), (11, 0, 0), (10, 3, 0), (5, 4, 0), (11, 1, 1), (8, 3, 0), (6, 4, 0), (11, 2, 2), (9, 4, 0), (11, 3, 3)] },
+    // mode 2
+    Bc6hMode { value: 1, regions: 2, transformed: true, precision: 7, deltas: [6, 6, 6],
+        fields: &[(7, 5, 5), (10, 4, 4), (10, 5, 5), (0, 6, 0), (11, 0, 0), (11, 1, 1), (8, 4, 4), (1, 6, 0), (8, 5, 5), (11, 2, 2), (7, 4, 4), (2, 6, 0), (11, 3, 3), (11, 5, 5), (11, 4, 4), (3, 5, 0), (7, 3, 0), (4, 5, 0), (10, 3, 0), (5, 5, 0), (8, 3, 0), (6, 5, 0), (9, 5, 0)] },
+    // mode 3
+    Bc6hMode { value: 2, regions: 2, transformed: true, precision: 11, deltas: [5, 4, 4],
+        fields: &[(0, 9, 0), (1, 9, 0), (2, 9, 0), (3, 4, 0), (0, 10, 10), (7, 3, 0), (4, 3, 0), (1, 10, 10), (11, 0, 0), (10, 3, 0), (5, 3, 0), (2, 10, 10), (11, 1, 1), (8, 3, 0), (6, 4, 0), (11, 2, 2), (9, 4, 0), (11, 3, 3)] },
+    // mode 4
+    Bc6hMode { value: 6, regions: 2, transformed: true, precision: 11, deltas: [4, 5, 4],
+        fields: &[(0, 9, 0), (1, 9, 0), (2, 9, 0), (3, 3, 0), (0, 10, 10), (10, 4, 4), (7, 3, 0), (4, 4, 0), (1, 10, 10), (10, 3, 0), (5, 3, 0), (2, 10, 10), (11, 1, 1), (8, 3, 0), (6, 3, 0), (11, 0, 0), (11, 2, 2), (9, 3, 0), (7, 4, 4), (11, 3, 3)] },
+    // mode 5
+    Bc6hMode { value: 10, regions: 2, transformed: true, precision: 11, deltas: [4, 4, 5],
+        fields: &[(0, 9, 0), (1, 9, 0), (2, 9, 0), (3, 3, 0), (0, 10, 10), (8, 4, 4), (7, 3, 0), (4, 3, 0), (1, 10, 10), (11, 0, 0), (10, 3, 0), (5, 4, 0), (2, 10, 10), (8, 3, 0), (6, 3, 0), (11, 1, 1), (11, 2, 2), (9, 3, 0), (11, 4, 4), (11, 3, 3)] },
+    // mode 6
+    Bc6hMode { value: 14, regions: 2, transformed: true, precision: 9, deltas: [5, 5, 5],
+        fields: &[(0, 8, 0), (8, 4, 4), (1, 8, 0), (7, 4, 4), (2, 8, 0), (11, 4, 4), (3, 4, 0), (10, 4, 4), (7, 3, 0), (4, 4, 0), (11, 0, 0), (10, 3, 0), (5, 4, 0), (11, 1, 1), (8, 3, 0), (6, 4, 0), (11, 2, 2), (9, 4, 0), (11, 3, 3)] },
+    // mode 7
+    Bc6hMode { value: 18, regions: 2, transformed: true, precision: 8, deltas: [6, 5, 5],
+        fields: &[(0, 7, 0), (10, 4, 4), (8, 4, 4), (1, 7, 0), (11, 2, 2), (7, 4, 4), (2, 7, 0), (11, 3, 3), (11, 4, 4), (3, 5, 0), (7, 3, 0), (4, 4, 0), (11, 0, 0), (10, 3, 0), (5, 4, 0), (11, 1, 1), (8, 3, 0), (6, 5, 0), (9, 5, 0)] },
+    // mode 8
+    Bc6hMode { value: 22, regions: 2, transformed: true, precision: 8, deltas: [5, 6, 5],
+        fields: &[(0, 7, 0), (11, 0, 0), (8, 4, 4), (1, 7, 0), (7, 5, 5), (7, 4, 4), (2, 7, 0), (10, 5, 5), (11, 4, 4), (3, 4, 0), (10, 4, 4), (7, 3, 0), (4, 5, 0), (10, 3, 0), (5, 4, 0), (11, 1, 1), (8, 3, 0), (6, 4, 0), (11, 2, 2), (9, 4, 0), (11, 3, 3)] },
+    // mode 9
+    Bc6hMode { value: 26, regions: 2, transformed: true, precision: 8, deltas: [5, 5, 6],
+        fields: &[(0, 7, 0), (11, 1, 1), (8, 4, 4), (1, 7, 0), (8, 5, 5), (7, 4, 4), (2, 7, 0), (11, 5, 5), (11, 4, 4), (3, 4, 0), (10, 4, 4), (7, 3, 0), (4, 4, 0), (11, 0, 0), (10, 3, 0), (5, 5, 0), (8, 3, 0), (6, 4, 0), (11, 2, 2), (9, 4, 0), (11, 3, 3)] },
+    // mode 10
+    Bc6hMode { value: 30, regions: 2, transformed: false, precision: 6, deltas: [6, 6, 6],
+        fields: &[(0, 5, 0), (10, 4, 4), (11, 0, 0), (11, 1, 1), (8, 4, 4), (1, 5, 0), (7, 5, 5), (8, 5, 5), (11, 2, 2), (7, 4, 4), (2, 5, 0), (10, 5, 5), (11, 3, 3), (11, 5, 5), (11, 4, 4), (3, 5, 0), (7, 3, 0), (4, 5, 0), (10, 3, 0), (5, 5, 0), (8, 3, 0), (6, 5, 0), (9, 5, 0)] },
+    // mode 11
+    Bc6hMode { value: 3, regions: 1, transformed: false, precision: 10, deltas: [10, 10, 10],
+        fields: &[(0, 9, 0), (1, 9, 0), (2, 9, 0), (3, 9, 0), (4, 9, 0), (5, 9, 0)] },
+    // mode 12
+    Bc6hMode { value: 7, regions: 1, transformed: true, precision: 11, deltas: [9, 9, 9],
+        fields: &[(0, 9, 0), (1, 9, 0), (2, 9, 0), (3, 8, 0), (0, 10, 10), (4, 8, 0), (1, 10, 10), (5, 8, 0), (2, 10, 10)] },
+    // mode 13
+    Bc6hMode { value: 11, regions: 1, transformed: true, precision: 12, deltas: [8, 8, 8],
+        fields: &[(0, 9, 0), (1, 9, 0), (2, 9, 0), (3, 7, 0), (0, 10, 11), (4, 7, 0), (1, 10, 11), (5, 7, 0), (2, 10, 11)] },
+    // mode 14
+    Bc6hMode { value: 15, regions: 1, transformed: true, precision: 16, deltas: [4, 4, 4],
+        fields: &[(0, 9, 0), (1, 9, 0), (2, 9, 0), (3, 3, 0), (0, 10, 15), (4, 3, 0), (1, 10, 15), (5, 3, 0), (2, 10, 15)] },
+];
+
+/// Sign extends value with `bits` bits.
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+/// Returns value of unsigned BC6H endpoint in the interpolation domain.
+fn bc6h_unquantize(value: u32, bits: u32) -> u32 {
+    if bits >= 15 {
+        value
+    } else if value == 0 {
+        0
+    } else if value == (1 << bits) - 1 {
+        0xFFFF
+    } else {
+        ((value << 16) + 0x8000) >> bits
+    }
+}
+
+fn decode_bc6h_block(block: &[u8]) -> [[f32; 4]; 16] {
+    let mut reader = BitReader::new(block);
+    let mut value = reader.read(2);
+    if value > 1 {
+        value |= reader.read(3) << 2;
+    }
+
+    // reserved modes decode to black
+    let mode = match BC6H_MODES.iter().find(|m| m.value == value) {
+        Some(mode) => mode,
+        None => return [[0.0, 0.0, 0.0, 1.0]; 16],
+    };
+
+    let mut endpoints = [0u32; 12];
+    for &(field, a, b) in mode.fields {
+        if a >= b {
+            for bit in b..=a { endpoints[field] |= reader.read(1) << bit; }
+        } else {
+            for bit in (a..=b).rev() { endpoints[field] |= reader.read(1) << bit; }
+        }
+    }
+    let partition = if mode.regions == 2 { reader.read(5) as usize } else { 0 };
+    let endpoint_count = mode.regions * 2;
+
+    // transformed endpoints are stored as signed deltas from the first endpoint
+    if mode.transformed {
+        let mask = (1 << mode.precision) - 1;
+        for e in 1..endpoint_count {
+            for c in 0..3 {
+                let delta = sign_extend(endpoints[e * 3 + c], mode.deltas[c]);
+                endpoints[e * 3 + c] = (endpoints[c] as i32 + delta) as u32 & mask;
+            }
+        }
+    }
+    for value in endpoints.iter_mut().take(endpoint_count * 3) {
+        *value = bc6h_unquantize(*value, mode.precision);
+    }
+
+    let index_bits = if mode.regions == 2 { 3 } else { 4 };
+    let mut pixels = [[0f32; 4]; 16];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let anchor = is_anchor(mode.regions, partition, i);
+        let weight = weights(index_bits)[reader.read(index_bits - anchor as u32) as usize];
+        let region = subset(mode.regions, partition, i);
+        for c in 0..3 {
+            let value = interpolate(endpoints[region * 6 + c], endpoints[region * 6 + 3 + c], weight);
+            pixel[c] = f16::from_bits(((value * 31) >> 6) as u16).to_f32();
+        }
+        pixel[3] = 1.0;
+    }
+    pixels
+}
+
+/// Decodes unsigned BC6H image to floating point RGBA.
+pub fn decode_bc6h(data: &[u8], width: usize, height: usize) -> Vec<f32> {
+    decode_blocks(data, width, height, 16, decode_bc6h_block)
+}
+
+/// Decodes RGBA16F image (little-endian half floats) to floating point RGBA.
+pub fn decode_rgba16f(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(2)
+        .map(|x| f16::from_bits(u16::from_le_bytes([x[0], x[1]])).to_f32())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::ImageDecoder;
+    use image::dxt::{DXTDecoder, DXTEncoder, DXTVariant};
+    use crate::bc::{encode_bc4, encode_bc5, encode_bc6h, encode_bc7, encode_rgba16f, bc4_palette, bc6h_unquantize, WEIGHTS4, BitWriter};
+    use crate::bc::{encode_bc6h_mode, encode_bc7_mode, bc6h_points, PARTITIONS2};
+    use crate::bc::{decode_bc1, decode_bc3, decode_bc4, decode_bc5, decode_bc6h, decode_bc7, decode_rgba16f};
+
+    /// Reads `count` bits starting at `offset` from 16 byte block.
+    fn bits(block: &[u8], offset: u32, count: u32) -> u32 {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(block);
+        ((u128::from_le_bytes(bytes) >> offset) & ((1 << count) - 1)) as u32
+    }
+
+    /// Returns the 4-bit index of pixel in BC6H/BC7 block with indices starting at `offset`.
+    fn index4(block: &[u8], offset: u32, pixel: u32) -> usize {
+        if pixel == 0 { bits(block, offset, 3) as usize } else { bits(block, offset + 4 * pixel - 1, 4) as usize }
+    }
+
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| vec![(i * 7 % 256) as u8, (i * 3 % 256) as u8, 128, 255])
+            .collect()
+    }
+
+    #[test]
+    fn block_counts() {
+        assert_eq!(encode_bc4(&gradient(8, 8), 8, 8).len(), 4 * 8);
+        assert_eq!(encode_bc5(&gradient(8, 4), 8, 4).len(), 2 * 16);
+        assert_eq!(encode_bc7(&gradient(5, 5), 5, 5).len(), 4 * 16);
+        assert_eq!(encode_bc7(&gradient(1, 1), 1, 1).len(), 16);
+        assert_eq!(encode_bc6h(&[0.5; 4 * 6 * 2], 6, 2).len(), 2 * 16);
+        assert_eq!(encode_rgba16f(&[1.0, 0.5, 0.0, 2.0]), vec![0x00, 0x3C, 0x00, 0x38, 0x00, 0x00, 0x00, 0x40]);
+    }
+
+    #[test]
+    fn bc4_block() {
+        let rgba: Vec<u8> = (0..16u8).flat_map(|i| vec![i * 16, 0, 0, 0]).collect();
+        let block = encode_bc4(&rgba, 4, 4);
+        assert_eq!((block[0], block[1]), (240, 0));
+
+        let palette = bc4_palette(block[0], block[1]);
+        let mut indices = [0u8; 8];
+        indices[..6].copy_from_slice(&block[2..8]);
+        let indices = u64::from_le_bytes(indices);
+        for i in 0..16 {
+            let value = palette[(indices >> (3 * i) & 7) as usize];
+            assert!((value as i32 - i * 16).abs() <= 18, "pixel {} decoded as {}", i, value);
+        }
+
+        // uniform block is exact
+        let block = encode_bc4(&[77, 1, 2, 3].repeat(16), 4, 4);
+        assert_eq!(bc4_palette(block[0], block[1])[0], 77);
+    }
+
+    #[test]
+    fn bc5_channels() {
+        let rgba = [10, 200, 0, 0].repeat(16);
+        let block = encode_bc5(&rgba, 4, 4);
+        assert_eq!(block[0], 10);
+        assert_eq!(block[8], 200);
+    }
+
+    #[test]
+    fn bc7_mode6_block() {
+        let mut block = [0u8; 16];
+        encode_bc7_mode(&[[200, 100, 50, 255]; 16], 6, 0, &mut block);
+
+        // mode 6 is seven bits with the last one set
+        assert_eq!(block[0] & 0x7F, 0x40);
+
+        let p0 = bits(&block, 63, 1);
+        let p1 = bits(&block, 64, 1);
+        for (c, expected) in [200u32, 100, 50, 255].iter().enumerate() {
+            let e0 = bits(&block, 7 + 14 * c as u32, 7) * 2 + p0;
+            let e1 = bits(&block, 14 + 14 * c as u32, 7) * 2 + p1;
+            let index = index4(&block, 65, 3);
+            let value = ((64 - WEIGHTS4[index]) * e0 + WEIGHTS4[index] * e1 + 32) >> 6;
+            assert!((value as i32 - *expected as i32).abs() <= 1, "channel {} decoded as {}", c, value);
+        }
+    }
+
+    #[test]
+    fn bc6h_mode11_block() {
+        let mut pixels = [[0f32; 4]; 16];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = [1.0 + i as f32 / 15.0, 1.0, 0.25, 1.0];
+        }
+        let mut block = [0u8; 16];
+        encode_bc6h_mode(&bc6h_points(&pixels), 10, 0, &mut block);
+
+        assert_eq!(bits(&block, 0, 5), 0b00011);
+        for i in [0u32, 7, 15].iter() {
+            let index = index4(&block, 65, *i);
+            let mut decoded = [0f32; 3];
+            for c in 0..3 {
+                let e0 = bc6h_unquantize(bits(&block, 5 + 10 * c, 10), 10);
+                let e1 = bc6h_unquantize(bits(&block, 35 + 10 * c, 10), 10);
+                let value = ((64 - WEIGHTS4[index]) * e0 + WEIGHTS4[index] * e1 + 32) >> 6;
+                decoded[c as usize] = half::f16::from_bits(((value * 31) >> 6) as u16).to_f32();
+            }
+
+            let expected = [1.0 + *i as f32 / 15.0, 1.0, 0.25];
+            for c in 0..3 {
+                assert!((decoded[c] - expected[c]).abs() <= 0.08 * expected[c], "pixel {} decoded as {:?}", i, decoded);
+            }
+        }
+    }
+
+    /// Returns squared error of the decoded pixels.
+    fn squared_error(actual: &[u8], expected: &[u8]) -> u32 {
+        actual.iter().zip(expected.iter()).map(|(a, e)| (*a as i32 - *e as i32).pow(2) as u32).sum()
+    }
+
+    #[test]
+    fn bc7_partitioned_block() {
+        // two gradients of different hues split by partition 13 (top and bottom half)
+        let rgba: Vec<u8> = (0..16u8)
+            .flat_map(|i| if PARTITIONS2[13] >> i & 1 == 0 { vec![100 + i * 8, 20, 10, 255] } else { vec![10, 40, 100 + i * 8, 255] })
+            .collect();
+        let block = encode_bc7(&rgba, 4, 4);
+        let mode = block[0].trailing_zeros();
+        assert!(mode == 1 || mode == 3 || mode == 7, "encoded with mode {}", mode);
+
+        let mut single = [0u8; 16];
+        let mut pixels = [[0u8; 4]; 16];
+        for (pixel, p) in pixels.iter_mut().zip(rgba.chunks(4)) { pixel.copy_from_slice(p); }
+        encode_bc7_mode(&pixels, 6, 0, &mut single);
+        let error = squared_error(&decode_bc7(&block, 4, 4), &rgba);
+        assert!(error * 4 < squared_error(&decode_bc7(&single, 4, 4), &rgba), "error {}", error);
+        assert_close(&decode_bc7(&block, 4, 4), &rgba, 8);
+
+        // alpha is kept by modes with alpha
+        let rgba: Vec<u8> = (0..16u8).flat_map(|i| vec![200, 100, 50, i * 16]).collect();
+        assert_close(&decode_bc7(&encode_bc7(&rgba, 4, 4), 4, 4), &rgba, 8);
+    }
+
+    #[test]
+    fn bc6h_partitioned_block() {
+        // red and blue gradients split by partition 13 (top and bottom half)
+        let mut rgba = vec![0f32; 64];
+        for i in 0..16 {
+            let pixel = if PARTITIONS2[13] >> i & 1 == 0 { [1.0 + i as f32 / 40.0, 0.6, 0.3, 1.0] } else { [0.3, 0.6, 1.0 + i as f32 / 40.0, 1.0] };
+            rgba[i * 4..i * 4 + 4].copy_from_slice(&pixel);
+        }
+        let block = encode_bc6h(&rgba, 4, 4);
+        // all one region modes have the two lowest bits set
+        assert_ne!(block[0] & 3, 3, "encoded with one region mode");
+
+        let mut pixels = [[0f32; 4]; 16];
+        for (pixel, p) in pixels.iter_mut().zip(rgba.chunks(4)) { pixel.copy_from_slice(p); }
+        let mut single = [0u8; 16];
+        encode_bc6h_mode(&bc6h_points(&pixels), 10, 0, &mut single);
+        let relative_error = |blocks: &[u8]| decode_bc6h(blocks, 4, 4).iter()
+            .zip(rgba.iter())
+            .map(|(d, e)| ((d - e) / e).abs())
+            .fold(0f32, f32::max);
+        assert!(relative_error(&block) < 0.05, "relative error {}", relative_error(&block));
+        assert!(relative_error(&block) < relative_error(&single));
+    }
+
+    fn assert_close(actual: &[u8], expected: &[u8], tolerance: i32) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!((*a as i32 - *e as i32).abs() <= tolerance, "component {} decoded as {}, expected {}", i, a, e);
+        }
+    }
+
+    fn dxt(rgba: &[u8], width: usize, height: usize, variant: DXTVariant) -> (Vec<u8>, Vec<u8>) {
+        let mut blocks = vec![];
+        DXTEncoder::new(&mut blocks)
+            .encode(rgba, width as u32, height as u32, variant)
+            .unwrap();
+        let decoded = DXTDecoder::new(blocks.as_slice(), width as u32, height as u32, variant)
+            .unwrap()
+            .read_image()
+            .unwrap();
+        (blocks, decoded)
+    }
+
+    #[test]
+    fn decode_dxt() {
+        let rgba = gradient(8, 8);
+        let rgb: Vec<u8> = rgba.chunks(4).flat_map(|p| p[..3].to_vec()).collect();
+
+        let (blocks, expected) = dxt(&rgb, 8, 8, DXTVariant::DXT1);
+        let decoded: Vec<u8> = decode_bc1(&blocks, 8, 8).chunks(4).flat_map(|p| p[..3].to_vec()).collect();
+        assert_close(&decoded, &expected, 1);
+
+        let (blocks, expected) = dxt(&rgba, 8, 8, DXTVariant::DXT5);
+        assert_close(&decode_bc3(&blocks, 8, 8), &expected, 1);
+
+        // padding of blocks is cropped
+        assert_eq!(decode_bc1(&blocks[..8], 2, 3).len(), 2 * 3 * 4);
+    }
+
+    #[test]
+    fn decode_bc4_bc5() {
+        let rgba = gradient(4, 4);
+        let decoded = decode_bc4(&encode_bc4(&rgba, 4, 4), 4, 4);
+        let expected: Vec<u8> = rgba.chunks(4).flat_map(|p| vec![p[0], 0, 0, 255]).collect();
+        assert_close(&decoded, &expected, 8);
+
+        let decoded = decode_bc5(&encode_bc5(&rgba, 4, 4), 4, 4);
+        let expected: Vec<u8> = rgba.chunks(4).flat_map(|p| vec![p[0], p[1], 0, 255]).collect();
+        assert_close(&decoded, &expected, 8);
+    }
+
+    #[test]
+    fn decode_bc7_modes() {
+        // uniform block produced by the encoder
+        let rgba = [200, 100, 50, 255].repeat(16);
+        assert_close(&decode_bc7(&encode_bc7(&rgba, 4, 4), 4, 4), &rgba, 1);
+
+        // mode 5 with rotation swapping red and alpha
+        let mut writer = BitWriter::new();
+        writer.write(1 << 5, 6);
+        writer.write(1, 2);
+        for value in [0x7F, 0x7F, 0, 0, 0x40, 0x40].iter() {
+            writer.write(*value, 7);
+        }
+        writer.write(10, 8);
+        writer.write(10, 8);
+        writer.write(0, 31);
+        writer.write(0, 31);
+        let mut block = [0u8; 16];
+        writer.finish(&mut block);
+        assert_eq!(decode_bc7(&block, 4, 4), [10, 0, 129, 255].repeat(16));
+
+        // reserved mode
+        assert_eq!(decode_bc7(&[0u8; 16], 4, 4), vec![0u8; 64]);
+    }
+
+    #[test]
+    fn decode_bc6h_modes() {
+        let mut rgba = vec![0f32; 64];
+        for i in 0..16 {
+            rgba[i * 4..i * 4 + 4].copy_from_slice(&[1.0 + i as f32 / 15.0, 1.0, 0.25, 1.0]);
+        }
+        let decoded = decode_bc6h(&encode_bc6h(&rgba, 4, 4), 4, 4);
+        for (d, e) in decoded.iter().zip(rgba.iter()) {
+            assert!((d - e).abs() <= 0.08 * e, "decoded {}, expected {}", d, e);
+        }
+
+        // mode 1 with zero deltas has the same color in all pixels
+        let mut writer = BitWriter::new();
+        writer.write(0, 2);
+        writer.write(0, 3);
+        for value in [512, 256, 128].iter() {
+            writer.write(*value, 10);
+        }
+        writer.write(0, 128 - 35);
+        let mut block = [0u8; 16];
+        writer.finish(&mut block);
+
+        let decoded = decode_bc6h(&block, 4, 4);
+        for (c, value) in [512u32, 256, 128].iter().enumerate() {
+            let expected = half::f16::from_bits(((bc6h_unquantize(*value, 10) * 31) >> 6) as u16).to_f32();
+            assert!(decoded.chunks(4).all(|p| p[c] == expected && p[3] == 1.0));
+        }
+    }
+
+    #[test]
+    fn decode_half_floats() {
+        let rgba = [1.0, 0.5, 0.0, 2.0, -3.0, 65504.0, 0.25, 1.0];
+        assert_eq!(decode_rgba16f(&encode_rgba16f(&rgba)), rgba.to_vec());
+    }
+}
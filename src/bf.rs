@@ -1,12 +1,20 @@
-use zerocopy::LayoutVerified;
+use zerocopy::{LayoutVerified, FromBytes, AsBytes};
 use byteorder::{LittleEndian, ByteOrder};
 use crate::bf::ColorSpace::{Linear, Srgb};
-use crate::bf::BfImageFormat::{Dxt1, Dxt3, Dxt5, Rgb8, Rgba8, Srgb8, Srgb8A8, SrgbDxt5, SrgbDxt3, SrgbDxt1};
+use crate::bf::BfImageFormat::{Dxt1, Dxt3, Dxt5, Rgb8, Rgba8, Srgb8, Srgb8A8, SrgbDxt5, SrgbDxt3, SrgbDxt1, Bc4, Bc5, Bc6h, Bc7, SrgbBc7, Rgba16f};
 use std::convert::TryFrom;
 use crate::bf::Kind::{Image, Geometry, Audio, Material, VirtualFileSystem, CompiledShader, Scene};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use lz4::block::CompressionMode::HIGHCOMPRESSION;
+use crate::bc;
 
 /// Enum representing possible types of BF files.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Kind {
     Image = 0,
@@ -44,21 +52,87 @@ pub struct BfHeader {
     pub magic: u16,
     pub kind: u8,
     pub version: u8,
-    pub reserved: u32,
+    /// CRC32 of the stored payload since version 2, zero in older versions.
+    pub checksum: u32,
     pub additional: u64,
     pub uncompressed: u64,
     pub compressed: u64,
 }
 
+/// Kind specific values stored in the `additional` field of the header.
+///
+/// Each kind defines its own packing into 8 bytes which are stored in the
+/// file in little-endian order, independently of the host and of the layout
+/// of the implementing struct.
+pub trait AdditionalData: Sized {
+    /// Packs the values into the bytes of the additional field.
+    fn write(&self, bytes: &mut [u8; 8]);
+
+    /// Unpacks the values from the bytes of the additional field.
+    fn read(bytes: &[u8; 8]) -> Self;
+
+    fn into_u64(self) -> u64 {
+        let mut bytes = [0; 8];
+        self.write(&mut bytes);
+        LittleEndian::read_u64(&bytes)
+    }
+
+    fn from_u64(data: u64) -> Self {
+        let mut bytes = [0; 8];
+        LittleEndian::write_u64(&mut bytes, data);
+        Self::read(&bytes)
+    }
+}
+
+/// Kinds without any additional data store zeros.
+impl AdditionalData for () {
+    fn write(&self, _: &mut [u8; 8]) {}
+
+    fn read(_: &[u8; 8]) -> Self {}
+}
+
+/// Dimensionality of image stored in BF file of Image kind.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum ImageDimension {
+    /// Ordinary 2D image.
+    D2 = 0,
+    /// Cube map with six faces in order +X, -X, +Y, -Y, +Z, -Z (or array of cube maps).
+    Cube = 1,
+    /// Array of 2D images with the same size.
+    Array = 2,
+    /// Volume image.
+    D3 = 3,
+}
+
+impl ImageDimension {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => ImageDimension::D2,
+            1 => ImageDimension::Cube,
+            2 => ImageDimension::Array,
+            _ => ImageDimension::D3,
+        }
+    }
+}
+
 /// Struct for additional data of Image kind.
-#[repr(C)]
+///
+/// Layout: width (u16) at byte 0, height (u16) at byte 2, format (u8) at
+/// byte 4, flags (u8) at byte 5 with chunked mip-maps in bit 0 and dimension
+/// in bits 1-2, depth (u16) at byte 6.
 #[derive(Eq, PartialEq, Hash, Debug, Copy, Clone)]
 pub struct BfImageAdditional {
     pub width: u16,
     pub height: u16,
     pub format: u8,
-    padding1: u8,
-    padding2: u16,
+    /// Each mip-map is compressed separately and the payload starts with table
+    /// of chunks (see `BfImageChunksBuilder`).
+    pub chunked: bool,
+    pub dimension: ImageDimension,
+    /// Depth of 3D images, number of layers of arrays or number of cubes of cube
+    /// maps. Always one for 2D images.
+    pub depth: u16,
 }
 
 impl BfImageAdditional {
@@ -67,26 +141,112 @@ impl BfImageAdditional {
             width,
             height,
             format,
-            padding1: 0,
-            padding2: 0,
+            chunked: false,
+            dimension: ImageDimension::D2,
+            depth: 1,
+        }
+    }
+
+    /// Returns copy of this additional data with specified dimensionality and depth
+    /// (or number of layers).
+    pub fn with_dimension(self, dimension: ImageDimension, depth: u16) -> Self {
+        BfImageAdditional { dimension, depth, ..self }
+    }
+
+    /// Returns the number of 2D slices in every mip-map that are not affected by
+    /// mip-mapping (faces of cube maps and layers of arrays).
+    pub fn layer_count(&self) -> usize {
+        match self.dimension {
+            ImageDimension::D2 | ImageDimension::D3 => 1,
+            ImageDimension::Cube => 6 * self.depth as usize,
+            ImageDimension::Array => self.depth as usize,
+        }
+    }
+
+    /// Returns width, height and depth of specified mip-map level. Depth is always
+    /// one for images that are not 3D.
+    pub fn mip_extent(&self, level: usize) -> (usize, usize, usize) {
+        let (w, h) = mip_dimensions(self.width as usize, self.height as usize, level);
+        let depth = match self.dimension {
+            ImageDimension::D3 => mip_dimensions(self.depth as usize, 1, level).0,
+            _ => 1,
+        };
+        (w, h, depth)
+    }
+
+    /// Returns the size of specified mip-map level with all its layers and depth
+    /// slices in specified format.
+    pub fn mip_size(&self, format: BfImageFormat, level: usize) -> usize {
+        let (w, h, d) = self.mip_extent(level);
+        format.size_of(w, h) * d * self.layer_count()
+    }
+}
+
+impl AdditionalData for BfImageAdditional {
+    fn write(&self, bytes: &mut [u8; 8]) {
+        LittleEndian::write_u16(&mut bytes[0..2], self.width);
+        LittleEndian::write_u16(&mut bytes[2..4], self.height);
+        bytes[4] = self.format;
+        bytes[5] = self.chunked as u8 | (self.dimension as u8) << 1;
+        LittleEndian::write_u16(&mut bytes[6..8], self.depth);
+    }
+
+    fn read(bytes: &[u8; 8]) -> Self {
+        BfImageAdditional {
+            width: LittleEndian::read_u16(&bytes[0..2]),
+            height: LittleEndian::read_u16(&bytes[2..4]),
+            format: bytes[4],
+            chunked: bytes[5] & 1 != 0,
+            dimension: ImageDimension::from_bits(bytes[5] >> 1),
+            // files before 3D images were introduced contain zero
+            depth: LittleEndian::read_u16(&bytes[6..8]).max(1),
         }
     }
+}
+
+/// Struct for additional data of Geometry kind. Geometry currently does not
+/// store anything in the header, all information is in the payload.
+#[derive(Eq, PartialEq, Hash, Debug, Copy, Clone, Default)]
+pub struct BfGeometryAdditional;
+
+impl AdditionalData for BfGeometryAdditional {
+    fn write(&self, _: &mut [u8; 8]) {}
+
+    fn read(_: &[u8; 8]) -> Self {
+        BfGeometryAdditional
+    }
+}
+
+/// Struct for additional data of VirtualFileSystem kind.
+///
+/// Layout: flags (u8) at byte 0 with compressed entries in bit 0, remaining
+/// bytes are zero.
+#[derive(Eq, PartialEq, Hash, Debug, Copy, Clone, Default)]
+pub struct BfVfsAdditional {
+    /// Each entry is compressed separately with LZ4 (with prepended size).
+    pub compressed_entries: bool,
+}
 
-    pub fn into_u64(self) -> u64 {
-        return unsafe { std::mem::transmute(self) };
+impl AdditionalData for BfVfsAdditional {
+    fn write(&self, bytes: &mut [u8; 8]) {
+        bytes[0] = self.compressed_entries as u8;
     }
 
-    pub fn from_u64(data: u64) -> Self {
-        return unsafe { std::mem::transmute(data) };
+    fn read(bytes: &[u8; 8]) -> Self {
+        BfVfsAdditional {
+            compressed_entries: bytes[0] & 1 != 0,
+        }
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum ColorSpace {
     Linear,
     Srgb,
 }
 
 /// Supported image formats in Image kind of BF files.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 #[repr(u8)]
 pub enum BfImageFormat {
     // linear variants
@@ -101,9 +261,23 @@ pub enum BfImageFormat {
     SrgbDxt5 = 7,
     Srgb8 = 8,
     Srgb8A8 = 9,
+    // high quality and hdr variants
+    Bc4 = 10,
+    Bc5 = 11,
+    Bc6h = 12,
+    Bc7 = 13,
+    SrgbBc7 = 14,
+    Rgba16f = 15,
 }
 
 impl BfImageFormat {
+    /// All supported formats in order of their values.
+    pub const ALL: [BfImageFormat; 16] = [
+        Dxt1, Dxt3, Dxt5, Rgb8, Rgba8,
+        SrgbDxt1, SrgbDxt3, SrgbDxt5, Srgb8, Srgb8A8,
+        Bc4, Bc5, Bc6h, Bc7, SrgbBc7, Rgba16f,
+    ];
+
     pub fn channels(&self) -> usize {
         match self {
             BfImageFormat::Dxt1 => 3,
@@ -116,6 +290,12 @@ impl BfImageFormat {
             BfImageFormat::SrgbDxt5 => 4,
             BfImageFormat::Srgb8 => 3,
             BfImageFormat::Srgb8A8 => 4,
+            BfImageFormat::Bc4 => 1,
+            BfImageFormat::Bc5 => 2,
+            BfImageFormat::Bc6h => 3,
+            BfImageFormat::Bc7 => 4,
+            BfImageFormat::SrgbBc7 => 4,
+            BfImageFormat::Rgba16f => 4,
         }
     }
 
@@ -131,6 +311,12 @@ impl BfImageFormat {
             BfImageFormat::SrgbDxt5 => 8,
             BfImageFormat::Srgb8 => 24,
             BfImageFormat::Srgb8A8 => 32,
+            BfImageFormat::Bc4 => 4,
+            BfImageFormat::Bc5 => 8,
+            BfImageFormat::Bc6h => 8,
+            BfImageFormat::Bc7 => 8,
+            BfImageFormat::SrgbBc7 => 8,
+            BfImageFormat::Rgba16f => 64,
         }
     }
 
@@ -141,9 +327,38 @@ impl BfImageFormat {
             BfImageFormat::SrgbDxt5 => Srgb,
             BfImageFormat::Srgb8 => Srgb,
             BfImageFormat::Srgb8A8 => Srgb,
+            BfImageFormat::SrgbBc7 => Srgb,
             _ => Linear
         }
     }
+
+    /// Returns true if this format is stored in 4x4 pixel blocks.
+    pub fn is_block_compressed(&self) -> bool {
+        self.block_size().is_some()
+    }
+
+    /// Returns number of bytes of one 4x4 block for block compressed formats.
+    pub fn block_size(&self) -> Option<usize> {
+        match self {
+            BfImageFormat::Dxt1 | BfImageFormat::SrgbDxt1 => Some(8),
+            BfImageFormat::Dxt3 | BfImageFormat::SrgbDxt3 => Some(16),
+            BfImageFormat::Dxt5 | BfImageFormat::SrgbDxt5 => Some(16),
+            BfImageFormat::Bc4 => Some(8),
+            BfImageFormat::Bc5 | BfImageFormat::Bc6h => Some(16),
+            BfImageFormat::Bc7 | BfImageFormat::SrgbBc7 => Some(16),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes needed to store image of specified size in
+    /// this format. Block compressed formats always occupy at least one whole
+    /// block in each dimension (even when the image is smaller than 4x4).
+    pub fn size_of(&self, width: usize, height: usize) -> usize {
+        match self.block_size() {
+            Some(block) => width.div_ceil(4).max(1) * height.div_ceil(4).max(1) * block,
+            None => width * height * self.bits_per_pixel() / 8,
+        }
+    }
 }
 
 impl TryFrom<&str> for BfImageFormat {
@@ -161,6 +376,12 @@ impl TryFrom<&str> for BfImageFormat {
             "srgb_dxt5" => Ok(SrgbDxt5),
             "srgb" => Ok(Srgb8),
             "srgb_a" => Ok(Srgb8A8),
+            "bc4" => Ok(Bc4),
+            "bc5" => Ok(Bc5),
+            "bc6h" => Ok(Bc6h),
+            "bc7" => Ok(Bc7),
+            "srgb_bc7" => Ok(SrgbBc7),
+            "rgba16f" => Ok(Rgba16f),
             _ => Err(())
         }
     }
@@ -181,6 +402,12 @@ impl TryFrom<u8> for BfImageFormat {
             7 => Ok(SrgbDxt5),
             8 => Ok(Srgb8),
             9 => Ok(Srgb8A8),
+            10 => Ok(Bc4),
+            11 => Ok(Bc5),
+            12 => Ok(Bc6h),
+            13 => Ok(Bc7),
+            14 => Ok(SrgbBc7),
+            15 => Ok(Rgba16f),
             _ => Err(()),
         }
     }
@@ -188,7 +415,11 @@ impl TryFrom<u8> for BfImageFormat {
 
 /* Constant representing the two byte magic sequence 'BF' */
 const BF_MAGIC: u16 = 17986;
-const BF_MAX_SUPPORTED_VERSION: u8 = 1;
+const BF_MAX_SUPPORTED_VERSION: u8 = 3;
+/* First version which stores checksum of the payload in the header */
+const BF_CHECKSUM_VERSION: u8 = 2;
+/* First version which contains header extension with the codec of the payload */
+const BF_CODEC_VERSION: u8 = 3;
 
 impl BfHeader {
     pub fn new(kind: Kind, version: u8, additional: u64, uncompressed: u64, compressed: u64) -> Self {
@@ -196,138 +427,2461 @@ impl BfHeader {
             magic: BF_MAGIC,
             kind: kind as u8,
             version,
-            reserved: 0,
+            checksum: 0,
             additional,
             uncompressed,
             compressed,
         }
     }
+
+    /// Returns the size of the payload as it is stored in the file.
+    pub fn stored_size(&self) -> u64 {
+        if self.compressed == 0 { self.uncompressed } else { self.compressed }
+    }
+
+    /// Returns true if this header contains checksum of the payload.
+    #[inline]
+    pub fn has_checksum(&self) -> bool {
+        self.version >= BF_CHECKSUM_VERSION
+    }
+}
+
+/// Extension of the header present in files since version 3. It directly
+/// follows the header so the payload stays 8 byte aligned.
+#[repr(C)]
+#[derive(FromBytes, AsBytes, Eq, PartialEq, Hash, Debug, Copy, Clone)]
+pub struct BfHeaderExtension {
+    pub codec: u8,
+    pub reserved: [u8; 7],
+}
+
+/// Codec used to compress the payload. Files older than version 3 do not store
+/// the codec and their compressed payloads are always LZ4 blocks.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum Codec {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Lz4),
+            2 => Ok(Codec::Zstd),
+            _ => Err(()),
+        }
+    }
 }
 
 /// Structure for holding loaded BfFile using zero-copy loading mechanism.
 #[derive(Debug)]
 pub struct BfFile<'a> {
     pub header: LayoutVerified<&'a [u8], BfHeader>,
+    pub extension: Option<LayoutVerified<&'a [u8], BfHeaderExtension>>,
     pub data: &'a [u8],
 }
 
-/// Enum representing possible types of images.
-pub enum ImageType {
-    DXT1,
-    DXT5,
-    RGBA8,
-    RGBA16,
-    RGB8,
-    RGB16,
-    RG16,
-    R16,
-}
+impl<'a> BfFile<'a> {
+    /// Returns true if the payload of this file is stored compressed.
+    #[inline]
+    pub fn is_compressed(&self) -> bool {
+        self.header.compressed != 0
+    }
 
-/// Enum representing possible types geometry lists.
-pub enum GeometryListType {
-    Positions = 0,
-    Normals = 1,
-    Tangents = 2,
-    Colors = 3,
-    UV1 = 4,
-    UV2 = 5,
-    UV3 = 6,
-    UV4 = 7,
-    Indices = 8,
+    /// Returns the codec the payload is compressed with.
+    pub fn codec(&self) -> Codec {
+        match &self.extension {
+            // validity of the codec is checked while loading
+            Some(ext) => Codec::try_from(ext.codec).unwrap_or(Codec::None),
+            None if self.is_compressed() => Codec::Lz4,
+            None => Codec::None,
+        }
+    }
+
+    /// Returns decoded payload of this file. If the payload is stored uncompressed
+    /// the returned value borrows the data of this file, otherwise the payload is
+    /// decompressed into newly allocated buffer. The size of decoded payload is
+    /// verified against the `uncompressed` field of header.
+    pub fn payload(&self) -> Result<Cow<'a, [u8]>, Error> {
+        let expected = self.header.uncompressed;
+
+        let payload = if self.is_compressed() {
+            let data = self.stored_payload()?;
+            if expected > i32::MAX as u64 {
+                return Err(Error::DecompressionFailed(format!("payload of {} bytes is too big", expected)));
+            }
+
+            Cow::Owned(decompress(self.codec(), data, expected as usize)?)
+        } else {
+            Cow::Borrowed(self.data)
+        };
+
+        if payload.len() as u64 != expected {
+            return Err(Error::PayloadSizeMismatch { expected, actual: payload.len() as u64 });
+        }
+
+        Ok(payload)
+    }
+
+    /// Returns the payload exactly as it is stored in the file (possibly compressed).
+    fn stored_payload(&self) -> Result<&'a [u8], Error> {
+        let stored = self.header.stored_size();
+        if stored > self.data.len() as u64 {
+            return Err(Error::NotEnoughData { expected: stored as usize, actual: self.data.len() });
+        }
+        Ok(&self.data[..stored as usize])
+    }
+
+    /// Computes the checksum of the stored payload.
+    pub fn compute_checksum(&self) -> Result<u32, Error> {
+        Ok(crc32fast::hash(self.stored_payload()?))
+    }
+
+    /// Verifies the checksum stored in the header against the stored payload.
+    /// Files older than version 2 do not contain checksum and are always valid.
+    pub fn verify_checksum(&self) -> Result<(), Error> {
+        if !self.header.has_checksum() {
+            return Ok(());
+        }
+
+        let actual = self.compute_checksum()?;
+        if actual != self.header.checksum {
+            return Err(Error::ChecksumMismatch { expected: self.header.checksum, actual });
+        }
+        Ok(())
+    }
 }
 
-pub struct GeometryList<'a> {
-    kind: GeometryListType,
-    length: usize,
-    data: &'a [u8],
+/// Decompresses data compressed with specified codec. The decompressed data
+/// are never larger than `expected` bytes (plus one byte to detect the mismatch),
+/// so corrupted headers or data cannot cause huge allocations.
+pub fn decompress(codec: Codec, data: &[u8], expected: usize) -> Result<Vec<u8>, Error> {
+    let failed = |e: io::Error| Error::DecompressionFailed(e.to_string());
+    match codec {
+        Codec::None => Err(Error::DecompressionFailed("payload is compressed but codec is none".to_owned())),
+        Codec::Lz4 => {
+            // lz4 cannot expand data more than 255 times, do not allocate huge
+            // buffers because of corrupted header
+            if expected as u64 > (data.len() as u64).saturating_mul(255).saturating_add(16) || expected > i32::MAX as usize {
+                return Err(Error::DecompressionFailed(format!("{} bytes cannot decompress to {} bytes", data.len(), expected)));
+            }
+            lz4::block::decompress(data, Some(expected as i32)).map_err(failed)
+        }
+        Codec::Zstd => {
+            let mut decompressed = Vec::new();
+            zstd::stream::Decoder::new(data)
+                .map_err(failed)?
+                .take(expected as u64 + 1)
+                .read_to_end(&mut decompressed)
+                .map_err(failed)?;
+            Ok(decompressed)
+        }
+    }
 }
 
-#[derive(Debug)]
-pub enum Error {
-    NotEnoughDataOrUnaligned,
-    InvalidFileSignature,
-    VersionTooHigh,
-    InvalidKindValue,
+/// Compression policy used by BfWriter for the payload.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Compression {
+    /// Payload is always stored uncompressed.
+    None,
+    /// Payload is compressed with LZ4 in fast mode.
+    Lz4,
+    /// Payload is compressed with LZ4 in high compression mode with specified level.
+    Lz4High(i32),
+    /// Payload is compressed with Zstandard with specified level. Slower than LZ4
+    /// but with higher ratio, intended for distribution builds.
+    Zstd(i32),
 }
 
-/// Loads and deserializes byte array to BfFile using zero-copy mechanism. If
-/// the specified byte sequence is invalid Error is returned.
-pub fn load_bf_from_bytes(bytes: &[u8]) -> Result<BfFile, Error> {
+impl Compression {
+    /// Policies with the default levels that can be selected by name.
+    pub const PRESETS: [(&'static str, Compression); 4] = [
+        ("none", Compression::None),
+        ("lz4", Compression::Lz4),
+        ("lz4hc", Compression::Lz4High(16)),
+        ("zstd", Compression::Zstd(19)),
+    ];
+
+    /// Returns the codec that is used to compress the data with this policy.
+    pub fn codec(self) -> Codec {
+        match self {
+            Compression::None => Codec::None,
+            Compression::Lz4 | Compression::Lz4High(_) => Codec::Lz4,
+            Compression::Zstd(_) => Codec::Zstd,
+        }
+    }
 
-    // verify magic, version and kind values
-    if LittleEndian::read_u16(bytes) != BF_MAGIC { return Err(Error::InvalidFileSignature); }
-    if bytes[2] > Kind::MaxValue as u8 { return Err(Error::InvalidKindValue); }
-    if bytes[3] > 1 { return Err(Error::VersionTooHigh); }
+    /// Compresses the data using this policy. Returns `None` if this policy does
+    /// not compress.
+    pub fn compress(self, data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Compression::None => Ok(None),
+            Compression::Lz4 => lz4::block::compress(data, None, false).map(Some),
+            Compression::Lz4High(level) => lz4::block::compress(data, Some(HIGHCOMPRESSION(level)), false).map(Some),
+            Compression::Zstd(level) => zstd::stream::encode_all(data, level).map(Some),
+        }
+    }
+}
 
-    // transmute the slice
-    match LayoutVerified::new_from_prefix(bytes) {
-        None => Err(Error::NotEnoughDataOrUnaligned),
-        Some((header, data)) => Ok(BfFile { header, data })
+impl TryFrom<&str> for Compression {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Compression::PRESETS.iter()
+            .find(|(name, _)| *name == value)
+            .map(|(_, compression)| *compression)
+            .ok_or(())
     }
 }
 
+/// Writer of BF files. The payload is either passed in whole or written using
+/// the `io::Write` implementation. When finished the writer compresses the payload
+/// according to its compression policy, fills the sizes in header and writes the
+/// file atomically.
+///
+/// If the compressed payload is not smaller than the uncompressed payload, the
+/// payload is stored uncompressed.
+pub struct BfWriter {
+    kind: Kind,
+    additional: u64,
+    compression: Compression,
+    payload: Vec<u8>,
+}
 
-#[cfg(test)]
-mod tests {
-    use matches::assert_matches;
-    use zerocopy::AsBytes;
-    use crate::bf::{BfHeader, Kind, load_bf_from_bytes, Error, BF_MAX_SUPPORTED_VERSION, BF_MAGIC, BfImageAdditional};
+impl BfWriter {
+    /// Creates a new writer for file of specified kind with its additional data.
+    pub fn new(kind: Kind, additional: u64) -> Self {
+        BfWriter {
+            kind,
+            additional,
+            compression: Compression::Lz4,
+            payload: Vec::new(),
+        }
+    }
 
-    #[test]
-    fn test_load_bf_from_bytes() {
-        let header = BfHeader {
-            magic: BF_MAGIC,
-            kind: Kind::CompiledShader as u8,
-            version: 1,
-            reserved: 0,
-            additional: 66,
-            uncompressed: 1024,
-            compressed: 1023,
+    /// Sets the compression policy of the payload.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the whole payload of the file (replacing anything written before).
+    pub fn payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Compresses the payload and returns header, its extension and the stored payload.
+    fn encode(self) -> io::Result<(BfHeader, BfHeaderExtension, Vec<u8>)> {
+        let compressed = self.compression.compress(&self.payload)?;
+
+        let uncompressed = self.payload.len() as u64;
+        let (mut header, codec, payload) = match compressed {
+            Some(c) if c.len() < self.payload.len() => {
+                let header = BfHeader::new(self.kind, BF_MAX_SUPPORTED_VERSION, self.additional, uncompressed, c.len() as u64);
+                (header, self.compression.codec(), c)
+            }
+            _ => (BfHeader::new(self.kind, BF_MAX_SUPPORTED_VERSION, self.additional, uncompressed, 0), Codec::None, self.payload),
         };
-        let data = &[1, 2, 3, 4, 1, 2, 3, 4];
+        header.checksum = crc32fast::hash(&payload);
 
-        let mut bytes = Vec::new();
-        bytes.extend(header.as_bytes());
-        bytes.extend(data.as_bytes());
+        let extension = BfHeaderExtension { codec: codec as u8, reserved: [0; 7] };
+        Ok((header, extension, payload))
+    }
 
-        // load from bytes
-        let result = load_bf_from_bytes(&bytes);
+    /// Finishes the file and returns its serialized bytes (header and stored payload).
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        let (header, extension, payload) = self.encode()?;
+        let mut bytes = Vec::with_capacity(std::mem::size_of::<BfHeader>() + std::mem::size_of::<BfHeaderExtension>() + payload.len());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(extension.as_bytes());
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
 
-        assert!(result.is_ok());
+    /// Finishes the file and writes it to specified path. The file is first written
+    /// to a temporary file next to the target and then renamed, so the target is never
+    /// left partially written. Returns header of the written file.
+    pub fn finish(self, path: &Path) -> io::Result<BfHeader> {
+        let (header, extension, payload) = self.encode()?;
 
-        let file = result.ok().unwrap();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
 
-        assert_eq!(file.header.magic, header.magic);
-        assert_eq!(file.header.kind, header.kind);
-        assert_eq!(file.header.version, header.version);
-        assert_eq!(file.header.reserved, header.reserved);
-        assert_eq!(file.header.uncompressed, header.uncompressed);
-        assert_eq!(file.header.compressed, header.compressed);
-        assert_eq!(file.header.additional, header.additional);
-        assert_eq!(file.data, data);
+        let result = (|| {
+            let mut file = File::create(&tmp)?;
+            file.write_all(header.as_bytes())?;
+            file.write_all(extension.as_bytes())?;
+            file.write_all(&payload)?;
+            file.sync_all()?;
+            std::fs::rename(&tmp, path)
+        })();
+
+        if result.is_err() {
+            std::fs::remove_file(&tmp).ok();
+        }
+
+        result.map(|_| header)
     }
+}
 
-    #[test]
-    fn test_invalid_header_variants() {
-        assert_matches!(load_bf_from_bytes(&[0, 0, 0]), Err(Error::InvalidFileSignature));
-        assert_matches!(load_bf_from_bytes(&[66, 70, 255]), Err(Error::InvalidKindValue));
-        assert_matches!(load_bf_from_bytes(&[66, 70, 1, BF_MAX_SUPPORTED_VERSION + 1]), Err(Error::VersionTooHigh));
-        assert_matches!(load_bf_from_bytes(&[66, 70, 1, 1, 0, 1]), Err(Error::NotEnoughDataOrUnaligned));
+impl Write for BfWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.payload.write(buf)
     }
 
-    #[test]
-    fn bf_image_additional_data() {
-        let a = BfImageAdditional {
-            width: 169,
-            height: 444,
-            format: 4,
-            padding1: 0,
-            padding2: 0,
-        };
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-        let b = a.clone();
-        let a_u64 = b.into_u64();
+/// Returns the size of specified mip-map level of image with specified base size.
+#[inline]
+pub fn mip_dimensions(width: usize, height: usize, level: usize) -> (usize, usize) {
+    let shift = |x: usize| x.checked_shr(level as u32).unwrap_or(0).max(1);
+    (shift(width), shift(height))
+}
 
-        assert_eq!(a, BfImageAdditional::from_u64(a_u64));
+/// Typed view into BF file of Image kind providing random access to its mip-maps.
+#[derive(Debug)]
+pub struct BfImage<'a> {
+    additional: BfImageAdditional,
+    format: BfImageFormat,
+    payload: Cow<'a, [u8]>,
+    /// Ranges of the mip-maps in payload, levels below `base_level` are empty.
+    mips: Vec<Range<usize>>,
+    base_level: usize,
+}
+
+impl<'a> BfImage<'a> {
+    /// Creates a view of the specified file. The payload is decoded and the
+    /// mip-map chain is verified against its length.
+    pub fn new(file: &BfFile<'a>) -> Result<Self, Error> {
+        BfImage::from_level(file, 0)
+    }
+
+    /// Creates a view of the specified file that provides only mip-maps starting
+    /// at `base_level` (or the last level if the image has less levels). If the
+    /// mip-maps are chunked only those mip-maps are decompressed, otherwise the
+    /// whole payload has to be decoded.
+    pub fn from_level(file: &BfFile<'a>, base_level: usize) -> Result<Self, Error> {
+        if file.header.kind != Kind::Image as u8 {
+            return Err(Error::KindMismatch { expected: Kind::Image as u8, actual: file.header.kind });
+        }
+
+        let additional = BfImageAdditional::from_u64(file.header.additional);
+        let format = BfImageFormat::try_from(additional.format)
+            .map_err(|_| Error::InvalidImageFormat(additional.format))?;
+        let payload = file.payload()?;
+
+        let (width, height) = (additional.width as usize, additional.height as usize);
+        let not_square_cube = additional.dimension == ImageDimension::Cube && width != height;
+        if width == 0 || height == 0 || not_square_cube {
+            return Err(Error::InvalidImageDimensions { width: additional.width, height: additional.height });
+        }
+
+        if additional.chunked {
+            return BfImage::from_chunks(additional, format, &payload, base_level);
+        }
+
+        // ranges of each mip-map in the payload
+        let mut mips = vec![];
+        let mut offset = 0;
+        while offset < payload.len() {
+            let level = mips.len();
+            if level > 0 && additional.mip_extent(level) == additional.mip_extent(level - 1) {
+                // data after the 1x1x1 mip-map
+                return Err(Error::InvalidMipChain { expected: offset, actual: payload.len() });
+            }
+
+            let size = additional.mip_size(format, level);
+            mips.push(offset..offset + size);
+            offset += size;
+        }
+
+        if offset != payload.len() || mips.is_empty() {
+            let expected = if mips.is_empty() { additional.mip_size(format, 0) } else { offset };
+            return Err(Error::InvalidMipChain { expected, actual: payload.len() });
+        }
+
+        let base_level = base_level.min(mips.len() - 1);
+        Ok(BfImage { additional, format, payload, mips, base_level })
+    }
+
+    /// Decompresses the chunks of mip-maps starting at `base_level`.
+    fn from_chunks(additional: BfImageAdditional, format: BfImageFormat, payload: &[u8], base_level: usize) -> Result<Self, Error> {
+        let chunks = read_image_chunks(payload)?;
+        if chunks.offsets.len() < 2 {
+            return Err(Error::InvalidMipChain { expected: additional.mip_size(format, 0), actual: 0 });
+        }
+
+        let count = chunks.offsets.len() - 1;
+        if count > 1 && additional.mip_extent(count - 1) == additional.mip_extent(count - 2) {
+            return Err(Error::InvalidMipChain { expected: count - 1, actual: count });
+        }
+
+        let base_level = base_level.min(count - 1);
+        let mut mips = vec![0..0; base_level];
+        let mut decoded = Vec::new();
+        for level in base_level..count {
+            let size = additional.mip_size(format, level);
+            let chunk = &chunks.data[chunks.offsets[level]..chunks.offsets[level + 1]];
+
+            let start = decoded.len();
+            if chunk.len() == size {
+                decoded.extend_from_slice(chunk);
+            } else {
+                let mip = decompress(chunks.codec, chunk, size)?;
+                if mip.len() != size {
+                    return Err(Error::PayloadSizeMismatch { expected: size as u64, actual: mip.len() as u64 });
+                }
+                decoded.extend_from_slice(&mip);
+            }
+            mips.push(start..decoded.len());
+        }
+
+        Ok(BfImage { additional, format, payload: Cow::Owned(decoded), mips, base_level })
+    }
+
+    #[inline]
+    pub fn additional(&self) -> BfImageAdditional {
+        self.additional
+    }
+
+    #[inline]
+    pub fn width(&self) -> u16 {
+        self.additional.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u16 {
+        self.additional.height
+    }
+
+    #[inline]
+    pub fn format(&self) -> BfImageFormat {
+        self.format
+    }
+
+    /// Returns the number of mip-maps stored in this image.
+    #[inline]
+    pub fn mip_count(&self) -> usize {
+        self.mips.len()
+    }
+
+    /// Returns the first mip-map level available in this view.
+    #[inline]
+    pub fn base_level(&self) -> usize {
+        self.base_level
+    }
+
+    #[inline]
+    pub fn dimension(&self) -> ImageDimension {
+        self.additional.dimension
+    }
+
+    /// Returns the number of faces or layers of this image (see `BfImageAdditional::layer_count`).
+    #[inline]
+    pub fn layer_count(&self) -> usize {
+        self.additional.layer_count()
+    }
+
+    /// Returns width, height and data of mip-map at specified level or None if
+    /// the level does not exist or is below the base level of this view. The
+    /// data contains all layers (faces) and depth slices of the mip-map.
+    pub fn mip(&self, level: usize) -> Option<(usize, usize, &[u8])> {
+        if level >= self.mip_count() || level < self.base_level { return None; }
+
+        let (w, h) = mip_dimensions(self.width() as usize, self.height() as usize, level);
+        Some((w, h, &self.payload[self.mips[level].clone()]))
+    }
+
+    /// Returns the number of 2D slices of mip-map at specified level. That is
+    /// the number of layers (faces) multiplied by depth of the mip-map.
+    pub fn slice_count(&self, level: usize) -> usize {
+        self.additional.mip_extent(level).2 * self.layer_count()
+    }
+
+    /// Returns data of one 2D slice of mip-map at specified level. Slices are
+    /// ordered by layer (face) and then by depth.
+    pub fn slice(&self, level: usize, index: usize) -> Option<&[u8]> {
+        if index >= self.slice_count(level) { return None; }
+
+        let (w, h, data) = self.mip(level)?;
+        let size = self.format.size_of(w, h);
+        Some(&data[index * size..(index + 1) * size])
+    }
+}
+
+/// Converts value in sRGB color space to linear color space.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Converts value in linear color space to sRGB color space.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Decodes one 2D slice of image in specified format (for example from
+/// `BfImage::slice`) to RGBA8 pixels.
+///
+/// Color channels are converted to `color_space` when it differs from the color
+/// space of the format, alpha is never converted. Floating point formats are
+/// linear and their values are clamped to range 0 to 1. Single and two channel
+/// formats store the values in red (and green) channel like the GPU would
+/// sample them.
+pub fn decode_rgba8(format: BfImageFormat, width: usize, height: usize, data: &[u8], color_space: ColorSpace) -> Result<Vec<u8>, Error> {
+    let size = format.size_of(width, height);
+    if data.len() < size {
+        return Err(Error::NotEnoughData { expected: size, actual: data.len() });
     }
-}
\ No newline at end of file
+    let data = &data[..size];
+
+    let floats = match format {
+        Bc6h => Some(bc::decode_bc6h(data, width, height)),
+        Rgba16f => Some(bc::decode_rgba16f(data)),
+        _ => None,
+    };
+    if let Some(floats) = floats {
+        let pixels = floats.iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let x = x.clamp(0.0, 1.0);
+                let x = if color_space == Srgb && i % 4 != 3 { linear_to_srgb(x) } else { x };
+                (x * 255.0).round() as u8
+            })
+            .collect();
+        return Ok(pixels);
+    }
+
+    let mut pixels = match format {
+        Dxt1 | SrgbDxt1 => bc::decode_bc1(data, width, height),
+        Dxt3 | SrgbDxt3 => bc::decode_bc2(data, width, height),
+        Dxt5 | SrgbDxt5 => bc::decode_bc3(data, width, height),
+        Bc4 => bc::decode_bc4(data, width, height),
+        Bc5 => bc::decode_bc5(data, width, height),
+        Bc7 | SrgbBc7 => bc::decode_bc7(data, width, height),
+        Rgb8 | Srgb8 => data.chunks_exact(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect(),
+        Rgba8 | Srgb8A8 | Bc6h | Rgba16f => data.to_vec(),
+    };
+
+    if format.color_space() != color_space {
+        let convert = |x: u8| {
+            let x = x as f32 / 255.0;
+            let x = if color_space == Srgb { linear_to_srgb(x) } else { srgb_to_linear(x) };
+            (x * 255.0).round() as u8
+        };
+        let table: Vec<u8> = (0..=255).map(convert).collect();
+        for pixel in pixels.chunks_exact_mut(4) {
+            for c in pixel[..3].iter_mut() {
+                *c = table[*c as usize];
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+/// Statistics of differences between two images decoded to RGBA8.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct PixelDiff {
+    /// Maximum absolute difference of one channel.
+    pub max_error: u8,
+    /// Mean squared error over all compared channels.
+    pub mse: f64,
+    /// Number of compared channel values.
+    pub samples: usize,
+}
+
+impl PixelDiff {
+    /// Compares first `channels` channels of RGBA8 pixels of two images with the
+    /// same dimensions.
+    pub fn compare(a: &[u8], b: &[u8], channels: usize) -> Self {
+        let mut diff = PixelDiff::default();
+        let mut sum = 0u64;
+        for (pa, pb) in a.chunks_exact(4).zip(b.chunks_exact(4)) {
+            for (x, y) in pa[..channels].iter().zip(pb[..channels].iter()) {
+                let error = (*x as i32 - *y as i32).unsigned_abs() as u8;
+                diff.max_error = diff.max_error.max(error);
+                sum += error as u64 * error as u64;
+            }
+            diff.samples += channels;
+        }
+        diff.mse = sum as f64 / diff.samples.max(1) as f64;
+        diff
+    }
+
+    /// Combines statistics of two parts of an image (for example two slices).
+    pub fn merge(self, other: PixelDiff) -> Self {
+        let samples = self.samples + other.samples;
+        PixelDiff {
+            max_error: self.max_error.max(other.max_error),
+            mse: (self.mse * self.samples as f64 + other.mse * other.samples as f64) / samples.max(1) as f64,
+            samples,
+        }
+    }
+
+    /// Returns peak signal-to-noise ratio in decibels. Identical images have
+    /// infinite PSNR.
+    pub fn psnr(&self) -> f64 {
+        10.0 * (255.0 * 255.0 / self.mse).log10()
+    }
+}
+
+/// Codec and offsets of the chunks of payload with chunked mip-maps.
+#[derive(Debug)]
+pub struct ImageChunks<'a> {
+    pub codec: Codec,
+    /// Offsets of the start of each chunk relative to `data` with the end of the
+    /// last chunk as the last item.
+    pub offsets: Vec<usize>,
+    pub data: &'a [u8],
+}
+
+impl<'a> ImageChunks<'a> {
+    /// Returns the stored (possibly compressed) data of the chunk at specified level.
+    pub fn chunk(&self, level: usize) -> Option<&'a [u8]> {
+        let start = *self.offsets.get(level)?;
+        let end = *self.offsets.get(level + 1)?;
+        Some(&self.data[start..end])
+    }
+}
+
+/// Reads the table of chunks from payload of Image kind with chunked mip-maps.
+/// The chunks themselves are not decompressed.
+pub fn read_image_chunks(payload: &[u8]) -> Result<ImageChunks<'_>, Error> {
+    if payload.len() < 8 {
+        return Err(Error::NotEnoughData { expected: 8, actual: payload.len() });
+    }
+
+    let codec = Codec::try_from(payload[0]).map_err(|_| Error::InvalidCodec(payload[0]))?;
+    let count = LittleEndian::read_u32(&payload[4..8]) as usize;
+    let table = count.saturating_add(1).saturating_mul(4);
+    if table > payload.len() - 8 {
+        return Err(Error::NotEnoughData { expected: table.saturating_add(8), actual: payload.len() });
+    }
+
+    let data = &payload[8 + table..];
+    let offsets: Vec<usize> = payload[8..8 + table]
+        .chunks_exact(4)
+        .map(|x| LittleEndian::read_u32(x) as usize)
+        .collect();
+
+    // offsets must start at zero, be monotonic and end at the end of data
+    if offsets[0] != 0 {
+        return Err(Error::InvalidChunkOffset { level: 0, offset: offsets[0] });
+    }
+    if let Some(level) = (1..=count).find(|&i| offsets[i] < offsets[i - 1]) {
+        return Err(Error::InvalidChunkOffset { level, offset: offsets[level] });
+    }
+    if offsets[count] != data.len() {
+        return Err(Error::InvalidMipChain { expected: offsets[count], actual: data.len() });
+    }
+
+    Ok(ImageChunks { codec, offsets, data })
+}
+
+/// Builder of payload of Image kind with chunked mip-maps. Each mip-map is
+/// compressed separately so loaders can decompress only the levels they need.
+///
+/// If the compressed mip-map is not smaller than the uncompressed mip-map, the
+/// mip-map is stored uncompressed.
+pub struct BfImageChunksBuilder {
+    compression: Compression,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl BfImageChunksBuilder {
+    pub fn new(compression: Compression) -> Self {
+        BfImageChunksBuilder { compression, chunks: Vec::new() }
+    }
+
+    /// Compresses and adds the next mip-map.
+    pub fn add_mip(&mut self, data: &[u8]) -> io::Result<()> {
+        let chunk = match self.compression.compress(data)? {
+            Some(c) if c.len() < data.len() => c,
+            _ => data.to_vec(),
+        };
+        self.chunks.push(chunk);
+        Ok(())
+    }
+
+    /// Serializes the table of chunks and the chunks to payload.
+    ///
+    /// # Panics
+    /// Panics if the chunks do not fit into 4 GB.
+    pub fn build(self) -> Vec<u8> {
+        let table = 8 + 4 * (self.chunks.len() + 1);
+        let mut payload = Vec::with_capacity(table + self.chunks.iter().map(|c| c.len()).sum::<usize>());
+        let mut buf = [0u8; 4];
+
+        payload.extend_from_slice(&[self.compression.codec() as u8, 0, 0, 0]);
+        LittleEndian::write_u32(&mut buf, self.chunks.len() as u32);
+        payload.extend_from_slice(&buf);
+
+        let mut offset = 0usize;
+        for chunk in std::iter::once(&Vec::new()).chain(self.chunks.iter()) {
+            offset += chunk.len();
+            LittleEndian::write_u32(&mut buf, u32::try_from(offset).expect("image chunks do not fit into 4 GB"));
+            payload.extend_from_slice(&buf);
+        }
+
+        for chunk in self.chunks {
+            payload.extend_from_slice(&chunk);
+        }
+        payload
+    }
+}
+
+/// Enum representing possible types of images.
+pub enum ImageType {
+    DXT1,
+    DXT5,
+    RGBA8,
+    RGBA16,
+    RGB8,
+    RGB16,
+    RG16,
+    R16,
+}
+
+/// Enum representing possible types geometry lists.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[repr(u16)]
+pub enum GeometryListType {
+    Positions = 0,
+    Normals = 1,
+    Tangents = 2,
+    Colors = 3,
+    UV1 = 4,
+    UV2 = 5,
+    UV3 = 6,
+    UV4 = 7,
+    IndicesU8 = 8,
+    IndicesU16 = 9,
+    IndicesU32 = 10,
+    /// Tangents with sign of the bitangent (handedness) in `w`.
+    SignedTangents = 11,
+}
+
+impl GeometryListType {
+    /// Returns the size of one element of this list type in bytes.
+    pub fn element_size(&self) -> usize {
+        match self {
+            GeometryListType::Positions => 12,
+            GeometryListType::Normals => 12,
+            GeometryListType::Tangents => 12,
+            GeometryListType::Colors => 12,
+            GeometryListType::UV1 => 8,
+            GeometryListType::UV2 => 8,
+            GeometryListType::UV3 => 8,
+            GeometryListType::UV4 => 8,
+            GeometryListType::IndicesU8 => 1,
+            GeometryListType::IndicesU16 => 2,
+            GeometryListType::IndicesU32 => 4,
+            GeometryListType::SignedTangents => 16,
+        }
+    }
+
+    /// Returns true if this list type contains indices.
+    pub fn is_indices(&self) -> bool {
+        matches!(self, GeometryListType::IndicesU8 | GeometryListType::IndicesU16 | GeometryListType::IndicesU32)
+    }
+}
+
+impl TryFrom<u16> for GeometryListType {
+    type Error = ();
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(GeometryListType::Positions),
+            1 => Ok(GeometryListType::Normals),
+            2 => Ok(GeometryListType::Tangents),
+            3 => Ok(GeometryListType::Colors),
+            4 => Ok(GeometryListType::UV1),
+            5 => Ok(GeometryListType::UV2),
+            6 => Ok(GeometryListType::UV3),
+            7 => Ok(GeometryListType::UV4),
+            8 => Ok(GeometryListType::IndicesU8),
+            9 => Ok(GeometryListType::IndicesU16),
+            10 => Ok(GeometryListType::IndicesU32),
+            11 => Ok(GeometryListType::SignedTangents),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Header at the start of (decompressed) payload of Geometry kind.
+#[repr(C)]
+#[derive(FromBytes, AsBytes, Eq, PartialEq, Hash, Debug)]
+pub struct BfGeometryHeader {
+    pub flags: u32,
+    pub lists: u32,
+}
+
+/// Header describing one list inside payload of Geometry kind. The `length`
+/// is the number of elements (not bytes) in the list.
+#[repr(C)]
+#[derive(FromBytes, AsBytes, Eq, PartialEq, Hash, Debug)]
+pub struct BfGeometryListHeader {
+    pub kind: u16,
+    pub flags: u16,
+    pub length: u32,
+}
+
+/// Data of each list is padded to this amount of bytes so all the lists
+/// can be read as typed slices without copying.
+const GEOMETRY_LIST_ALIGNMENT: usize = 4;
+
+/// One list of the Geometry kind payload (borrowed from the payload).
+#[derive(Debug)]
+pub struct GeometryList<'a> {
+    kind: GeometryListType,
+    flags: u16,
+    length: usize,
+    data: &'a [u8],
+}
+
+/// Typed slice of indices stored in Geometry kind.
+#[derive(Debug, Copy, Clone)]
+pub enum Indices<'a> {
+    U8(&'a [u8]),
+    U16(&'a [u16]),
+    U32(&'a [u32]),
+}
+
+impl<'a> Indices<'a> {
+    /// Returns the number of indices.
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U8(t) => t.len(),
+            Indices::U16(t) => t.len(),
+            Indices::U32(t) => t.len(),
+        }
+    }
+
+    /// Returns true if there are no indices.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the index at specified position widened to u32.
+    pub fn get(&self, idx: usize) -> Option<u32> {
+        match self {
+            Indices::U8(t) => t.get(idx).map(|x| u32::from(*x)),
+            Indices::U16(t) => t.get(idx).map(|x| u32::from(*x)),
+            Indices::U32(t) => t.get(idx).cloned(),
+        }
+    }
+}
+
+impl<'a> GeometryList<'a> {
+    #[inline]
+    pub fn kind(&self) -> GeometryListType {
+        self.kind
+    }
+
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        self.flags
+    }
+
+    /// Returns number of elements in this list.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Returns raw bytes of this list (without padding).
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn typed<T: FromBytes>(&self) -> Option<&'a [T]> {
+        LayoutVerified::new_slice(self.data).map(|t| t.into_slice())
+    }
+
+    /// Returns the list as slice of float3 if this list contains float3 elements.
+    pub fn as_float3(&self) -> Option<&'a [[f32; 3]]> {
+        match self.kind {
+            GeometryListType::Positions | GeometryListType::Normals |
+            GeometryListType::Tangents | GeometryListType::Colors => self.typed(),
+            _ => None,
+        }
+    }
+
+    /// Returns the list as slice of float2 if this list contains float2 elements.
+    pub fn as_float2(&self) -> Option<&'a [[f32; 2]]> {
+        match self.kind {
+            GeometryListType::UV1 | GeometryListType::UV2 |
+            GeometryListType::UV3 | GeometryListType::UV4 => self.typed(),
+            _ => None,
+        }
+    }
+
+    /// Returns the list as slice of float4 if this list contains float4 elements.
+    pub fn as_float4(&self) -> Option<&'a [[f32; 4]]> {
+        match self.kind {
+            GeometryListType::SignedTangents => self.typed(),
+            _ => None,
+        }
+    }
+
+    /// Returns the list as typed indices if this list contains indices.
+    pub fn as_indices(&self) -> Option<Indices<'a>> {
+        match self.kind {
+            GeometryListType::IndicesU8 => Some(Indices::U8(self.data)),
+            GeometryListType::IndicesU16 => self.typed().map(Indices::U16),
+            GeometryListType::IndicesU32 => self.typed().map(Indices::U32),
+            _ => None,
+        }
+    }
+}
+
+/// Structure for holding (decompressed) Geometry payload using zero-copy loading mechanism.
+#[derive(Debug)]
+pub struct BfGeometry<'a> {
+    pub header: LayoutVerified<&'a [u8], BfGeometryHeader>,
+    lists: Vec<GeometryList<'a>>,
+}
+
+impl<'a> BfGeometry<'a> {
+    /// Returns all lists in the order they are stored in the payload.
+    pub fn lists(&self) -> &[GeometryList<'a>] {
+        &self.lists
+    }
+
+    /// Returns first list of specified type if it exists.
+    pub fn list(&self, kind: GeometryListType) -> Option<&GeometryList<'a>> {
+        self.lists.iter().find(|l| l.kind == kind)
+    }
+
+    pub fn positions(&self) -> Option<&'a [[f32; 3]]> {
+        self.list(GeometryListType::Positions).and_then(|l| l.as_float3())
+    }
+
+    pub fn normals(&self) -> Option<&'a [[f32; 3]]> {
+        self.list(GeometryListType::Normals).and_then(|l| l.as_float3())
+    }
+
+    pub fn tangents(&self) -> Option<&'a [[f32; 3]]> {
+        self.list(GeometryListType::Tangents).and_then(|l| l.as_float3())
+    }
+
+    /// Returns tangents with handedness (sign of the bitangent) in `w`.
+    pub fn signed_tangents(&self) -> Option<&'a [[f32; 4]]> {
+        self.list(GeometryListType::SignedTangents).and_then(|l| l.as_float4())
+    }
+
+    pub fn colors(&self) -> Option<&'a [[f32; 3]]> {
+        self.list(GeometryListType::Colors).and_then(|l| l.as_float3())
+    }
+
+    /// Returns first uv set (`UV1`).
+    pub fn uvs(&self) -> Option<&'a [[f32; 2]]> {
+        self.list(GeometryListType::UV1).and_then(|l| l.as_float2())
+    }
+
+    /// Returns first list of indices regardless of its index type.
+    pub fn indices(&self) -> Option<Indices<'a>> {
+        self.lists.iter().find(|l| l.kind.is_indices()).and_then(|l| l.as_indices())
+    }
+
+    /// Returns the number of LOD levels (lists of indices).
+    pub fn lod_count(&self) -> usize {
+        self.lists.iter().filter(|l| l.kind.is_indices()).count()
+    }
+
+    /// Returns list of indices of specified LOD level.
+    pub fn lod(&self, level: u16) -> Option<Indices<'a>> {
+        self.lists.iter()
+            .find(|l| l.kind.is_indices() && l.flags == level)
+            .and_then(|l| l.as_indices())
+    }
+
+    /// Returns minimum and maximum corner of axis aligned bounding box of the
+    /// positions or None if there are no positions.
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        let positions = self.positions().filter(|p| !p.is_empty())?;
+        let mut min = positions[0];
+        let mut max = positions[0];
+        for p in positions.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+        Some((min, max))
+    }
+}
+
+/// Builder that serializes lists into payload of Geometry kind.
+#[derive(Default)]
+pub struct BfGeometryBuilder {
+    flags: u32,
+    lists: Vec<(BfGeometryListHeader, Vec<u8>)>,
+}
+
+impl BfGeometryBuilder {
+    pub fn new() -> Self {
+        BfGeometryBuilder::default()
+    }
+
+    /// Sets global flags of the geometry.
+    pub fn flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Adds a list of specified type to the geometry.
+    ///
+    /// # Panics
+    /// Panics if the size of `T` does not match the element size of the list type.
+    pub fn list<T: AsBytes>(mut self, kind: GeometryListType, data: &[T]) -> Self {
+        assert_eq!(std::mem::size_of::<T>(), kind.element_size(), "element type does not match list type {:?}", kind);
+
+        let header = BfGeometryListHeader {
+            kind: kind as u16,
+            flags: 0,
+            length: data.len() as u32,
+        };
+        self.lists.push((header, Vec::from(data.as_bytes())));
+        self
+    }
+
+    /// Adds list of indices using the smallest index type that can hold all
+    /// the values.
+    pub fn indices(self, indices: &[u32]) -> Self {
+        self.lod_indices(0, indices)
+    }
+
+    /// Adds list of indices of specified LOD level (stored in the list flags)
+    /// using the smallest index type that can hold all the values. All levels
+    /// share the vertex lists.
+    pub fn lod_indices(self, level: u16, indices: &[u32]) -> Self {
+        let max = indices.iter().cloned().max().unwrap_or(0);
+
+        let mut builder = if max <= u32::from(u8::MAX) {
+            let narrow: Vec<u8> = indices.iter().map(|x| *x as u8).collect();
+            self.list(GeometryListType::IndicesU8, &narrow)
+        } else if max <= u32::from(u16::MAX) {
+            let narrow: Vec<u16> = indices.iter().map(|x| *x as u16).collect();
+            self.list(GeometryListType::IndicesU16, &narrow)
+        } else {
+            self.list(GeometryListType::IndicesU32, indices)
+        };
+        builder.lists.last_mut().unwrap().0.flags = level;
+        builder
+    }
+
+    /// Serializes the geometry header, list headers and lists data to
+    /// uncompressed payload.
+    pub fn build(self) -> Vec<u8> {
+        let header = BfGeometryHeader {
+            flags: self.flags,
+            lists: self.lists.len() as u32,
+        };
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(header.as_bytes());
+        for (list_header, _) in self.lists.iter() {
+            payload.extend_from_slice(list_header.as_bytes());
+        }
+        for (_, data) in self.lists.iter() {
+            payload.extend_from_slice(data);
+            while payload.len() % GEOMETRY_LIST_ALIGNMENT != 0 {
+                payload.push(0);
+            }
+        }
+        payload
+    }
+}
+
+/// One entry (file) stored in Virtual File System archive.
+#[derive(Debug, Copy, Clone)]
+pub struct VfsEntry<'a> {
+    name: &'a str,
+    offset: usize,
+    data: &'a [u8],
+}
+
+impl<'a> VfsEntry<'a> {
+    /// Returns path of the entry relative to the archive root (with `/` separators).
+    #[inline]
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns offset of the entry data from the start of the VFS payload.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Structure for holding (decompressed) VirtualFileSystem payload using zero-copy
+/// loading mechanism.
+#[derive(Debug)]
+pub struct BfVfs<'a> {
+    entries: Vec<VfsEntry<'a>>,
+}
+
+impl<'a> BfVfs<'a> {
+    /// Returns all entries in the order they are stored in the archive.
+    pub fn entries(&self) -> &[VfsEntry<'a>] {
+        &self.entries
+    }
+
+    /// Finds the entry by its name.
+    pub fn entry(&self, name: &str) -> Option<&VfsEntry<'a>> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// Returns data of the entry with specified name.
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.entry(name).map(|e| e.data)
+    }
+}
+
+/// Builder that serializes files into payload of VirtualFileSystem kind.
+#[derive(Default)]
+pub struct BfVfsBuilder {
+    entries: BTreeMap<String, Vec<u8>>,
+    archive_compression: Option<Compression>,
+}
+
+impl BfVfsBuilder {
+    pub fn new() -> Self {
+        BfVfsBuilder::default()
+    }
+
+    /// Adds file with specified name to the archive. If the file with same name
+    /// already exists in the archive, it is replaced.
+    pub fn add(&mut self, name: &str, data: Vec<u8>) {
+        self.entries.insert(name.to_owned(), data);
+    }
+
+    /// Recursively adds all files inside the `root` directory. Names of the entries
+    /// are paths relative to the `root` with `/` as separator.
+    pub fn add_directory(&mut self, root: &Path) -> io::Result<()> {
+        self.add_directory_filtered(root, |_| true)
+    }
+
+    /// Recursively adds all files inside the `root` directory for which the `filter`
+    /// returns true. The filter receives the name of the entry.
+    pub fn add_directory_filtered<F: Fn(&str) -> bool>(&mut self, root: &Path, filter: F) -> io::Result<()> {
+        let mut stack = vec![root.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    stack.push(path);
+                    continue;
+                }
+
+                let name = vfs_entry_name(root, &path)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "file name is not valid utf8"))?;
+                if filter(&name) {
+                    self.add(&name, std::fs::read(&path)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of entries added so far.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Sets the compression policy of the whole payload used by `VfsCompression::Archive`
+    /// layout (defaults to `Compression::Lz4High(16)`). It is ignored by other layouts: entries
+    /// of the `VfsCompression::Entries` layout are always compressed with LZ4 HC.
+    pub fn archive_compression(&mut self, compression: Compression) {
+        self.archive_compression = Some(compression);
+    }
+
+    /// Returns the total size of data of all entries added so far.
+    pub fn data_size(&self) -> usize {
+        self.entries.values().map(|d| d.len()).sum()
+    }
+
+    /// Serializes the archive using specified compression layout and saves it
+    /// as BF file to `output`.
+    pub fn save(mut self, output: &Path, compression: VfsCompression) -> io::Result<BfHeader> {
+        let mut additional = BfVfsAdditional::default();
+        if let VfsCompression::Entries = compression {
+            additional.compressed_entries = true;
+            for data in self.entries.values_mut() {
+                *data = lz4::block::compress(data, Some(HIGHCOMPRESSION(16)), true)?;
+            }
+        }
+
+        let policy = match compression {
+            VfsCompression::Archive => self.archive_compression.unwrap_or(Compression::Lz4High(16)),
+            _ => Compression::None,
+        };
+
+        BfWriter::new(Kind::VirtualFileSystem, additional.into_u64())
+            .compression(policy)
+            .payload(self.build())
+            .finish(output)
+    }
+
+    /// Serializes the entry table and data of all entries to uncompressed payload.
+    ///
+    /// # Panics
+    /// Panics if the data of all entries does not fit into 4 GB.
+    pub fn build(self) -> Vec<u8> {
+        let mut table = Vec::new();
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4];
+
+        LittleEndian::write_u32(&mut buf, self.entries.len() as u32);
+        table.extend_from_slice(&buf);
+
+        for (name, contents) in self.entries {
+            let offset = u32::try_from(data.len()).expect("vfs archive is too big");
+            let length = u32::try_from(contents.len()).expect("vfs entry is too big");
+
+            table.extend_from_slice(name.as_bytes());
+            table.push(0);
+            LittleEndian::write_u32(&mut buf, length);
+            table.extend_from_slice(&buf);
+            LittleEndian::write_u32(&mut buf, offset);
+            table.extend_from_slice(&buf);
+
+            data.extend(contents);
+        }
+
+        table.extend(data);
+        table
+    }
+}
+
+/// Converts path of a file inside `root` to name of VFS entry.
+fn vfs_entry_name(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts = relative.components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(parts.join("/"))
+}
+
+/// Possible layouts of compression in VirtualFileSystem archive.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VfsCompression {
+    /// Neither the archive nor the entries are compressed.
+    None,
+    /// The whole payload is compressed, `compress(vfs(files))`.
+    Archive,
+    /// Each entry is compressed separately, `vfs(compress(file))`.
+    Entries,
+}
+
+impl TryFrom<&str> for VfsCompression {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "none" => Ok(VfsCompression::None),
+            "archive" => Ok(VfsCompression::Archive),
+            "entries" => Ok(VfsCompression::Entries),
+            _ => Err(())
+        }
+    }
+}
+
+/// Decompresses data of VFS entry stored in archive with compressed entries.
+/// The size prefix of the entry is checked first, so corrupted entries cannot
+/// cause huge allocations.
+pub fn decompress_vfs_entry(data: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    if data.len() < 4 {
+        return Err(invalid(format!("entry of {} bytes has no size prefix", data.len())));
+    }
+    let (prefix, data) = data.split_at(4);
+    let size = i32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+
+    // lz4 cannot expand data more than 255 times
+    if size <= 0 || size as u64 > (data.len() as u64).saturating_mul(255) {
+        return Err(invalid(format!("{} bytes cannot decompress to {} bytes", data.len(), size)));
+    }
+    lz4::block::decompress(data, Some(size))
+}
+
+/// Packs all files inside `root` directory into uncompressed VFS archive
+/// saved as `output`.
+pub fn pack_directory(root: &Path, output: &Path) -> io::Result<()> {
+    let mut builder = BfVfsBuilder::new();
+    builder.add_directory(root)?;
+    builder.save(output, VfsCompression::None).map(|_| ())
+}
+
+/// Errors that can occur while loading BF files and their payloads.
+#[derive(Debug)]
+pub enum Error {
+    /// Input is shorter than the structure that is being read.
+    NotEnoughData { expected: usize, actual: usize },
+    /// Input is not aligned for zero-copy access of the structure that is being read.
+    Unaligned,
+    InvalidFileSignature(u16),
+    VersionTooHigh(u8),
+    InvalidKindValue(u8),
+    KindMismatch { expected: u8, actual: u8 },
+    InvalidGeometryListType(u16),
+    InvalidVfsEntryName,
+    DecompressionFailed(String),
+    PayloadSizeMismatch { expected: u64, actual: u64 },
+    InvalidImageFormat(u8),
+    /// Neither the image format nor any of its fallback formats can be sampled by the device.
+    UnsupportedImageFormat(u8),
+    InvalidImageDimensions { width: u16, height: u16 },
+    /// Sizes of the mip-maps computed from image dimensions do not add up to the payload length.
+    InvalidMipChain { expected: usize, actual: usize },
+    /// Offset in the table of chunks is not zero for the first chunk or is smaller than
+    /// the offset of the previous chunk.
+    InvalidChunkOffset { level: usize, offset: usize },
+    InvalidCodec(u8),
+    /// Checksum of the stored payload does not match the checksum in header.
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotEnoughData { expected, actual } => write!(f, "not enough data: expected at least {} bytes, got {}", expected, actual),
+            Error::Unaligned => write!(f, "data is not properly aligned"),
+            Error::InvalidFileSignature(magic) => write!(f, "invalid file signature: {:#06x}", magic),
+            Error::VersionTooHigh(version) => write!(f, "version {} is higher than supported version {}", version, BF_MAX_SUPPORTED_VERSION),
+            Error::InvalidKindValue(kind) => write!(f, "invalid kind value: {}", kind),
+            Error::KindMismatch { expected, actual } => write!(f, "unexpected kind: expected {}, got {}", expected, actual),
+            Error::InvalidGeometryListType(kind) => write!(f, "invalid geometry list type: {}", kind),
+            Error::InvalidVfsEntryName => write!(f, "vfs entry name is not valid utf8"),
+            Error::DecompressionFailed(e) => write!(f, "payload decompression failed: {}", e),
+            Error::PayloadSizeMismatch { expected, actual } => write!(f, "payload size mismatch: expected {} bytes, got {}", expected, actual),
+            Error::InvalidImageFormat(format) => write!(f, "invalid image format: {}", format),
+            Error::UnsupportedImageFormat(format) => write!(f, "image format {} cannot be sampled by the device", format),
+            Error::InvalidImageDimensions { width, height } => write!(f, "invalid image dimensions: {}x{}", width, height),
+            Error::InvalidMipChain { expected, actual } => write!(f, "mip-map chain needs {} bytes, payload has {}", expected, actual),
+            Error::InvalidChunkOffset { level, offset } => write!(f, "chunk {} starts at invalid offset {}", level, offset),
+            Error::InvalidCodec(codec) => write!(f, "invalid codec: {}", codec),
+            Error::ChecksumMismatch { expected, actual } => write!(f, "checksum mismatch: expected {:#010x}, got {:#010x}", expected, actual),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Zero-copy structure read from the start of the slice with the rest of the slice.
+type Prefix<'a, T> = (LayoutVerified<&'a [u8], T>, &'a [u8]);
+
+/// Reads the structure `T` from the start of `bytes` returning the rest of the
+/// bytes or Error describing why the structure could not be read.
+fn read_prefix<T: FromBytes>(bytes: &[u8]) -> Result<Prefix<'_, T>, Error> {
+    let expected = std::mem::size_of::<T>();
+    if bytes.len() < expected {
+        return Err(Error::NotEnoughData { expected, actual: bytes.len() });
+    }
+    LayoutVerified::new_from_prefix(bytes).ok_or(Error::Unaligned)
+}
+
+/// Loads and deserializes byte array to BfFile using zero-copy mechanism. If
+/// the specified byte sequence is invalid Error is returned.
+pub fn load_bf_from_bytes(bytes: &[u8]) -> Result<BfFile<'_>, Error> {
+    let header_size = std::mem::size_of::<BfHeader>();
+    if bytes.len() < 2 {
+        return Err(Error::NotEnoughData { expected: header_size, actual: bytes.len() });
+    }
+
+    // verify magic, version and kind values (as far as the data goes)
+    let magic = LittleEndian::read_u16(bytes);
+    if magic != BF_MAGIC { return Err(Error::InvalidFileSignature(magic)); }
+    if let Some(&kind) = bytes.get(2) {
+        if Kind::try_from(kind).is_err() { return Err(Error::InvalidKindValue(kind)); }
+    }
+    if let Some(&version) = bytes.get(3) {
+        if version > BF_MAX_SUPPORTED_VERSION { return Err(Error::VersionTooHigh(version)); }
+    }
+
+    // transmute the slice
+    let (header, data): Prefix<BfHeader> = read_prefix(bytes)?;
+    if header.version < BF_CODEC_VERSION {
+        return Ok(BfFile { header, extension: None, data });
+    }
+
+    let (extension, data): Prefix<BfHeaderExtension> = read_prefix(data)?;
+    if Codec::try_from(extension.codec).is_err() {
+        return Err(Error::InvalidCodec(extension.codec));
+    }
+    Ok(BfFile { header, extension: Some(extension), data })
+}
+
+/// Same as `load_bf_from_bytes` but additionally verifies the checksum of
+/// the payload (if the file contains one).
+pub fn load_bf_from_bytes_verified(bytes: &[u8]) -> Result<BfFile<'_>, Error> {
+    let file = load_bf_from_bytes(bytes)?;
+    file.verify_checksum()?;
+    Ok(file)
+}
+
+/// Loads and deserializes decompressed payload of Geometry kind using zero-copy
+/// mechanism. If the payload is truncated, unaligned or contains unknown list
+/// types Error is returned.
+pub fn load_geometry_from_bytes(bytes: &[u8]) -> Result<BfGeometry<'_>, Error> {
+    let (header, rest) = read_prefix::<BfGeometryHeader>(bytes)?;
+
+    let not_enough = |expected: Option<usize>, actual: usize| Error::NotEnoughData {
+        expected: expected.unwrap_or(usize::MAX),
+        actual,
+    };
+
+    let header_bytes = (header.lists as usize)
+        .checked_mul(std::mem::size_of::<BfGeometryListHeader>());
+    let (list_headers, mut data) = match header_bytes {
+        Some(n) if n <= rest.len() => rest.split_at(n),
+        _ => return Err(not_enough(header_bytes, rest.len())),
+    };
+    let list_headers = LayoutVerified::<_, [BfGeometryListHeader]>::new_slice(list_headers)
+        .ok_or(Error::Unaligned)?
+        .into_slice();
+
+    let mut lists = Vec::with_capacity(list_headers.len());
+    for list_header in list_headers {
+        let kind = GeometryListType::try_from(list_header.kind)
+            .map_err(|_| Error::InvalidGeometryListType(list_header.kind))?;
+        let size = (list_header.length as usize).checked_mul(kind.element_size());
+        let size = match size {
+            Some(n) if n <= data.len() => n,
+            _ => return Err(not_enough(size, data.len())),
+        };
+
+        lists.push(GeometryList {
+            kind,
+            flags: list_header.flags,
+            length: list_header.length as usize,
+            data: &data[..size],
+        });
+
+        let padded = size.div_ceil(GEOMETRY_LIST_ALIGNMENT) * GEOMETRY_LIST_ALIGNMENT;
+        data = &data[padded.min(data.len())..];
+    }
+
+    Ok(BfGeometry { header, lists })
+}
+
+/// Loads and deserializes decompressed payload of VirtualFileSystem kind. Entry
+/// data is not copied. If the entry table is truncated or any entry points
+/// outside of the payload Error is returned.
+pub fn load_vfs_from_bytes(bytes: &[u8]) -> Result<BfVfs<'_>, Error> {
+    fn read_u32(bytes: &[u8], at: &mut usize) -> Result<u32, Error> {
+        let end = *at + 4;
+        if end > bytes.len() { return Err(Error::NotEnoughData { expected: end, actual: bytes.len() }); }
+        let value = LittleEndian::read_u32(&bytes[*at..end]);
+        *at = end;
+        Ok(value)
+    }
+
+    let mut at = 0;
+    let count = read_u32(bytes, &mut at)?;
+
+    // first pass: entry table, offsets are relative to the data part
+    let mut raw = Vec::new();
+    for _ in 0..count {
+        let terminator = bytes[at..].iter()
+            .position(|x| *x == 0)
+            .ok_or(Error::NotEnoughData { expected: bytes.len() + 1, actual: bytes.len() })?;
+        let name = std::str::from_utf8(&bytes[at..at + terminator])
+            .map_err(|_| Error::InvalidVfsEntryName)?;
+        at += terminator + 1;
+
+        let length = read_u32(bytes, &mut at)? as usize;
+        let offset = read_u32(bytes, &mut at)? as usize;
+        raw.push((name, offset, length));
+    }
+
+    // second pass: resolve the entries against the data part
+    let data_start = at;
+    let mut entries = Vec::with_capacity(raw.len());
+    for (name, offset, length) in raw {
+        let range = data_start.checked_add(offset)
+            .and_then(|start| start.checked_add(length).map(|end| start..end));
+        match range {
+            Some(r) if r.end <= bytes.len() => entries.push(VfsEntry { name, offset: r.start, data: &bytes[r] }),
+            _ => return Err(Error::NotEnoughData {
+                expected: range.map(|r| r.end).unwrap_or(usize::MAX),
+                actual: bytes.len(),
+            }),
+        }
+    }
+
+    Ok(BfVfs { entries })
+}
+
+/// Result of one structural check performed by `validate`.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    /// Description of the problem if the check failed.
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn new<E: std::fmt::Display>(name: &'static str, result: Result<(), E>) -> Self {
+        Check { name, error: result.err().map(|e| e.to_string()) }
+    }
+
+    #[inline]
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Performs structural checks of the file and its payload: the checksum, size
+/// of the decoded payload and the kind specific structure (mip-map chain of
+/// images, lists and indices of geometries, entries of VFS archives). Checks of
+/// the payload structure are skipped when the payload cannot be decoded.
+pub fn validate(file: &BfFile) -> Vec<Check> {
+    let mut checks = vec![Check::new("checksum", file.verify_checksum())];
+
+    let payload = match file.payload() {
+        Ok(payload) => payload,
+        Err(e) => {
+            checks.push(Check::new("payload", Err(e)));
+            return checks;
+        }
+    };
+    checks.push(Check::new::<Error>("payload", Ok(())));
+
+    match Kind::try_from(file.header.kind) {
+        Ok(Image) => checks.push(Check::new("mip_chain", BfImage::new(file).map(|_| ()))),
+        Ok(Geometry) => validate_geometry(&payload, &mut checks),
+        Ok(VirtualFileSystem) => validate_vfs(&payload, BfVfsAdditional::from_u64(file.header.additional), &mut checks),
+        _ => {}
+    }
+    checks
+}
+
+fn validate_geometry(payload: &[u8], checks: &mut Vec<Check>) {
+    let geometry = match load_geometry_from_bytes(payload) {
+        Ok(geometry) => geometry,
+        Err(e) => return checks.push(Check::new("geometry_lists", Err(e))),
+    };
+    checks.push(Check::new::<Error>("geometry_lists", Ok(())));
+
+    // all vertex attributes must have one element per vertex
+    let vertices = geometry.positions().map(|p| p.len());
+    let mismatch = geometry.lists()
+        .iter()
+        .filter(|l| !l.kind().is_indices())
+        .find(|l| Some(l.len()) != vertices);
+    checks.push(Check::new("geometry_attributes", match mismatch {
+        Some(list) => Err(format!("list {:?} has {} elements, positions have {}", list.kind(), list.len(), vertices.unwrap_or(0))),
+        None => Ok(()),
+    }));
+
+    let vertices = vertices.unwrap_or(0);
+    let mut result = Ok(());
+    for list in geometry.lists().iter().filter(|l| l.kind().is_indices()) {
+        let indices = list.as_indices().unwrap();
+        if let Some(index) = (0..indices.len()).filter_map(|i| indices.get(i)).find(|i| *i as usize >= vertices) {
+            result = Err(format!("list {:?} contains index {} but there are only {} vertices", list.kind(), index, vertices));
+            break;
+        }
+    }
+    checks.push(Check::new("geometry_indices", result));
+}
+
+fn validate_vfs(payload: &[u8], additional: BfVfsAdditional, checks: &mut Vec<Check>) {
+    let vfs = match load_vfs_from_bytes(payload) {
+        Ok(vfs) => vfs,
+        Err(e) => return checks.push(Check::new("vfs_entries", Err(e))),
+    };
+    checks.push(Check::new::<Error>("vfs_entries", Ok(())));
+
+    // entries sorted by their offset must not overlap
+    let mut entries: Vec<&VfsEntry> = vfs.entries().iter().filter(|e| !e.data().is_empty()).collect();
+    entries.sort_by_key(|e| e.offset());
+    let overlap = entries.windows(2).find(|w| w[0].offset() + w[0].data().len() > w[1].offset());
+    checks.push(Check::new("vfs_overlap", match overlap {
+        Some(w) => Err(format!("entry {} overlaps entry {}", w[0].name(), w[1].name())),
+        None => Ok(()),
+    }));
+
+    if additional.compressed_entries {
+        let invalid = vfs.entries().iter().find(|e| decompress_vfs_entry(e.data()).is_err());
+        checks.push(Check::new("vfs_compressed_entries", match invalid {
+            Some(entry) => Err(format!("entry {} cannot be decompressed", entry.name())),
+            None => Ok(()),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use zerocopy::AsBytes;
+    use crate::bf::{BfHeader, Kind, load_bf_from_bytes, Error, BF_MAX_SUPPORTED_VERSION, BF_MAGIC, BfImageAdditional, BfGeometryBuilder, GeometryListType, load_geometry_from_bytes, Indices, BfVfsBuilder, load_vfs_from_bytes, BfWriter, Compression, BfImage, BfImageFormat, mip_dimensions, load_bf_from_bytes_verified, BfImageChunksBuilder, read_image_chunks, ImageDimension, Codec, BfHeaderExtension, AdditionalData, BfVfsAdditional, BfGeometryAdditional, ColorSpace::{Linear, Srgb}};
+    use crate::bf::BfImageFormat::{Bc4, Bc5, Bc6h, Bc7, SrgbBc7, Rgba16f, Rgb8, Rgba8, Srgb8A8, Dxt1, Dxt5};
+    use crate::bf::{decode_rgba8, decompress_vfs_entry, validate, PixelDiff, BF_CHECKSUM_VERSION};
+    use std::borrow::Cow;
+    use std::convert::TryFrom;
+    use std::io::Write;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_load_bf_from_bytes() {
+        let header = BfHeader {
+            magic: BF_MAGIC,
+            kind: Kind::CompiledShader as u8,
+            version: 1,
+            checksum: 0,
+            additional: 66,
+            uncompressed: 1024,
+            compressed: 1023,
+        };
+        let data = &[1, 2, 3, 4, 1, 2, 3, 4];
+
+        let mut bytes = Vec::new();
+        bytes.extend(header.as_bytes());
+        bytes.extend(data.as_bytes());
+
+        // load from bytes
+        let result = load_bf_from_bytes(&bytes);
+
+        assert!(result.is_ok());
+
+        let file = result.ok().unwrap();
+
+        assert_eq!(file.header.magic, header.magic);
+        assert_eq!(file.header.kind, header.kind);
+        assert_eq!(file.header.version, header.version);
+        assert_eq!(file.header.checksum, header.checksum);
+        assert_eq!(file.header.uncompressed, header.uncompressed);
+        assert_eq!(file.header.compressed, header.compressed);
+        assert_eq!(file.header.additional, header.additional);
+        assert_eq!(file.data, data);
+    }
+
+    #[test]
+    fn test_invalid_header_variants() {
+        assert_matches!(load_bf_from_bytes(&[0, 0, 0]), Err(Error::InvalidFileSignature(0)));
+        assert_matches!(load_bf_from_bytes(&[66, 70, 255]), Err(Error::InvalidKindValue(255)));
+        assert_matches!(load_bf_from_bytes(&[66, 70, 1, BF_MAX_SUPPORTED_VERSION + 1]), Err(Error::VersionTooHigh(_)));
+        assert_matches!(load_bf_from_bytes(&[66, 70, 1, 1, 0, 1]), Err(Error::NotEnoughData { expected: 32, actual: 6 }));
+        assert_matches!(load_bf_from_bytes(&[]), Err(Error::NotEnoughData { .. }));
+        assert_matches!(load_bf_from_bytes(&[66]), Err(Error::NotEnoughData { .. }));
+        assert_matches!(load_bf_from_bytes(&[66, 70]), Err(Error::NotEnoughData { .. }));
+        assert_matches!(load_bf_from_bytes(&[66, 70, Kind::MaxValue as u8]), Err(Error::InvalidKindValue(_)));
+    }
+
+    #[test]
+    fn bf_image_additional_data() {
+        let a = BfImageAdditional::new(169, 444, 4);
+        let a_u64 = a.into_u64();
+
+        assert_eq!(a, BfImageAdditional::from_u64(a_u64));
+    }
+
+    #[test]
+    fn additional_data_byte_layout() {
+        let image = BfImageAdditional::new(0x0102, 0x0304, 0x05);
+        assert_eq!(image.into_u64().to_le_bytes(), [0x02, 0x01, 0x04, 0x03, 0x05, 0, 0x01, 0]);
+        assert_eq!(image.into_u64(), 0x0001_0005_0304_0102);
+        assert_eq!(BfImageAdditional::from_u64(0x0001_0005_0304_0102), image);
+        // older files do not store depth
+        assert_eq!(BfImageAdditional::from_u64(0x0000_0005_0304_0102), image);
+
+        let mut chunked = image.with_dimension(ImageDimension::D3, 0x0607);
+        chunked.chunked = true;
+        assert_eq!(chunked.into_u64().to_le_bytes(), [0x02, 0x01, 0x04, 0x03, 0x05, 0b111, 0x07, 0x06]);
+        assert_eq!(BfImageAdditional::from_u64(chunked.into_u64()), chunked);
+
+        for dimension in &[ImageDimension::D2, ImageDimension::Cube, ImageDimension::Array, ImageDimension::D3] {
+            let a = image.with_dimension(*dimension, 3);
+            assert_eq!(BfImageAdditional::from_u64(a.into_u64()), a);
+        }
+
+        let vfs = BfVfsAdditional { compressed_entries: true };
+        assert_eq!(vfs.into_u64().to_le_bytes(), [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(BfVfsAdditional::default().into_u64(), 0);
+        assert_eq!(BfVfsAdditional::from_u64(1), vfs);
+
+        assert_eq!(BfGeometryAdditional.into_u64(), 0);
+        assert_eq!(().into_u64(), 0);
+    }
+
+    #[test]
+    fn additional_data_in_file() {
+        let bytes = BfWriter::new(Kind::Image, BfImageAdditional::new(0x0102, 0x0304, 0x05).into_u64())
+            .compression(Compression::None)
+            .payload(vec![0; 3])
+            .into_bytes()
+            .unwrap();
+
+        assert_eq!(&bytes[8..16], &[0x02, 0x01, 0x04, 0x03, 0x05, 0, 0x01, 0]);
+    }
+
+    #[test]
+    fn geometry_roundtrip() {
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let normals = [[0.0f32, 0.0, 1.0]; 3];
+        let uvs = [[0.0f32, 0.0], [1.0, 0.0], [0.0, 1.0]];
+        let tangents = [[1.0f32, 0.0, 0.0, -1.0]; 3];
+        let indices = [0u32, 1, 2];
+
+        let payload = BfGeometryBuilder::new()
+            .list(GeometryListType::Positions, &positions)
+            .indices(&indices)
+            .list(GeometryListType::Normals, &normals)
+            .list(GeometryListType::UV1, &uvs)
+            .list(GeometryListType::SignedTangents, &tangents)
+            .build();
+
+        // u8 indices must not break alignment of the following lists
+        let geometry = load_geometry_from_bytes(&payload).ok().unwrap();
+
+        assert_eq!(geometry.header.lists, 5);
+        assert_eq!(geometry.lists().len(), 5);
+        assert_eq!(geometry.positions().unwrap(), &positions);
+        assert_eq!(geometry.normals().unwrap(), &normals);
+        assert_eq!(geometry.uvs().unwrap(), &uvs);
+        assert_eq!(geometry.signed_tangents().unwrap(), &tangents);
+        assert!(geometry.tangents().is_none());
+        assert_matches!(geometry.indices(), Some(Indices::U8(&[0, 1, 2])));
+        assert_eq!(geometry.bounds(), Some(([0.0, 0.0, 0.0], [1.0, 1.0, 0.0])));
+        assert_eq!(geometry.lod_count(), 1);
+    }
+
+    #[test]
+    fn geometry_lod_levels() {
+        let positions = [[0.0f32; 3]; 300];
+        let payload = BfGeometryBuilder::new()
+            .list(GeometryListType::Positions, &positions)
+            .indices(&[0, 1, 299, 0, 299, 2])
+            .lod_indices(1, &[0, 1, 2])
+            .build();
+        let geometry = load_geometry_from_bytes(&payload).ok().unwrap();
+
+        assert_eq!(geometry.lod_count(), 2);
+        assert_matches!(geometry.indices(), Some(Indices::U16(&[0, 1, 299, 0, 299, 2])));
+        assert_matches!(geometry.lod(0), Some(Indices::U16(&[0, 1, 299, 0, 299, 2])));
+        assert_matches!(geometry.lod(1), Some(Indices::U8(&[0, 1, 2])));
+        assert!(geometry.lod(2).is_none());
+    }
+
+    #[test]
+    fn geometry_uses_smallest_index_type() {
+        let payload = BfGeometryBuilder::new()
+            .indices(&[0, 300, 2])
+            .build();
+        let geometry = load_geometry_from_bytes(&payload).ok().unwrap();
+        assert_matches!(geometry.indices(), Some(Indices::U16(&[0, 300, 2])));
+
+        let payload = BfGeometryBuilder::new()
+            .indices(&[0, 70000, 2])
+            .build();
+        let geometry = load_geometry_from_bytes(&payload).ok().unwrap();
+        let indices = geometry.indices().unwrap();
+        assert_matches!(indices, Indices::U32(_));
+        assert_eq!(indices.get(1), Some(70000));
+        assert_eq!(indices.len(), 3);
+    }
+
+    #[test]
+    fn geometry_invalid_payloads() {
+        let payload = BfGeometryBuilder::new()
+            .list(GeometryListType::Positions, &[[1.0f32, 2.0, 3.0]; 4])
+            .build();
+
+        assert_matches!(load_geometry_from_bytes(&payload[..4]), Err(Error::NotEnoughData { .. }));
+        assert_matches!(load_geometry_from_bytes(&payload[..12]), Err(Error::NotEnoughData { .. }));
+        assert_matches!(load_geometry_from_bytes(&payload[..payload.len() - 4]), Err(Error::NotEnoughData { .. }));
+
+        let mut invalid = payload.clone();
+        invalid[8] = 42; // list type of the first list
+        assert_matches!(load_geometry_from_bytes(&invalid), Err(Error::InvalidGeometryListType(42)));
+    }
+
+    #[test]
+    fn vfs_roundtrip() {
+        let mut builder = BfVfsBuilder::new();
+        builder.add("textures/b.bf", vec![4, 5, 6, 7]);
+        builder.add("a.txt", vec![1, 2, 3]);
+        builder.add("empty", vec![]);
+        let payload = builder.build();
+
+        let vfs = load_vfs_from_bytes(&payload).ok().unwrap();
+
+        assert_eq!(vfs.entries().len(), 3);
+        assert_eq!(vfs.get("a.txt"), Some(&[1u8, 2, 3][..]));
+        assert_eq!(vfs.get("textures/b.bf"), Some(&[4u8, 5, 6, 7][..]));
+        assert_eq!(vfs.get("empty"), Some(&[][..]));
+        assert_eq!(vfs.get("textures"), None);
+
+        let entry = vfs.entry("textures/b.bf").unwrap();
+        assert_eq!(&payload[entry.offset()..entry.offset() + 4], &[4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn vfs_invalid_payloads() {
+        let mut builder = BfVfsBuilder::new();
+        builder.add("a.txt", vec![1, 2, 3]);
+        let payload = builder.build();
+
+        assert_matches!(load_vfs_from_bytes(&payload[..2]), Err(Error::NotEnoughData { .. }));
+        assert_matches!(load_vfs_from_bytes(&payload[..7]), Err(Error::NotEnoughData { .. }));
+        assert_matches!(load_vfs_from_bytes(&payload[..payload.len() - 1]), Err(Error::NotEnoughData { .. }));
+
+        let mut invalid = payload.clone();
+        invalid[4] = 0xFF; // first byte of the entry name
+        assert_matches!(load_vfs_from_bytes(&invalid), Err(Error::InvalidVfsEntryName));
+    }
+
+    fn file_bytes(header: BfHeader, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(header.as_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    #[test]
+    fn payload_uncompressed_is_borrowed() {
+        let data = [1u8, 2, 3, 4, 5];
+        let bytes = file_bytes(BfHeader::new(Kind::Audio, 1, 0, 5, 0), &data);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+
+        let payload = file.payload().ok().unwrap();
+        assert_matches!(payload, Cow::Borrowed(_));
+        assert_eq!(&payload[..], &data);
+    }
+
+    #[test]
+    fn payload_compressed_is_decompressed() {
+        let data = vec![7u8; 1000];
+        let compressed = lz4::block::compress(&data, None, false).unwrap();
+        let bytes = file_bytes(BfHeader::new(Kind::Audio, 1, 0, 1000, compressed.len() as u64), &compressed);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+
+        let payload = file.payload().ok().unwrap();
+        assert_matches!(payload, Cow::Owned(_));
+        assert_eq!(&payload[..], &data[..]);
+    }
+
+    #[test]
+    fn payload_size_mismatch() {
+        let bytes = file_bytes(BfHeader::new(Kind::Audio, 1, 0, 6, 0), &[1, 2, 3, 4, 5]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(file.payload(), Err(Error::PayloadSizeMismatch { expected: 6, actual: 5 }));
+
+        let data = vec![7u8; 1000];
+        let compressed = lz4::block::compress(&data, None, false).unwrap();
+        let bytes = file_bytes(BfHeader::new(Kind::Audio, 1, 0, 1001, compressed.len() as u64), &compressed);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(file.payload(), Err(Error::PayloadSizeMismatch { expected: 1001, actual: 1000 }));
+
+        let bytes = file_bytes(BfHeader::new(Kind::Audio, 1, 0, 1000, 3), &[0xFF, 0xFF, 0xFF]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(file.payload(), Err(Error::DecompressionFailed(_)));
+    }
+
+    #[test]
+    fn writer_compresses_payload() {
+        let bytes = BfWriter::new(Kind::Geometry, 42)
+            .compression(Compression::Lz4High(16))
+            .payload(vec![3u8; 4096])
+            .into_bytes()
+            .unwrap();
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+
+        assert_eq!(file.header.kind, Kind::Geometry as u8);
+        assert_eq!(file.header.additional, 42);
+        assert_eq!(file.header.uncompressed, 4096);
+        assert_eq!(file.header.compressed, file.data.len() as u64);
+        assert!(file.is_compressed());
+        assert_eq!(&file.payload().ok().unwrap()[..], &[3u8; 4096][..]);
+    }
+
+    #[test]
+    fn writer_stores_incompressible_payload() {
+        let mut writer = BfWriter::new(Kind::Audio, 0);
+        writer.write_all(&[1, 2, 3]).unwrap();
+        writer.write_all(&[4, 5]).unwrap();
+        let bytes = writer.into_bytes().unwrap();
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+
+        assert!(!file.is_compressed());
+        assert_eq!(file.header.stored_size(), 5);
+        assert_eq!(file.data, &[1, 2, 3, 4, 5]);
+
+        let bytes = BfWriter::new(Kind::Audio, 0)
+            .compression(Compression::None)
+            .payload(vec![0u8; 4096])
+            .into_bytes()
+            .unwrap();
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert!(!file.is_compressed());
+    }
+
+    #[test]
+    fn writer_finish_writes_file() {
+        let path = std::env::temp_dir().join("vk_test_writer_finish.bf");
+        let header = BfWriter::new(Kind::Material, 0)
+            .payload(vec![9u8; 100])
+            .finish(&path)
+            .unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_eq!(*file.header, header);
+        assert_eq!(&file.payload().ok().unwrap()[..], &[9u8; 100][..]);
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        assert!(!std::path::Path::new(&tmp).exists());
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn writer_codecs_roundtrip() {
+        let payload: Vec<u8> = (0..8192u32).map(|x| (x % 251) as u8 / 4).collect();
+        for (name, compression) in Compression::PRESETS.iter() {
+            let bytes = BfWriter::new(Kind::Audio, 0)
+                .compression(*compression)
+                .payload(payload.clone())
+                .into_bytes()
+                .unwrap();
+            let file = load_bf_from_bytes_verified(&bytes).ok().unwrap();
+
+            assert_eq!(file.codec(), compression.codec(), "{}", name);
+            assert_eq!(file.extension.as_ref().unwrap().codec, compression.codec() as u8);
+            assert_eq!(file.is_compressed(), *compression != Compression::None);
+            assert_eq!(&file.payload().ok().unwrap()[..], &payload[..], "{}", name);
+        }
+    }
+
+    #[test]
+    fn codec_of_old_versions() {
+        let compressed = lz4::block::compress(&[5u8; 1000], None, false).unwrap();
+        let bytes = file_bytes(BfHeader::new(Kind::Audio, 2, 0, 1000, compressed.len() as u64), &compressed);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert!(file.extension.is_none());
+        assert_eq!(file.codec(), Codec::Lz4);
+        assert_eq!(&file.payload().ok().unwrap()[..], &[5u8; 1000][..]);
+
+        let bytes = file_bytes(BfHeader::new(Kind::Audio, 1, 0, 3, 0), &[1, 2, 3]);
+        assert_eq!(load_bf_from_bytes(&bytes).ok().unwrap().codec(), Codec::None);
+    }
+
+    #[test]
+    fn invalid_codec() {
+        let mut bytes = BfWriter::new(Kind::Audio, 0).payload(vec![1, 2, 3]).into_bytes().unwrap();
+        bytes[32] = 200;
+        assert_matches!(load_bf_from_bytes(&bytes), Err(Error::InvalidCodec(200)));
+
+        // header extension is missing
+        assert_matches!(load_bf_from_bytes(&bytes[..36]), Err(Error::NotEnoughData { expected: 8, actual: 4 }));
+    }
+
+    #[test]
+    fn zstd_size_mismatch() {
+        let compressed = zstd::stream::encode_all(&[3u8; 1000][..], 3).unwrap();
+        let mut header = BfHeader::new(Kind::Audio, 3, 0, 999, compressed.len() as u64);
+        header.checksum = crc32fast::hash(&compressed);
+
+        let mut bytes = file_bytes(header, &[]);
+        bytes.extend_from_slice(BfHeaderExtension { codec: Codec::Zstd as u8, reserved: [0; 7] }.as_bytes());
+        bytes.extend_from_slice(&compressed);
+
+        let file = load_bf_from_bytes_verified(&bytes).ok().unwrap();
+        assert_matches!(file.payload(), Err(Error::PayloadSizeMismatch { expected: 999, actual: 1000 }));
+    }
+
+    #[test]
+    fn checksum_is_verified() {
+        for compression in &[Compression::None, Compression::Lz4] {
+            let mut bytes = BfWriter::new(Kind::Audio, 0)
+                .compression(*compression)
+                .payload(vec![7u8; 1024])
+                .into_bytes()
+                .unwrap();
+
+            {
+                let file = load_bf_from_bytes_verified(&bytes).ok().unwrap();
+                assert!(file.header.has_checksum());
+                assert_eq!(file.header.checksum, crc32fast::hash(file.data));
+            }
+
+            // corrupt the last byte of the payload
+            let last = bytes.len() - 1;
+            bytes[last] ^= 0xFF;
+            assert!(load_bf_from_bytes(&bytes).is_ok());
+            assert_matches!(load_bf_from_bytes_verified(&bytes), Err(Error::ChecksumMismatch { .. }));
+        }
+    }
+
+    #[test]
+    fn checksum_is_not_verified_in_old_versions() {
+        let bytes = file_bytes(BfHeader::new(Kind::Audio, 1, 0, 5, 0), &[1, 2, 3, 4, 5]);
+        let file = load_bf_from_bytes_verified(&bytes).ok().unwrap();
+
+        assert!(!file.header.has_checksum());
+        assert!(file.verify_checksum().is_ok());
+    }
+
+    fn image_bytes(width: u16, height: u16, format: u8, payload: Vec<u8>) -> Vec<u8> {
+        BfWriter::new(Kind::Image, BfImageAdditional::new(width, height, format).into_u64())
+            .payload(payload)
+            .into_bytes()
+            .unwrap()
+    }
+
+    #[test]
+    fn format_sizes() {
+        assert_eq!(BfImageFormat::Dxt1.size_of(8, 8), 32);
+        assert_eq!(BfImageFormat::Dxt1.size_of(2, 2), 8);
+        assert_eq!(BfImageFormat::Dxt1.size_of(1, 1), 8);
+        assert_eq!(BfImageFormat::Dxt5.size_of(1, 1), 16);
+        assert_eq!(BfImageFormat::Dxt5.size_of(6, 2), 32);
+        assert_eq!(BfImageFormat::Rgb8.size_of(3, 1), 9);
+        assert_eq!(BfImageFormat::Srgb8A8.size_of(2, 2), 16);
+        assert_eq!(BfImageFormat::Bc4.size_of(8, 8), 32);
+        assert_eq!(BfImageFormat::Bc5.size_of(8, 8), 64);
+        assert_eq!(BfImageFormat::Bc6h.size_of(1, 1), 16);
+        assert_eq!(BfImageFormat::SrgbBc7.size_of(6, 2), 32);
+        assert_eq!(BfImageFormat::Rgba16f.size_of(3, 1), 24);
+
+        assert_eq!(mip_dimensions(8, 2, 0), (8, 2));
+        assert_eq!(mip_dimensions(8, 2, 2), (2, 1));
+        assert_eq!(mip_dimensions(8, 2, 64), (1, 1));
+    }
+
+    #[test]
+    fn format_properties() {
+        let formats = [
+            ("bc4", Bc4, 1, Linear),
+            ("bc5", Bc5, 2, Linear),
+            ("bc6h", Bc6h, 3, Linear),
+            ("bc7", Bc7, 4, Linear),
+            ("srgb_bc7", SrgbBc7, 4, Srgb),
+            ("rgba16f", Rgba16f, 4, Linear),
+        ];
+
+        for (name, format, channels, color_space) in formats.iter() {
+            assert_eq!(BfImageFormat::try_from(*name), Ok(*format));
+            assert_eq!(BfImageFormat::try_from(*format as u8), Ok(*format));
+            assert_eq!(format.channels(), *channels);
+            assert_eq!(format.color_space(), *color_space);
+        }
+        assert_eq!(BfImageFormat::try_from(16u8), Err(()));
+    }
+
+    #[test]
+    fn image_mip_access() {
+        // 8x8 (32) + 4x4 (8) + 2x2 (8) + 1x1 (8)
+        let payload: Vec<u8> = (0..56).collect();
+        let bytes = image_bytes(8, 8, BfImageFormat::Dxt1 as u8, payload.clone());
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        let image = BfImage::new(&file).ok().unwrap();
+
+        assert_eq!(image.width(), 8);
+        assert_eq!(image.height(), 8);
+        assert_eq!(image.format(), BfImageFormat::Dxt1);
+        assert_eq!(image.mip_count(), 4);
+        assert_eq!(image.mip(0), Some((8, 8, &payload[0..32])));
+        assert_eq!(image.mip(1), Some((4, 4, &payload[32..40])));
+        assert_eq!(image.mip(2), Some((2, 2, &payload[40..48])));
+        assert_eq!(image.mip(3), Some((1, 1, &payload[48..56])));
+        assert_eq!(image.mip(4), None);
+    }
+
+    #[test]
+    fn image_partial_and_rectangular_chains() {
+        // 4x2 (32) + 2x1 (8) only, the chain does not need to end at 1x1
+        let bytes = image_bytes(4, 2, BfImageFormat::Rgba8 as u8, vec![0; 40]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        let image = BfImage::new(&file).ok().unwrap();
+
+        assert_eq!(image.mip_count(), 2);
+        assert_eq!(image.mip(1).map(|(w, h, d)| (w, h, d.len())), Some((2, 1, 8)));
+    }
+
+    #[test]
+    fn image_invalid_chains() {
+        let bytes = image_bytes(8, 8, BfImageFormat::Dxt1 as u8, vec![0; 33]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::InvalidMipChain { expected: 40, actual: 33 }));
+
+        let bytes = image_bytes(1, 1, BfImageFormat::Rgba8 as u8, vec![0; 8]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::InvalidMipChain { expected: 4, actual: 8 }));
+
+        let bytes = image_bytes(0, 4, BfImageFormat::Rgba8 as u8, vec![0; 8]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::InvalidImageDimensions { width: 0, height: 4 }));
+
+        let bytes = image_bytes(1, 1, 200, vec![0; 4]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::InvalidImageFormat(200)));
+    }
+
+    fn layered_image_bytes(additional: BfImageAdditional, payload: Vec<u8>) -> Vec<u8> {
+        BfWriter::new(Kind::Image, additional.into_u64())
+            .payload(payload)
+            .into_bytes()
+            .unwrap()
+    }
+
+    #[test]
+    fn decode_formats() {
+        let rgb = [255, 128, 0, 10, 20, 30];
+        assert_eq!(decode_rgba8(Rgb8, 2, 1, &rgb, Linear).ok().unwrap(), vec![255, 128, 0, 255, 10, 20, 30, 255]);
+        assert_eq!(decode_rgba8(Srgb8A8, 1, 1, &[128, 0, 255, 128], Linear).ok().unwrap(), vec![55, 0, 255, 128]);
+        assert_eq!(decode_rgba8(Rgba8, 1, 1, &[55, 0, 255, 128], Srgb).ok().unwrap(), vec![128, 0, 255, 128]);
+
+        // floats are clamped, alpha stays linear
+        let half = crate::bc::encode_rgba16f(&[0.5, 2.0, -1.0, 0.5]);
+        assert_eq!(decode_rgba8(Rgba16f, 1, 1, &half, Linear).ok().unwrap(), vec![128, 255, 0, 128]);
+        assert_eq!(decode_rgba8(Rgba16f, 1, 1, &half, Srgb).ok().unwrap(), vec![188, 255, 0, 128]);
+
+        // mip-maps smaller than one block
+        let block = [0xFF, 0xFF, 0, 0, 0, 0, 0, 0];
+        assert_eq!(decode_rgba8(Dxt1, 1, 2, &block, Linear).ok().unwrap(), vec![255, 255, 255, 255, 255, 255, 255, 255]);
+        assert_matches!(decode_rgba8(Dxt5, 4, 4, &block, Linear), Err(Error::NotEnoughData { expected: 16, actual: 8 }));
+    }
+
+    #[test]
+    fn pixel_diff() {
+        let a = [10, 20, 30, 255, 0, 0, 0, 255];
+        let b = [12, 20, 30, 0, 0, 0, 4, 0];
+
+        let same = PixelDiff::compare(&a, &a, 4);
+        assert_eq!(same.max_error, 0);
+        assert_eq!(same.psnr(), f64::INFINITY);
+
+        // alpha is not compared for three channels
+        let rgb = PixelDiff::compare(&a, &b, 3);
+        assert_eq!(rgb, PixelDiff { max_error: 4, mse: 20.0 / 6.0, samples: 6 });
+        assert!((rgb.psnr() - 42.902).abs() < 0.001);
+
+        let merged = PixelDiff::compare(&a[..4], &b[..4], 3).merge(PixelDiff::compare(&a[4..], &b[4..], 3));
+        assert_eq!(merged.max_error, rgb.max_error);
+        assert!((merged.mse - rgb.mse).abs() < 1e-9);
+    }
+
+    #[test]
+    fn image_cube_map() {
+        // 4x4 (6 * 64) + 2x2 (6 * 16) + 1x1 (6 * 4) of RGBA8
+        let additional = BfImageAdditional::new(4, 4, BfImageFormat::Rgba8 as u8).with_dimension(ImageDimension::Cube, 1);
+        let payload: Vec<u8> = (0..504u32).map(|x| x as u8).collect();
+        let bytes = layered_image_bytes(additional, payload.clone());
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        let image = BfImage::new(&file).ok().unwrap();
+
+        assert_eq!(image.dimension(), ImageDimension::Cube);
+        assert_eq!(image.layer_count(), 6);
+        assert_eq!(image.mip_count(), 3);
+        assert_eq!(image.mip(1).map(|(w, h, d)| (w, h, d.len())), Some((2, 2, 96)));
+        assert_eq!(image.slice_count(2), 6);
+        assert_eq!(image.slice(0, 5), Some(&payload[320..384]));
+        assert_eq!(image.slice(1, 0), Some(&payload[384..400]));
+        assert_eq!(image.slice(2, 5), Some(&payload[500..504]));
+        assert_eq!(image.slice(2, 6), None);
+
+        // faces of cube maps must be square
+        let additional = BfImageAdditional::new(4, 2, BfImageFormat::Rgba8 as u8).with_dimension(ImageDimension::Cube, 1);
+        let bytes = layered_image_bytes(additional, vec![0; 192]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::InvalidImageDimensions { width: 4, height: 2 }));
+    }
+
+    #[test]
+    fn image_array_and_volume() {
+        // array of 3 layers of 2x2 (3 * 16) + 1x1 (3 * 4)
+        let additional = BfImageAdditional::new(2, 2, BfImageFormat::Rgba8 as u8).with_dimension(ImageDimension::Array, 3);
+        let bytes = layered_image_bytes(additional, vec![0; 60]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        let image = BfImage::new(&file).ok().unwrap();
+        assert_eq!(image.layer_count(), 3);
+        assert_eq!(image.mip_count(), 2);
+        assert_eq!(image.slice_count(1), 3);
+
+        // volume of 2x2x4 (4 * 16), 1x1x2 (2 * 4), 1x1x1 (4), depth shrinks with mip-maps
+        let additional = BfImageAdditional::new(2, 2, BfImageFormat::Rgba8 as u8).with_dimension(ImageDimension::D3, 4);
+        assert_eq!(additional.mip_extent(1), (1, 1, 2));
+        assert_eq!(additional.mip_size(BfImageFormat::Rgba8, 2), 4);
+
+        let bytes = layered_image_bytes(additional, vec![0; 76]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        let image = BfImage::new(&file).ok().unwrap();
+        assert_eq!(image.layer_count(), 1);
+        assert_eq!(image.mip_count(), 3);
+        assert_eq!(image.slice_count(0), 4);
+        assert_eq!(image.slice_count(1), 2);
+        assert_eq!(image.slice(1, 1).map(|s| s.len()), Some(4));
+
+        // 1x1x1 is the last mip-map
+        let bytes = layered_image_bytes(additional, vec![0; 80]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::InvalidMipChain { expected: 76, actual: 80 }));
+    }
+
+    #[test]
+    fn image_version1_file() {
+        let payload: Vec<u8> = (0..56).collect();
+        let additional = BfImageAdditional::new(8, 8, BfImageFormat::Dxt1 as u8).into_u64();
+        let bytes = file_bytes(BfHeader::new(Kind::Image, 1, additional, 56, 0), &payload);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        let image = BfImage::new(&file).ok().unwrap();
+
+        assert!(!image.additional().chunked);
+        assert_eq!(image.mip_count(), 4);
+        assert_eq!(image.mip(3), Some((1, 1, &payload[48..56])));
+    }
+
+    fn chunked_image_bytes(width: u16, height: u16, format: BfImageFormat, mips: &[Vec<u8>]) -> Vec<u8> {
+        let mut chunks = BfImageChunksBuilder::new(Compression::Lz4);
+        for mip in mips {
+            chunks.add_mip(mip).unwrap();
+        }
+
+        let mut additional = BfImageAdditional::new(width, height, format as u8);
+        additional.chunked = true;
+        BfWriter::new(Kind::Image, additional.into_u64())
+            .compression(Compression::None)
+            .payload(chunks.build())
+            .into_bytes()
+            .unwrap()
+    }
+
+    #[test]
+    fn image_chunked_mip_access() {
+        // 16x16, 8x8, 4x4, 2x2 and 1x1 of RGBA8, big mip-maps compress well
+        let mips: Vec<Vec<u8>> = (0..5).map(|l| vec![l as u8; 4 * (16 >> l) * (16 >> l)]).collect();
+        let bytes = chunked_image_bytes(16, 16, BfImageFormat::Rgba8, &mips);
+        let file = load_bf_from_bytes_verified(&bytes).ok().unwrap();
+
+        let chunks = read_image_chunks(file.data).ok().unwrap();
+        assert_eq!(chunks.codec, Codec::Lz4);
+        assert_eq!(chunks.offsets.len(), 6);
+        assert!(chunks.chunk(0).unwrap().len() < mips[0].len());
+        assert_eq!(chunks.chunk(4).unwrap(), &mips[4][..]);
+
+        let image = BfImage::new(&file).ok().unwrap();
+        assert_eq!(image.mip_count(), 5);
+        assert_eq!(image.base_level(), 0);
+        for (level, mip) in mips.iter().enumerate() {
+            assert_eq!(image.mip(level).unwrap().2, &mip[..]);
+        }
+
+        let image = BfImage::from_level(&file, 3).ok().unwrap();
+        assert_eq!(image.mip_count(), 5);
+        assert_eq!(image.base_level(), 3);
+        assert_eq!(image.mip(2), None);
+        assert_eq!(image.mip(3), Some((2, 2, &mips[3][..])));
+        assert_eq!(image.mip(4), Some((1, 1, &mips[4][..])));
+
+        assert_eq!(BfImage::from_level(&file, 100).ok().unwrap().base_level(), 4);
+    }
+
+    #[test]
+    fn image_chunked_invalid() {
+        let mips = vec![vec![0u8; 64], vec![0u8; 16]];
+        let mut bytes = chunked_image_bytes(4, 4, BfImageFormat::Rgba8, &mips);
+
+        // mip-map of wrong size
+        let bytes_small = chunked_image_bytes(4, 4, BfImageFormat::Rgba8, &[vec![0u8; 60]]);
+        let file = load_bf_from_bytes(&bytes_small).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::PayloadSizeMismatch { expected: 64, .. }));
+
+        // no mip-maps at all
+        let bytes_empty = chunked_image_bytes(4, 4, BfImageFormat::Rgba8, &[]);
+        let file = load_bf_from_bytes(&bytes_empty).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::InvalidMipChain { .. }));
+
+        // offsets out of order
+        let offsets = 40 + 8;
+        bytes[offsets + 4..offsets + 8].copy_from_slice(&[255, 255, 0, 0]);
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        assert_matches!(BfImage::new(&file), Err(Error::InvalidChunkOffset { level: 2, .. }));
+
+        // first offset is not zero
+        assert_matches!(read_image_chunks(&[1, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0, 8, 0, 0, 0]), Err(Error::InvalidChunkOffset { level: 0, offset: 4 }));
+
+        // table longer than the payload
+        assert_matches!(read_image_chunks(&[1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]), Err(Error::NotEnoughData { expected: 20, actual: 12 }));
+        let expected = 8 + (u32::MAX as usize + 1) * 4;
+        assert_matches!(read_image_chunks(&[1, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0]), Err(Error::NotEnoughData { expected: e, actual: 12 }) if e == expected);
+        assert_matches!(read_image_chunks(&[7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(Error::InvalidCodec(7)));
+    }
+
+    /// Runs the bytes through every parser. Used to check that no input can panic.
+    fn parse_everything(bytes: &[u8]) {
+        if let Ok(file) = load_bf_from_bytes(bytes) {
+            let _ = BfImage::new(&file);
+            let _ = BfImage::from_level(&file, 2);
+            let _ = validate(&file);
+            if let Ok(payload) = file.payload() {
+                let _ = read_image_chunks(&payload);
+                let _ = load_geometry_from_bytes(&payload);
+                let _ = load_vfs_from_bytes(&payload);
+            }
+        }
+        let _ = load_geometry_from_bytes(bytes);
+        let _ = load_vfs_from_bytes(bytes);
+    }
+
+    fn valid_files() -> Vec<Vec<u8>> {
+        let geometry = BfGeometryBuilder::new()
+            .list(GeometryListType::Positions, &[[1.0f32, 2.0, 3.0]; 48])
+            .indices(&(0..48).collect::<Vec<_>>())
+            .build();
+
+        let mut vfs = BfVfsBuilder::new();
+        vfs.add("a.txt", vec![1; 100]);
+        vfs.add("b/c.txt", vec![2; 50]);
+
+        vec![
+            BfWriter::new(Kind::Geometry, 0).compression(Compression::None).payload(geometry.clone()).into_bytes().unwrap(),
+            BfWriter::new(Kind::Geometry, 0).compression(Compression::Lz4High(16)).payload(geometry).into_bytes().unwrap(),
+            BfWriter::new(Kind::VirtualFileSystem, 0).payload(vfs.build()).into_bytes().unwrap(),
+            image_bytes(8, 8, BfImageFormat::Dxt5 as u8, vec![0; 112]),
+            image_bytes(8, 4, BfImageFormat::Rgb8 as u8, (0..=255).cycle().take(129).collect()),
+            chunked_image_bytes(4, 4, BfImageFormat::Rgba8, &[vec![0; 64], vec![1; 16], vec![2; 4]]),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn random_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            parse_everything(&bytes);
+        }
+
+        #[test]
+        fn random_files_with_valid_header_never_panic(
+            kind in 0u8..7,
+            version in 0u8..=BF_MAX_SUPPORTED_VERSION,
+            width in 0u16..64,
+            height in 0u16..64,
+            format in 0u8..17,
+            uncompressed in 0u64..4096,
+            compressed in prop_oneof![Just(0u64), 0u64..1024, any::<u64>()],
+            payload in prop::collection::vec(any::<u8>(), 0..1024),
+        ) {
+            let mut header = BfHeader::new(Kind::Image, version, BfImageAdditional::new(width, height, format).into_u64(), uncompressed, compressed);
+            header.kind = kind;
+            let bytes = file_bytes(header, &payload);
+
+            parse_everything(&bytes);
+        }
+
+        #[test]
+        fn vfs_entries_with_huge_size_are_rejected(
+            size in prop_oneof![Just(i32::MAX), Just(-1i32), 256i32..],
+            data in prop::collection::vec(any::<u8>(), 0..16),
+        ) {
+            let mut entry = size.to_le_bytes().to_vec();
+            entry.extend_from_slice(&data);
+            if size as i64 > data.len() as i64 * 255 || size <= 0 {
+                prop_assert!(decompress_vfs_entry(&entry).is_err());
+            } else {
+                let _ = decompress_vfs_entry(&entry);
+            }
+        }
+
+        #[test]
+        fn truncated_files_are_rejected(cut in 0usize..4096) {
+            for bytes in valid_files() {
+                let cut = cut % bytes.len();
+                let truncated = &bytes[..cut];
+
+                parse_everything(truncated);
+
+                let rejected = match load_bf_from_bytes(truncated) {
+                    Err(_) => true,
+                    Ok(file) => match file.payload() {
+                        Err(_) => true,
+                        Ok(payload) => match Kind::try_from(file.header.kind).unwrap() {
+                            Kind::Image => BfImage::new(&file).is_err(),
+                            Kind::Geometry => load_geometry_from_bytes(&payload).is_err(),
+                            Kind::VirtualFileSystem => load_vfs_from_bytes(&payload).is_err(),
+                            _ => false,
+                        },
+                    },
+                };
+                prop_assert!(rejected, "truncated file of {} bytes was accepted", cut);
+            }
+        }
+    }
+
+    #[test]
+    fn valid_files_are_accepted() {
+        for bytes in valid_files() {
+            let file = load_bf_from_bytes_verified(&bytes).ok().unwrap();
+            assert!(validate(&file).iter().all(|c| c.passed()), "{:?}", validate(&file));
+            let payload = file.payload().ok().unwrap();
+            match Kind::try_from(file.header.kind).unwrap() {
+                Kind::Image => assert!(BfImage::new(&file).is_ok()),
+                Kind::Geometry => assert!(load_geometry_from_bytes(&payload).is_ok()),
+                Kind::VirtualFileSystem => assert!(load_vfs_from_bytes(&payload).is_ok()),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn failed_checks(bytes: &[u8]) -> Vec<&'static str> {
+        let file = load_bf_from_bytes(bytes).ok().unwrap();
+        validate(&file).iter().filter(|c| !c.passed()).map(|c| c.name).collect()
+    }
+
+    #[test]
+    fn validate_finds_problems() {
+        assert_eq!(failed_checks(&image_bytes(8, 8, BfImageFormat::Dxt1 as u8, vec![0; 44])), vec!["mip_chain"]);
+        let mut bytes = image_bytes(8, 8, BfImageFormat::Dxt1 as u8, vec![0; 40]);
+        let last = bytes.len() - 1;
+        bytes[last] = 1;
+        assert_eq!(failed_checks(&bytes), vec!["checksum"]);
+
+        let mut header = BfHeader::new(Kind::Image, BF_CHECKSUM_VERSION, 0, 100, 10);
+        header.checksum = crc32fast::hash(&[0; 10]);
+        assert_eq!(failed_checks(&file_bytes(header, &[0; 10])), vec!["payload"]);
+
+        let geometry = BfGeometryBuilder::new()
+            .list(GeometryListType::Positions, &[[0.0f32; 3]; 3])
+            .list(GeometryListType::Normals, &[[0.0f32; 3]; 2])
+            .indices(&[0, 1, 3])
+            .build();
+        let bytes = BfWriter::new(Kind::Geometry, 0).payload(geometry).into_bytes().unwrap();
+        assert_eq!(failed_checks(&bytes), vec!["geometry_attributes", "geometry_indices"]);
+
+        // two entries sharing two bytes
+        let mut vfs = vec![2, 0, 0, 0];
+        vfs.extend_from_slice(b"a\0\x04\0\0\0\0\0\0\0b\0\x04\0\0\0\x02\0\0\0");
+        vfs.extend_from_slice(&[0; 6]);
+        let bytes = BfWriter::new(Kind::VirtualFileSystem, 0).payload(vfs).into_bytes().unwrap();
+        assert_eq!(failed_checks(&bytes), vec!["vfs_overlap"]);
+    }
+}
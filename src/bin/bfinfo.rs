@@ -1,101 +1,649 @@
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fs::File;
+use std::fs::create_dir_all;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
-use clap::{App, Arg};
-use image::{DynamicImage, ImageBuffer, ImageDecoder, ImageFormat};
-use image::dxt::{DXTDecoder, DXTVariant};
-use lz4::block::decompress;
+use clap::{App, AppSettings, Arg, SubCommand};
+use image::{DynamicImage, GenericImageView, ImageBuffer, ImageFormat, Luma};
+use serde_json::{Value, json};
 
-use vk_test::bf::{BfImageAdditional, BfImageFormat, Kind};
+use vk_test::bf::{AdditionalData, BfFile, BfImage, BfImageAdditional, BfImageFormat, BfVfsAdditional, ColorSpace, ImageDimension, Compression, Kind, PixelDiff, decode_rgba8, load_geometry_from_bytes, load_vfs_from_bytes, read_image_chunks, validate};
+use vk_test::perf::Stopwatch;
 
 fn main() {
     let matches = App::new("bfinfo")
         .version("1.0")
         .author("Matej K. <dobrakmato@gmail.com>")
         .about("Inspect various BF files")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("input")
             .short("i")
             .long("input")
             .value_name("INPUT_FILE")
-            .help("Path to the file to inspect")
+            .help("Path to the file to inspect or content root to scan for BF files")
             .required(true)
             .takes_value(true))
         .arg(Arg::with_name("dump")
             .short("d")
             .long("dump")
-            .help("Dump contents of the file"))
+            .help("Dump contents of the file (mip-maps, faces and layers of images as png files)"))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("OUTPUT_DIR")
+            .help("Directory to dump the contents of the file into")
+            .default_value(".")
+            .takes_value(true))
+        .arg(Arg::with_name("not-vflip")
+            .short("v")
+            .long("not-vflip")
+            .help("Do not vertically flip dumped images (img2bf flips images by default)"))
+        .arg(Arg::with_name("validate")
+            .long("validate")
+            .help("Check structure of the payload and exit with non-zero code if the file is invalid"))
+        .arg(Arg::with_name("codecs")
+            .short("c")
+            .long("codecs")
+            .help("Compare compression ratio of the payload with all available codecs"))
+        .arg(Arg::with_name("json")
+            .short("j")
+            .long("json")
+            .conflicts_with("dump")
+            .help("Print information about the file as one JSON document instead of key=value lines"))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Compares two BF files (header, decoded mip-maps of images, geometry statistics)")
+            .arg(Arg::with_name("a")
+                .value_name("FILE_A")
+                .help("Path to the first (old) file")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("b")
+                .value_name("FILE_B")
+                .help("Path to the second (new) file")
+                .required(true)
+                .index(2)))
         .get_matches();
 
-    let mut file = File::open(matches.value_of("input").unwrap())
-        .map_err(|e| panic!("cannot open input file: {}", e))
+    if let ("diff", Some(m)) = matches.subcommand() {
+        return diff(Path::new(m.value_of("a").unwrap()), Path::new(m.value_of("b").unwrap()));
+    }
+
+    let input = Path::new(matches.value_of("input").unwrap());
+    if input.is_dir() {
+        return scan(input, matches.is_present("json"));
+    }
+
+    let cnts = read_file(input);
+    let (file, kind) = load_file(&cnts);
+    let header = &file.header;
+
+    if matches.is_present("json") {
+        let report = json_report(&file, kind, matches.is_present("codecs"));
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+        // asset pipeline relies on the exit code
+        if matches.is_present("validate") && report["validation"]["valid"] == false {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    println!("magic={}", header.magic);
+    println!("version={}", header.version);
+    println!("kind={:?}", kind);
+
+    println!("additional={}", additional_string(kind, header.additional));
+
+    println!("uncompressed={}", header.uncompressed);
+    println!("compressed={}", header.compressed);
+    println!("codec={:?}", file.codec());
+
+    if header.has_checksum() {
+        match file.compute_checksum() {
+            Ok(actual) if actual == header.checksum => println!("checksum={:#010x} ok", header.checksum),
+            Ok(actual) => println!("checksum={:#010x} mismatch actual={:#010x}", header.checksum, actual),
+            Err(e) => println!("checksum={:#010x} error={}", header.checksum, e),
+        }
+    } else {
+        println!("checksum=none");
+    }
+
+    if matches.is_present("validate") {
+        let checks = validate(&file);
+        for check in checks.iter() {
+            match &check.error {
+                None => println!("check name={} ok", check.name),
+                Some(e) => println!("check name={} failed error={}", check.name, e),
+            }
+        }
+
+        // asset pipeline relies on the exit code
+        if checks.iter().any(|c| !c.passed()) {
+            std::process::exit(1);
+        }
+    }
+
+    if matches.is_present("codecs") {
+        let payload = file.payload()
+            .map_err(|e| panic!("cannot decode payload: {}", e))
+            .unwrap();
+
+        for (name, size, time) in compare_codecs(&payload) {
+            println!("codec name={} size={} ratio={} time={}ms", name, size, ratio(size, payload.len()), time);
+        }
+    }
+
+    if let Kind::Image = kind {
+        let image = BfImage::new(&file)
+            .map_err(|e| panic!("cannot decode image: {}", e))
+            .unwrap();
+
+        let payload = file.payload()
+            .map_err(|e| panic!("cannot decode payload: {}", e))
+            .unwrap();
+        let chunks = if image.additional().chunked {
+            let chunks = read_image_chunks(&payload)
+                .map_err(|e| panic!("cannot decode image chunks: {}", e))
+                .unwrap();
+            println!("layout=chunked codec={:?}", chunks.codec);
+            Some(chunks)
+        } else {
+            println!("layout=contiguous");
+            None
+        };
+
+        println!("dimension={:?} layers={}", image.dimension(), image.layer_count());
+
+        let dump_dir = PathBuf::from(matches.value_of("output").unwrap());
+        let vflip = !matches.is_present("not-vflip");
+        if matches.is_present("dump") {
+            create_dir_all(&dump_dir)
+                .map_err(|e| panic!("cannot create output directory: {}", e))
+                .unwrap();
+        }
+
+        for level in 0..image.mip_count() {
+            let (width, height, mipmap) = image.mip(level).unwrap();
+            let depth = image.additional().mip_extent(level).2;
+            match chunks.as_ref().and_then(|c| c.chunk(level)) {
+                Some(chunk) => println!("mipmap level={} width={} height={} depth={} size={} stored={}", level, width, height, depth, mipmap.len(), chunk.len()),
+                None => println!("mipmap level={} width={} height={} depth={} size={}", level, width, height, depth, mipmap.len()),
+            }
+
+            // slices are ordered by layer (face) and then by depth
+            let slices = image.slice_count(level);
+            for index in 0..slices {
+                let slice = image.slice(level, index).unwrap();
+                if slices > 1 {
+                    println!("slice level={} index={} layer={} z={} size={}", level, index, index / depth, index % depth, slice.len());
+                }
+
+                if matches.is_present("dump") {
+                    let mut name = format!("dump_mipmap{}", level);
+                    if image.layer_count() > 1 {
+                        name.push_str(&format!("_layer{}", index / depth));
+                    }
+                    if depth > 1 {
+                        name.push_str(&format!("_z{}", index % depth));
+                    }
+
+                    let path = dump_dir.join(name + ".png");
+                    dump_slice(image.format(), width, height, slice, vflip, &path);
+                }
+            }
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Vec<u8> {
+    let mut file = File::open(path)
+        .map_err(|e| panic!("cannot open input file {}: {}", path.display(), e))
         .unwrap();
 
     let mut cnts = vec![];
     file.read_to_end(&mut cnts)
         .expect("read error");
+    cnts
+}
 
-    let file = vk_test::bf::load_bf_from_bytes(&cnts)
-        .map_err(|e| panic!("cannot decode input file: {:?}", e))
+fn load_file(cnts: &[u8]) -> (BfFile<'_>, Kind) {
+    let file = vk_test::bf::load_bf_from_bytes(cnts)
+        .map_err(|e| panic!("cannot decode input file: {}", e))
         .unwrap();
-    let header = file.header;
-    let payload = file.data;
 
-    let kind = Kind::try_from(header.kind)
-        .map_err(|_| panic!("invalid kind value: {}", header.kind))
+    let kind = Kind::try_from(file.header.kind)
+        .map_err(|_| panic!("invalid kind value: {}", file.header.kind))
         .unwrap();
+    (file, kind)
+}
 
-
-    println!("magic={}", header.magic);
-    println!("version={}", header.version);
-    println!("kind={:?}", kind);
-
+fn additional_string(kind: Kind, additional: u64) -> String {
     match kind {
-        Kind::Image => println!("additional={:?}", BfImageAdditional::from_u64(header.additional)),
-        _ => println!("additional={}", header.additional)
+        Kind::Image => format!("{:?}", BfImageAdditional::from_u64(additional)),
+        Kind::VirtualFileSystem => format!("{:?}", BfVfsAdditional::from_u64(additional)),
+        _ => format!("{}", additional),
     }
+}
 
-    println!("uncompressed={}", header.uncompressed);
-    println!("compressed={}", header.compressed);
+/// Decodes one 2D slice of image and saves it as PNG file with the number of
+/// channels of the format. Two channel formats are saved as RGB with zero blue.
+fn dump_slice(format: BfImageFormat, width: usize, height: usize, data: &[u8], vflip: bool, path: &Path) {
+    // png files are stored in srgb, hdr formats are linear
+    let color_space = match format {
+        BfImageFormat::Bc6h | BfImageFormat::Rgba16f => ColorSpace::Srgb,
+        format => format.color_space(),
+    };
+    let raw = decode_rgba8(format, width, height, data, color_space)
+        .map_err(|e| panic!("cannot decode image data: {}", e))
+        .unwrap();
+    let mut img = ImageBuffer::from_raw(width as u32, height as u32, raw)
+        .map(DynamicImage::ImageRgba8)
+        .expect("cannot create image buffer from decoded data");
 
-    let uncompressed = decompress(payload, Some(header.uncompressed as i32))
-        .map_err(|e| panic!("payload decompression failed: {}", e))
+    img = match format.channels() {
+        1 => DynamicImage::ImageLuma8(ImageBuffer::from_fn(width as u32, height as u32, |x, y| Luma([img.get_pixel(x, y)[0]]))),
+        2 | 3 => DynamicImage::ImageRgb8(img.to_rgb()),
+        _ => img,
+    };
+
+    if vflip {
+        img = img.flipv();
+    }
+
+    img.save_with_format(path, ImageFormat::PNG)
+        .map_err(|e| panic!("cannot save dumped file: {}", e))
         .unwrap();
+}
+
+/// Compresses the payload with every codec preset and returns the name of the
+/// preset, size of the compressed payload and time it took in milliseconds.
+fn compare_codecs(payload: &[u8]) -> Vec<(&'static str, usize, u128)> {
+    Compression::PRESETS.iter()
+        .map(|(name, compression)| {
+            let mut timer = Stopwatch::new(name);
+            timer.start();
+            let size = compression.compress(payload)
+                .map_err(|e| panic!("cannot compress payload with {}: {}", name, e))
+                .unwrap()
+                .map_or(payload.len(), |c| c.len());
+            timer.end();
+            (*name, size, timer.total_time().as_millis())
+        })
+        .collect()
+}
+
+/// Returns size of the stored data as percentage of the raw size.
+fn ratio(stored: usize, raw: usize) -> f32 {
+    100.0 * stored as f32 / raw.max(1) as f32
+}
 
+/// Builds JSON document with the header, kind specific metadata and results of
+/// validation of the file. Metadata that cannot be decoded are replaced with
+/// `null` (the reason is reported by validation).
+fn json_report(file: &BfFile, kind: Kind, codecs: bool) -> Value {
+    let header = &file.header;
+    let checksum = if header.has_checksum() {
+        let actual = file.compute_checksum().ok();
+        json!({
+            "stored": header.checksum,
+            "actual": actual,
+            "ok": actual == Some(header.checksum),
+        })
+    } else {
+        Value::Null
+    };
+
+    let mut report = json!({
+        "header": {
+            "magic": header.magic,
+            "version": header.version,
+            "kind": format!("{:?}", kind),
+            "additional": header.additional,
+            "uncompressed": header.uncompressed,
+            "compressed": header.compressed,
+            "codec": format!("{:?}", file.codec()),
+            "checksum": checksum,
+        },
+        "stored": header.stored_size(),
+        "ratio": ratio(header.stored_size() as usize, header.uncompressed as usize),
+    });
+
+    let payload = file.payload().ok();
+    let metadata = payload.as_ref().and_then(|payload| match kind {
+        Kind::Image => json_image(file, payload),
+        Kind::Geometry => load_geometry_from_bytes(payload).ok().map(|geometry| json!({
+            "flags": geometry.header.flags,
+            "lists": geometry.lists().iter().map(|list| json!({
+                "kind": format!("{:?}", list.kind()),
+                "flags": list.flags(),
+                "length": list.len(),
+                "bytes": list.data().len(),
+            })).collect::<Vec<_>>(),
+        })),
+        Kind::VirtualFileSystem => load_vfs_from_bytes(payload).ok().map(|vfs| json!({
+            "compressed_entries": BfVfsAdditional::from_u64(header.additional).compressed_entries,
+            "entries": vfs.entries().iter().map(|entry| json!({
+                "name": entry.name(),
+                "offset": entry.offset(),
+                "size": entry.data().len(),
+            })).collect::<Vec<_>>(),
+        })),
+        _ => None,
+    });
     match kind {
-        Kind::Image => {
-            let additional = BfImageAdditional::from_u64(header.additional);
-            let mut level = 0;
-            let format = BfImageFormat::try_from(additional.format).expect("invalid format value");
-            let mut width = additional.width;
-            let mut height = additional.height;
-            let mut index = 0;
-            while index < uncompressed.len() {
-                let size = width as usize * height as usize * format.bits_per_pixel() / 8;
-                println!("mipmap level={} width={} height={} size={}", level, width, height, size);
-                let mipmap = &uncompressed[index..index + size];
+        Kind::Image => report["image"] = metadata.unwrap_or(Value::Null),
+        Kind::Geometry => report["geometry"] = metadata.unwrap_or(Value::Null),
+        Kind::VirtualFileSystem => report["vfs"] = metadata.unwrap_or(Value::Null),
+        _ => {}
+    }
 
-                if matches.is_present("dump") {
-                    let decoder = DXTDecoder::new(mipmap, width as u32, height as u32, DXTVariant::DXT1)
-                        .map_err(|e| panic!("cannot create dxt decoder: {}", e))
-                        .unwrap();
-                    let raw = decoder.read_image()
-                        .map_err(|e| panic!("cannot decode dxt data: {}", e))
-                        .unwrap();
-                    let img = ImageBuffer::from_raw(width as u32, height as u32, raw)
-                        .map(DynamicImage::ImageRgb8)
-                        .expect("cannot create image buffer from decoded data");
-                    img.save_with_format(format!("dump_mipmap{}.png", level), ImageFormat::PNG)
-                        .map_err(|e| panic!("cannot save dumped file: {}", e))
-                        .unwrap();
-                }
+    if let (true, Some(payload)) = (codecs, payload.as_ref()) {
+        report["codecs"] = compare_codecs(payload).into_iter()
+            .map(|(name, size, time)| json!({
+                "name": name,
+                "size": size,
+                "ratio": ratio(size, payload.len()),
+                "time_ms": time as u64,
+            }))
+            .collect();
+    }
+
+    let checks = validate(file);
+    report["validation"] = json!({
+        "valid": checks.iter().all(|c| c.passed()),
+        "checks": checks.iter().map(|check| json!({
+            "name": check.name,
+            "passed": check.passed(),
+            "error": check.error,
+        })).collect::<Vec<_>>(),
+    });
 
-                width /= 2;
-                height /= 2;
-                level += 1;
-                index += size;
+    report
+}
+
+fn json_image(file: &BfFile, payload: &[u8]) -> Option<Value> {
+    let image = BfImage::new(file).ok()?;
+    let chunks = if image.additional().chunked {
+        Some(read_image_chunks(payload).ok()?)
+    } else {
+        None
+    };
+
+    let mips = (0..image.mip_count()).map(|level| {
+        let (width, height, mipmap) = image.mip(level).unwrap();
+        let depth = image.additional().mip_extent(level).2;
+        let stored = chunks.as_ref().and_then(|c| c.chunk(level)).map_or(mipmap.len(), |c| c.len());
+        json!({
+            "level": level,
+            "width": width,
+            "height": height,
+            "depth": depth,
+            "size": mipmap.len(),
+            "stored": stored,
+        })
+    }).collect::<Vec<_>>();
+
+    Some(json!({
+        "width": image.width(),
+        "height": image.height(),
+        "depth": image.additional().depth,
+        "format": format!("{:?}", image.format()),
+        "color_space": format!("{:?}", image.format().color_space()),
+        "dimension": format!("{:?}", image.dimension()),
+        "layers": image.layer_count(),
+        "chunked": image.additional().chunked,
+        "chunk_codec": chunks.as_ref().map(|c| format!("{:?}", c.codec)),
+        "mips": mips,
+    }))
+}
+
+/// Summary of one BF file found while scanning a directory.
+struct ScanRow {
+    path: String,
+    kind: String,
+    format: String,
+    dimensions: String,
+    raw: u64,
+    stored: u64,
+}
+
+/// Recursively finds all `.bf` files in the `root` directory and prints table
+/// with their kind, format, dimensions and sizes followed by totals grouped by
+/// kind and format. Files that cannot be decoded are reported and skipped.
+fn scan(root: &Path, json: bool) {
+    let mut paths = vec![];
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .map_err(|e| panic!("cannot read directory {}: {}", dir.display(), e))
+            .unwrap();
+        for entry in entries {
+            let path = entry.expect("read error").path();
+            if path.is_dir() {
+                stack.push(path);
+            } else if path.extension().is_some_and(|e| e == "bf") {
+                paths.push(path);
             }
         }
-        _ => {}
     }
-}
\ No newline at end of file
+
+    let mut rows = vec![];
+    for path in paths {
+        let name = path.strip_prefix(root).unwrap_or(&path).display().to_string();
+        match scan_file(&path, name) {
+            Ok(row) => rows.push(row),
+            Err(e) => eprintln!("skipping {}: {}", path.display(), e),
+        }
+    }
+
+    // the biggest files are the interesting ones
+    rows.sort_by(|a, b| b.raw.cmp(&a.raw).then_with(|| a.path.cmp(&b.path)));
+
+    let mut totals: BTreeMap<(&str, &str), (usize, u64, u64)> = BTreeMap::new();
+    for row in rows.iter() {
+        let total = totals.entry((&row.kind, &row.format)).or_default();
+        total.0 += 1;
+        total.1 += row.raw;
+        total.2 += row.stored;
+    }
+    let raw = rows.iter().map(|r| r.raw).sum::<u64>();
+    let stored = rows.iter().map(|r| r.stored).sum::<u64>();
+
+    if json {
+        let report = json!({
+            "files": rows.iter().map(|row| json!({
+                "path": row.path,
+                "kind": row.kind,
+                "format": row.format,
+                "dimensions": row.dimensions,
+                "raw": row.raw,
+                "stored": row.stored,
+                "ratio": ratio(row.stored as usize, row.raw as usize),
+            })).collect::<Vec<_>>(),
+            "totals": totals.iter().map(|((kind, format), (count, raw, stored))| json!({
+                "kind": kind,
+                "format": format,
+                "count": count,
+                "raw": raw,
+                "stored": stored,
+                "ratio": ratio(*stored as usize, *raw as usize),
+            })).collect::<Vec<_>>(),
+            "total": {
+                "count": rows.len(),
+                "raw": raw,
+                "stored": stored,
+                "ratio": ratio(stored as usize, raw as usize),
+            },
+        });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return;
+    }
+
+    println!("{:<48} {:<18} {:<10} {:<20} {:>12} {:>12} {:>7}", "path", "kind", "format", "dimensions", "raw", "stored", "ratio");
+    for row in rows.iter() {
+        println!("{:<48} {:<18} {:<10} {:<20} {:>12} {:>12} {:>6.1}%", row.path, row.kind, row.format, row.dimensions, row.raw, row.stored, ratio(row.stored as usize, row.raw as usize));
+    }
+
+    println!();
+    println!("{:<18} {:<10} {:>6} {:>12} {:>12} {:>7}", "kind", "format", "files", "raw", "stored", "ratio");
+    for ((kind, format), (count, raw, stored)) in totals.iter() {
+        println!("{:<18} {:<10} {:>6} {:>12} {:>12} {:>6.1}%", kind, format, count, raw, stored, ratio(*stored as usize, *raw as usize));
+    }
+    println!("{:<18} {:<10} {:>6} {:>12} {:>12} {:>6.1}%", "total", "", rows.len(), raw, stored, ratio(stored as usize, raw as usize));
+}
+
+/// Reads kind, format, dimensions, raw (decoded) size and stored size of one
+/// file. Only chunked images need their payload to find the raw size, the rest
+/// is read from the header.
+fn scan_file(path: &Path, name: String) -> Result<ScanRow, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    let file = vk_test::bf::load_bf_from_bytes(&bytes).map_err(|e| e.to_string())?;
+    let header = &file.header;
+    let kind = Kind::try_from(header.kind).map_err(|_| format!("invalid kind value: {}", header.kind))?;
+
+    if let Kind::Image = kind {
+        let additional = BfImageAdditional::from_u64(header.additional);
+        let format = BfImageFormat::try_from(additional.format)
+            .map_err(|_| format!("invalid image format: {}", additional.format))?;
+        let dimensions = match additional.dimension {
+            ImageDimension::D2 => format!("{}x{}", additional.width, additional.height),
+            dimension => format!("{}x{}x{} {:?}", additional.width, additional.height, additional.depth, dimension),
+        };
+
+        let raw = if additional.chunked {
+            let payload = file.payload().map_err(|e| e.to_string())?;
+            let chunks = read_image_chunks(&payload).map_err(|e| e.to_string())?;
+            (0..chunks.offsets.len() - 1).map(|level| additional.mip_size(format, level) as u64).sum()
+        } else {
+            header.uncompressed
+        };
+        return Ok(ScanRow {
+            path: name,
+            kind: format!("{:?}", kind),
+            format: format!("{:?}", format),
+            dimensions,
+            raw,
+            stored: header.stored_size(),
+        });
+    }
+
+    Ok(ScanRow {
+        path: name,
+        kind: format!("{:?}", kind),
+        format: "-".to_string(),
+        dimensions: "-".to_string(),
+        raw: header.uncompressed,
+        stored: header.stored_size(),
+    })
+}
+
+/// Prints differences between two files: header fields, per mip-map error
+/// statistics of images and vertex, index and bounding box changes of geometries.
+fn diff(path_a: &Path, path_b: &Path) {
+    let (cnts_a, cnts_b) = (read_file(path_a), read_file(path_b));
+    let (a, kind_a) = load_file(&cnts_a);
+    let (b, kind_b) = load_file(&cnts_b);
+    let (ha, hb) = (&a.header, &b.header);
+
+    let fields = [
+        ("magic", ha.magic.to_string(), hb.magic.to_string()),
+        ("version", ha.version.to_string(), hb.version.to_string()),
+        ("kind", format!("{:?}", kind_a), format!("{:?}", kind_b)),
+        ("additional", additional_string(kind_a, ha.additional), additional_string(kind_b, hb.additional)),
+        ("uncompressed", ha.uncompressed.to_string(), hb.uncompressed.to_string()),
+        ("compressed", ha.compressed.to_string(), hb.compressed.to_string()),
+        ("codec", format!("{:?}", a.codec()), format!("{:?}", b.codec())),
+        ("checksum", format!("{:#010x}", ha.checksum), format!("{:#010x}", hb.checksum)),
+    ];
+    let mut identical = true;
+    for (name, value_a, value_b) in fields.iter().filter(|(_, x, y)| x != y) {
+        println!("header field={} a={} b={}", name, value_a, value_b);
+        identical = false;
+    }
+    if identical {
+        println!("header identical");
+    }
+    println!("stored a={} b={} ratio a={} b={}", ha.stored_size(), hb.stored_size(),
+             ratio(ha.stored_size() as usize, ha.uncompressed as usize),
+             ratio(hb.stored_size() as usize, hb.uncompressed as usize));
+
+    if kind_a != kind_b {
+        println!("kinds differ, payloads are not compared");
+        return;
+    }
+
+    let payload_a = a.payload()
+        .map_err(|e| panic!("cannot decode payload of {}: {}", path_a.display(), e))
+        .unwrap();
+    let payload_b = b.payload()
+        .map_err(|e| panic!("cannot decode payload of {}: {}", path_b.display(), e))
+        .unwrap();
+
+    match kind_a {
+        Kind::Image => diff_images(&a, &b),
+        Kind::Geometry => diff_geometry(&payload_a, &payload_b),
+        _ if payload_a == payload_b => println!("payload identical"),
+        _ => println!("payload differs"),
+    }
+}
+
+fn diff_images(a: &BfFile, b: &BfFile) {
+    let ia = BfImage::new(a)
+        .map_err(|e| panic!("cannot decode image: {}", e))
+        .unwrap();
+    let ib = BfImage::new(b)
+        .map_err(|e| panic!("cannot decode image: {}", e))
+        .unwrap();
+
+    if ia.mip_count() != ib.mip_count() {
+        println!("mipmaps a={} b={}", ia.mip_count(), ib.mip_count());
+    }
+
+    // channels missing in one of the formats are not compared
+    let channels = ia.format().channels().min(ib.format().channels());
+    for level in 0..ia.mip_count().min(ib.mip_count()) {
+        let (width, height, _) = ia.mip(level).unwrap();
+        let extent_b = ib.mip(level).map(|(w, h, _)| (w, h)).unwrap();
+        if (width, height) != extent_b || ia.slice_count(level) != ib.slice_count(level) {
+            println!("mipmap level={} differs a={}x{}x{} b={}x{}x{}", level, width, height, ia.slice_count(level),
+                     extent_b.0, extent_b.1, ib.slice_count(level));
+            continue;
+        }
+
+        // errors are measured on the values as they are displayed (in srgb)
+        let mut diff = PixelDiff::default();
+        for index in 0..ia.slice_count(level) {
+            let decode = |image: &BfImage, name: &str| decode_rgba8(image.format(), width, height, image.slice(level, index).unwrap(), ColorSpace::Srgb)
+                .map_err(|e| panic!("cannot decode image data of {}: {}", name, e))
+                .unwrap();
+            diff = diff.merge(PixelDiff::compare(&decode(&ia, "a"), &decode(&ib, "b"), channels));
+        }
+        println!("mipmap level={} width={} height={} max_error={} mse={:.3} psnr={:.2}", level, width, height, diff.max_error, diff.mse, diff.psnr());
+    }
+}
+
+fn diff_geometry(payload_a: &[u8], payload_b: &[u8]) {
+    let ga = load_geometry_from_bytes(payload_a)
+        .map_err(|e| panic!("cannot decode geometry: {}", e))
+        .unwrap();
+    let gb = load_geometry_from_bytes(payload_b)
+        .map_err(|e| panic!("cannot decode geometry: {}", e))
+        .unwrap();
+
+    let vertices = |g: &vk_test::bf::BfGeometry| g.positions().map_or(0, |p| p.len());
+    let indices = |g: &vk_test::bf::BfGeometry| g.indices().map_or(0, |i| i.len());
+    println!("vertices a={} b={} delta={}", vertices(&ga), vertices(&gb), vertices(&gb) as i64 - vertices(&ga) as i64);
+    println!("indices a={} b={} delta={}", indices(&ga), indices(&gb), indices(&gb) as i64 - indices(&ga) as i64);
+
+    let lists = |g: &vk_test::bf::BfGeometry| g.lists().iter().map(|l| format!("{:?}", l.kind())).collect::<Vec<_>>().join(",");
+    if lists(&ga) != lists(&gb) {
+        println!("lists a={} b={}", lists(&ga), lists(&gb));
+    }
+
+    match (ga.bounds(), gb.bounds()) {
+        (a, b) if a == b => println!("bounds identical {:?}", a),
+        (a, b) => println!("bounds a={:?} b={:?}", a, b),
+    }
+}
@@ -1,18 +1,17 @@
-use std::fs::File;
-use std::io::Write;
-use std::path::{Path, PathBuf};
-
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg};
 use image::{ColorType, FilterType, GenericImageView, DynamicImage};
 use image::dxt::{DXTEncoder, DXTVariant};
-use lz4::block::compress;
-use lz4::block::CompressionMode::HIGHCOMPRESSION;
-use zerocopy::AsBytes;
 
-use vk_test::bf::{BfHeader, BfImageFormat, Kind, BfImageAdditional};
+use image::hdr::HDRDecoder;
+
+use vk_test::bc::{encode_bc4, encode_bc5, encode_bc6h, encode_bc7, encode_rgba16f};
+use vk_test::bf::{BfImageFormat, Kind, BfImageAdditional, BfWriter, AdditionalData, BfImageChunksBuilder, Compression, srgb_to_linear};
 use vk_test::perf::Stopwatch;
 use std::convert::TryFrom;
-use vk_test::cli::derive_input_and_output;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use vk_test::cli::{codec_arg, derive_compression, derive_input_and_output};
 
 struct Timers<'a> {
     load: Stopwatch<'a>,
@@ -20,7 +19,6 @@ struct Timers<'a> {
     channels: Stopwatch<'a>,
     mipmaps: Stopwatch<'a>,
     dxt: Stopwatch<'a>,
-    lz4: Stopwatch<'a>,
     save: Stopwatch<'a>,
 }
 
@@ -32,7 +30,6 @@ impl<'a> Default for Timers<'a> {
             channels: Stopwatch::new("channels"),
             mipmaps: Stopwatch::new("mipmaps"),
             dxt: Stopwatch::new("dxt"),
-            lz4: Stopwatch::new("lz4"),
             save: Stopwatch::new("save"),
         }
     }
@@ -48,6 +45,82 @@ pub fn num_components(c: ColorType) -> usize {
     }
 }
 
+/// Floating point RGBA image used for the hdr output formats.
+struct HdrImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<f32>,
+}
+
+impl HdrImage {
+    /// Loads the image from Radiance HDR file or converts any other image
+    /// to linear floating point values in range 0 to 1.
+    fn open(path: &Path) -> HdrImage {
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("hdr")) {
+            let file = File::open(path)
+                .map_err(|e| panic!("cannot open input file: {}", e))
+                .unwrap();
+            let decoder = HDRDecoder::new(BufReader::new(file))
+                .map_err(|e| panic!("cannot load input file as hdr image: {}", e))
+                .unwrap();
+            let meta = decoder.metadata();
+            let pixels = decoder.read_image_hdr()
+                .map_err(|e| panic!("cannot decode hdr image: {}", e))
+                .unwrap()
+                .iter()
+                .flat_map(|p| vec![p[0], p[1], p[2], 1.0])
+                .collect();
+
+            HdrImage { width: meta.width, height: meta.height, pixels }
+        } else {
+            let img = image::open(path)
+                .map_err(|e| panic!("cannot load input file as image: {}", e))
+                .unwrap()
+                .to_rgba();
+
+            HdrImage {
+                width: img.width(),
+                height: img.height(),
+                // ldr images are stored in srgb, hdr formats are linear
+                pixels: img.into_raw()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| if i % 4 == 3 { *x as f32 / 255.0 } else { srgb_to_linear(*x as f32 / 255.0) })
+                    .collect(),
+            }
+        }
+    }
+
+    fn flipv(&self) -> HdrImage {
+        let row = self.width as usize * 4;
+        HdrImage {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.chunks(row).rev().flatten().copied().collect(),
+        }
+    }
+
+    /// Returns image with half the dimensions using 2x2 box filter.
+    fn downsample(&self) -> HdrImage {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..4 {
+                    let mut sum = 0.0;
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                        let sx = (x * 2 + dx).min(self.width - 1) as usize;
+                        let sy = (y * 2 + dy).min(self.height - 1) as usize;
+                        sum += self.pixels[(sy * self.width as usize + sx) * 4 + c];
+                    }
+                    pixels.push(sum / 4.0);
+                }
+            }
+        }
+        HdrImage { width, height, pixels }
+    }
+}
+
 fn main() {
     let mut timers = Timers::default();
 
@@ -77,17 +150,69 @@ fn main() {
             .short("f")
             .long("format")
             .value_name("FORMAT")
-            .help("One of: DXT1, DXT3, DXT5, RGB8, RGBA8") // todo: generate variants from enum
+            .help("One of: dxt1, dxt3, dxt5, rgb, rgba, srgb_dxt1, srgb_dxt3, srgb_dxt5, srgb, srgb_a, bc4, bc5, bc6h, bc7, srgb_bc7, rgba16f") // todo: generate variants from enum
             .required(true)
             .takes_value(true))
         .arg(Arg::with_name("not-vflip")
             .short("v")
             .long("not-vflip")
             .help("Do not vertically flip image during conversion"))
+        .arg(codec_arg())
+        .arg(Arg::with_name("chunked")
+            .long("chunked")
+            .help("Compress each mip-map separately so they can be loaded independently"))
         .get_matches();
 
     let (input, output) = derive_input_and_output(&matches);
+    let compression = derive_compression(&matches);
+    let output_format = BfImageFormat::try_from(matches.value_of("format").unwrap())
+        .expect("invalid output format specified");
+
+    // hdr formats are processed as floats in separate pipeline
+    let mips = match output_format {
+        BfImageFormat::Bc6h | BfImageFormat::Rgba16f => convert_hdr(&input, output_format, !matches.is_present("not-vflip"), &mut timers),
+        _ => convert(&input, output_format, !matches.is_present("not-vflip"), &mut timers),
+    };
+
+    // 6. compress with selected codec and write file_out
+    timers.save.start();
+    let (width, height) = mips[0].0;
+    let mips: Vec<Vec<u8>> = mips.into_iter().map(|(_, mip)| mip).collect();
+    let raw: usize = mips.iter().map(|m| m.len()).sum();
+    let mut additional = BfImageAdditional::new(width as u16, height as u16, output_format as u8);
+    let (payload, compression) = if matches.is_present("chunked") {
+        additional.chunked = true;
+        let mut chunks = BfImageChunksBuilder::new(compression);
+        for mip in mips.iter() {
+            chunks.add_mip(mip)
+                .map_err(|e| panic!("cannot compress mip-map: {}", e))
+                .unwrap();
+        }
+        (chunks.build(), Compression::None)
+    } else {
+        (mips.concat(), compression)
+    };
 
+    let bf_header = BfWriter::new(Kind::Image, additional.into_u64())
+        .compression(compression)
+        .payload(payload)
+        .finish(&output)
+        .map_err(|e| panic!("cannot write output file: {}", e))
+        .unwrap();
+    timers.save.end();
+
+    println!("raw={} compressed={} ratio={}", raw, bf_header.stored_size(), 100.0 * bf_header.stored_size() as f32 / raw as f32);
+    println!("time load={}ms", timers.load.total_time().as_millis());
+    println!("time vflip={}ms", timers.vflip.total_time().as_millis());
+    println!("time channels={}ms", timers.channels.total_time().as_millis());
+    println!("time mipmaps={}ms", timers.mipmaps.total_time().as_millis());
+    println!("time dxt={}ms", timers.dxt.total_time().as_millis());
+    println!("time save={}ms", timers.save.total_time().as_millis());
+}
+
+/// Converts the input image and its mip-maps to specified low dynamic range
+/// format. Returns dimensions and data of each mip-map.
+fn convert(input: &Path, output_format: BfImageFormat, vflip: bool, timers: &mut Timers) -> Vec<((u32, u32), Vec<u8>)> {
     // 1. load image
     timers.load.start();
     let mut input_image = image::open(input)
@@ -103,18 +228,18 @@ fn main() {
 
     // 2. vflip
     timers.vflip.start();
-    if !matches.is_present("not-vflip") {
+    if vflip {
         input_image = input_image.flipv();
     }
     timers.vflip.end();
 
     // 3. rgba <-> rgb
-    let output_format = BfImageFormat::try_from(matches.value_of("format").unwrap())
-        .expect("invalid output format specified");
-
     // todo: remove and use the function from `image` crate when PR 1002 is merged
     timers.channels.start();
-    if num_components(input_image.color()) != output_format.channels() {
+    if let BfImageFormat::Bc4 | BfImageFormat::Bc5 | BfImageFormat::Bc7 | BfImageFormat::SrgbBc7 = output_format {
+        // bc encoders always read rgba pixels
+        input_image = DynamicImage::ImageRgba8(input_image.to_rgba());
+    } else if num_components(input_image.color()) != output_format.channels() {
         if num_components(input_image.color()) > output_format.channels() {
             input_image = DynamicImage::ImageRgb8(input_image.to_rgb());
         } else {
@@ -141,7 +266,7 @@ fn main() {
 
     // 5. convert to output format
     timers.dxt.start();
-    let mut payload = vec![];
+    let mut mips = vec![];
     for img in mipmaps {
         let raw = img.raw_pixels();
         let raw = raw.as_slice();
@@ -155,52 +280,67 @@ fn main() {
             storage
         };
 
+        let (w, h) = (img.width() as usize, img.height() as usize);
         let result = match output_format {
             // we need to perform dxt compression
             BfImageFormat::SrgbDxt1 | BfImageFormat::Dxt1 => dxt(DXTVariant::DXT1),
             BfImageFormat::SrgbDxt3 | BfImageFormat::Dxt3 => dxt(DXTVariant::DXT3),
             BfImageFormat::SrgbDxt5 | BfImageFormat::Dxt5 => dxt(DXTVariant::DXT5),
+            BfImageFormat::Bc4 => encode_bc4(raw, w, h),
+            BfImageFormat::Bc5 => encode_bc5(raw, w, h),
+            BfImageFormat::SrgbBc7 | BfImageFormat::Bc7 => encode_bc7(raw, w, h),
 
             // for uncompressed formats we just copy the buffer
             _ => Vec::from(raw), // todo: optimize needless copy
         };
 
-        payload.extend(result);
+        mips.push(((img.width(), img.height()), result));
     }
     timers.dxt.end();
 
+    mips
+}
 
-    // 5. compress with lz4
-    timers.lz4.start();
-    let mut compressed = compress(payload.as_slice(), Some(HIGHCOMPRESSION(16)), false)
-        .map_err(|e| panic!("compression failed: {}", e))
-        .unwrap();
-    timers.lz4.end();
+/// Converts the input image and its mip-maps to specified floating point
+/// format. Returns dimensions and data of each mip-map.
+fn convert_hdr(input: &Path, output_format: BfImageFormat, vflip: bool, timers: &mut Timers) -> Vec<((u32, u32), Vec<u8>)> {
+    // 1. load image
+    timers.load.start();
+    let mut input_image = HdrImage::open(input);
+    timers.load.end();
 
-    // 6. write file_out
-    timers.save.start();
-    let bf_header = BfHeader::new(
-        Kind::Image,
-        1,
-        BfImageAdditional::new(width as u16, height as u16, output_format as u8).into_u64(),
-        payload.len() as u64,
-        compressed.len() as u64,
-    );
-    let mut out_file = File::create(output)
-        .map_err(|e| panic!("cannot open output file: {}", e))
-        .unwrap();
-    out_file.write_all(&bf_header.as_bytes()).expect("cannot write to output file");
-    out_file.write_all(&compressed.as_mut_slice()).expect("cannot write to output file");
-    out_file.flush().expect("cannot write to output file");
-    ;
-    timers.save.end();
+    println!("width={}", input_image.width);
+    println!("height={}", input_image.height);
+    println!("color=hdr");
 
-    println!("raw={} compressed={} ratio={}", bf_header.uncompressed, bf_header.compressed, 100.0 * bf_header.compressed as f32 / bf_header.uncompressed as f32);
-    println!("time load={}ms", timers.load.total_time().as_millis());
-    println!("time vflip={}ms", timers.vflip.total_time().as_millis());
-    println!("time channels={}ms", timers.channels.total_time().as_millis());
-    println!("time mipmaps={}ms", timers.mipmaps.total_time().as_millis());
-    println!("time dxt={}ms", timers.dxt.total_time().as_millis());
-    println!("time lz4={}ms", timers.lz4.total_time().as_millis());
-    println!("time save={}ms", timers.save.total_time().as_millis());
+    // 2. vflip
+    timers.vflip.start();
+    if vflip {
+        input_image = input_image.flipv();
+    }
+    timers.vflip.end();
+
+    // 4. mipmaps
+    timers.mipmaps.start();
+    let mut mipmaps = vec![input_image];
+    while mipmaps.last().unwrap().width > 4 { // 4 is the minimal size for bc texture
+        let lower = mipmaps.last().unwrap().downsample();
+        mipmaps.push(lower);
+    }
+    timers.mipmaps.end();
+
+    // 5. convert to output format
+    timers.dxt.start();
+    let mips = mipmaps.iter()
+        .map(|img| {
+            let result = match output_format {
+                BfImageFormat::Bc6h => encode_bc6h(&img.pixels, img.width as usize, img.height as usize),
+                _ => encode_rgba16f(&img.pixels),
+            };
+            ((img.width, img.height), result)
+        })
+        .collect();
+    timers.dxt.end();
+
+    mips
 }
@@ -0,0 +1,247 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use glob::Pattern;
+
+use vk_test::bf::{AdditionalData, BfVfsAdditional, BfVfsBuilder, decompress_vfs_entry, Kind, load_bf_from_bytes_verified, load_vfs_from_bytes, VfsCompression};
+use vk_test::cli::{codec_arg, derive_compression};
+use vk_test::perf::Stopwatch;
+
+struct Timers<'a> {
+    walk: Stopwatch<'a>,
+    save: Stopwatch<'a>,
+}
+
+impl<'a> Default for Timers<'a> {
+    fn default() -> Self {
+        Timers {
+            walk: Stopwatch::new("walk"),
+            save: Stopwatch::new("save"),
+        }
+    }
+}
+
+/// Include and exclude glob patterns matched against names of the entries.
+struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        let patterns = |name| matches.values_of(name)
+            .map(|values| values
+                .map(|p| Pattern::new(p)
+                    .map_err(|e| panic!("invalid glob pattern {}: {}", p, e))
+                    .unwrap())
+                .collect())
+            .unwrap_or_default();
+
+        Filter {
+            include: patterns("include"),
+            exclude: patterns("exclude"),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(name)))
+            && !self.exclude.iter().any(|p| p.matches(name))
+    }
+}
+
+fn filter_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(Arg::with_name("include")
+        .long("include")
+        .value_name("GLOB")
+        .help("Only process entries matching the pattern (can be specified multiple times)")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1))
+        .arg(Arg::with_name("exclude")
+            .long("exclude")
+            .value_name("GLOB")
+            .help("Skip entries matching the pattern (can be specified multiple times)")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+}
+
+fn main() {
+    let matches = App::new("mkvfs")
+        .version("1.0")
+        .author("Matej K. <dobrakmato@gmail.com>")
+        .about("Packs content directories into VFS archives and inspects them")
+        .subcommand(filter_args(SubCommand::with_name("pack")
+            .about("Packs all files in directory into one VFS archive")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("CONTENT_PATH")
+                .help("Path to the content root directory to pack")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT_FILE")
+                .help("Path to the archive to generate")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("compression")
+                .short("c")
+                .long("compression")
+                .value_name("LAYOUT")
+                .help("One of: none, archive (compress(vfs(files))), entries (vfs(compress(file))); --codec applies only to archive")
+                .default_value("none")
+                .takes_value(true))
+            .arg(codec_arg())))
+        .subcommand(filter_args(SubCommand::with_name("list")
+            .about("Lists entries of VFS archive")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT_FILE")
+                .help("Path to the archive to list")
+                .required(true)
+                .takes_value(true))))
+        .subcommand(filter_args(SubCommand::with_name("extract")
+            .about("Extracts entries of VFS archive to directory")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT_FILE")
+                .help("Path to the archive to extract")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT_DIR")
+                .help("Directory to extract the entries into")
+                .required(true)
+                .takes_value(true))))
+        .get_matches();
+
+    match matches.subcommand() {
+        ("pack", Some(m)) => pack(m),
+        ("list", Some(m)) => list(m),
+        ("extract", Some(m)) => extract(m),
+        _ => println!("{}", matches.usage()),
+    }
+}
+
+fn pack(matches: &ArgMatches) {
+    let mut timers = Timers::default();
+    let filter = Filter::from_matches(matches);
+    let input = Path::new(matches.value_of("input").unwrap());
+    let output = Path::new(matches.value_of("output").unwrap());
+    let compression = VfsCompression::try_from(matches.value_of("compression").unwrap())
+        .expect("invalid compression layout specified");
+    if compression != VfsCompression::Archive && matches.occurrences_of("codec") > 0 {
+        panic!("--codec can only be used with --compression archive");
+    }
+
+    timers.walk.start();
+    let mut builder = BfVfsBuilder::new();
+    builder.archive_compression(derive_compression(matches));
+    builder.add_directory_filtered(input, |name| filter.matches(name))
+        .map_err(|e| panic!("cannot read content directory: {}", e))
+        .unwrap();
+    timers.walk.end();
+
+    let files = builder.len();
+    let raw = builder.data_size();
+
+    timers.save.start();
+    let header = builder.save(output, compression)
+        .map_err(|e| panic!("cannot write output file: {}", e))
+        .unwrap();
+    timers.save.end();
+
+    let stored = header.stored_size();
+
+    println!("files={}", files);
+    println!("raw={} compressed={} ratio={}", raw, stored, 100.0 * stored as f32 / raw as f32);
+    println!("time walk={}ms", timers.walk.total_time().as_millis());
+    println!("time save={}ms", timers.save.total_time().as_millis());
+}
+
+/// Reads the archive and returns its decompressed payload and additional data.
+fn read_archive(path: &str) -> (Vec<u8>, BfVfsAdditional) {
+    let mut file = File::open(path)
+        .map_err(|e| panic!("cannot open input file: {}", e))
+        .unwrap();
+
+    let mut cnts = vec![];
+    file.read_to_end(&mut cnts)
+        .expect("read error");
+
+    let file = load_bf_from_bytes_verified(&cnts)
+        .map_err(|e| panic!("cannot decode input file: {}", e))
+        .unwrap();
+
+    if file.header.kind != Kind::VirtualFileSystem as u8 {
+        panic!("input file is not a vfs archive: kind={}", file.header.kind);
+    }
+
+    let payload = file.payload()
+        .map_err(|e| panic!("cannot decode payload: {}", e))
+        .unwrap()
+        .into_owned();
+
+    (payload, BfVfsAdditional::from_u64(file.header.additional))
+}
+
+fn list(matches: &ArgMatches) {
+    let filter = Filter::from_matches(matches);
+    let (payload, additional) = read_archive(matches.value_of("input").unwrap());
+    let vfs = load_vfs_from_bytes(&payload)
+        .map_err(|e| panic!("cannot decode vfs entries: {}", e))
+        .unwrap();
+
+    println!("entries={}", vfs.entries().len());
+    println!("compressed_entries={}", additional.compressed_entries);
+
+    for entry in vfs.entries().iter().filter(|e| filter.matches(e.name())) {
+        println!("entry name={} offset={} size={}", entry.name(), entry.offset(), entry.data().len());
+    }
+}
+
+fn extract(matches: &ArgMatches) {
+    let filter = Filter::from_matches(matches);
+    let output = Path::new(matches.value_of("output").unwrap());
+    let (payload, additional) = read_archive(matches.value_of("input").unwrap());
+    let vfs = load_vfs_from_bytes(&payload)
+        .map_err(|e| panic!("cannot decode vfs entries: {}", e))
+        .unwrap();
+
+    for entry in vfs.entries().iter().filter(|e| filter.matches(e.name())) {
+        // do not let malicious archives write outside of the output directory
+        if !Path::new(entry.name()).components().all(|c| matches!(c, Component::Normal(_))) {
+            panic!("invalid entry name: {}", entry.name());
+        }
+
+        let path = output.join(entry.name());
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| panic!("cannot create output directory: {}", e))
+                .unwrap();
+        }
+
+        let data = if additional.compressed_entries {
+            decompress_vfs_entry(entry.data())
+                .map_err(|e| panic!("cannot decompress entry {}: {}", entry.name(), e))
+                .unwrap()
+        } else {
+            entry.data().to_vec()
+        };
+
+        std::fs::write(&path, data)
+            .map_err(|e| panic!("cannot write {}: {}", path.display(), e))
+            .unwrap();
+        println!("extracted {}", entry.name());
+    }
+}
@@ -1,17 +1,19 @@
 use std::path::{Path, PathBuf};
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg};
 
+use vk_test::bf::{AdditionalData, BfGeometryAdditional, BfWriter, Compression, Kind};
+use vk_test::mesh::{Mesh, NormalSmoothing, VERTEX_CACHE_SIZE, cache_stats, optimize_vertex_cache};
 use vk_test::perf::Stopwatch;
 use wavefront_obj::obj::parse;
-use vk_test::cli::derive_input_and_output;
+use vk_test::cli::{codec_arg, derive_compression, derive_input_and_output};
 
 struct Timers<'a> {
     load: Stopwatch<'a>,
+    index: Stopwatch<'a>,
     lods: Stopwatch<'a>,
     normalize: Stopwatch<'a>,
     optimize: Stopwatch<'a>,
-    lz4: Stopwatch<'a>,
     save: Stopwatch<'a>,
 }
 
@@ -19,10 +21,10 @@ impl<'a> Default for Timers<'a> {
     fn default() -> Self {
         Timers {
             load: Stopwatch::new("load"),
+            index: Stopwatch::new("index"),
             lods: Stopwatch::new("lods"),
             normalize: Stopwatch::new("normalize"),
             optimize: Stopwatch::new("optimize"),
-            lz4: Stopwatch::new("lz4"),
             save: Stopwatch::new("save"),
         }
     }
@@ -55,15 +57,43 @@ fn main() {
             .takes_value(true))
         .arg(Arg::with_name("LOD_LEVELS")
             .long("lod-levels")
-            .help("Specify number of LOD levels generated")
+            .help("Specify number of LOD levels generated (at most 8), each level has half of the triangles")
+            .takes_value(true))
+        .arg(Arg::with_name("lod-output")
+            .long("lod-output")
+            .value_name("LAYOUT")
+            .help("One of: single (all levels in one file), files (each level in sibling file with _lod suffix)")
+            .default_value("single")
+            .takes_value(true))
+        .arg(Arg::with_name("normals")
+            .long("normals")
+            .value_name("SMOOTHING")
+            .help("Generate normals, one of: flat, smooth, angle in degrees up to which the faces are smoothed (OBJ smoothing groups are respected)")
             .takes_value(true))
         .arg(Arg::with_name("optimize")
-            .short("o")
             .long("optimize")
-            .help("Optimize the mesh for cache accesses"))
+            .help("Optimize the mesh for cache accesses (reorder triangles for vertex cache and vertices for fetch)"))
+        .arg(codec_arg())
         .get_matches();
 
     let (input, output) = derive_input_and_output(&matches);
+    let compression = derive_compression(&matches);
+    let lod_levels = matches.value_of("LOD_LEVELS")
+        .map(|x| x.parse::<usize>().expect("invalid number of lod levels"))
+        .unwrap_or(0);
+    if lod_levels > 8 {
+        panic!("at most 8 lod levels are supported");
+    }
+    let normals = matches.value_of("normals").map(|x| match x {
+        "flat" => NormalSmoothing::Flat,
+        "smooth" => NormalSmoothing::Smooth,
+        t => NormalSmoothing::Crease(t.parse::<f32>().expect("invalid normal smoothing specified")),
+    });
+    let separate_lods = match matches.value_of("lod-output").unwrap() {
+        "single" => false,
+        "files" => true,
+        t => panic!("invalid lod output specified: {}", t),
+    };
 
     timers.load.start();
     let cnts = std::fs::read_to_string(&input)
@@ -76,17 +106,118 @@ fn main() {
 
     println!("objects={}", obj.objects.len());
 
-    // todo: generate lods (simplify mesh)
-    // rewrite to indexed (duplicate values)
-    // optimize meshes (forsyth)
-    // compress
-    // save
+    // rewrite to indexed (deduplicate vertices)
+    timers.index.start();
+    let mut mesh = Mesh::from_obj(&obj);
+    timers.index.end();
+
+    let shapes = obj.objects.iter()
+        .flat_map(|o| o.geometry.iter())
+        .map(|g| g.shapes.len())
+        .sum::<usize>();
+    println!("shapes={} triangles={} vertices={} indices={}", shapes, mesh.triangle_count(), mesh.vertex_count(), mesh.indices.len());
+    println!("normals={} uvs={}", mesh.normals.is_some(), mesh.uvs.is_some());
 
-    //println!("raw={} compressed={} ratio={}", bf_header.uncompressed, bf_header.compressed, 100.0 * bf_header.compressed as f32 / bf_header.uncompressed as f32);
+    // generate normals (split vertices on hard edges)
+    if let Some(smoothing) = normals {
+        timers.normalize.start();
+        mesh.generate_normals(smoothing, Some(&Mesh::obj_smoothing_groups(&obj)));
+        timers.normalize.end();
+        println!("normals={:?} vertices={}", smoothing, mesh.vertex_count());
+    }
+
+    // generate tangents (mikktspace) when the mesh can be normal mapped
+    if mesh.normals.is_some() && mesh.uvs.is_some() {
+        timers.normalize.start();
+        mesh.generate_tangents();
+        timers.normalize.end();
+        println!("tangents=true vertices={}", mesh.vertex_count());
+    }
+
+    // generate lods (simplify mesh), error is relative to size of the mesh
+    timers.lods.start();
+    let diagonal = bounds_diagonal(&mesh.positions);
+    let mut lods: Vec<Vec<u32>> = vec![];
+    for level in 1..=lod_levels {
+        let (indices, error) = mesh.simplify(mesh.triangle_count() >> level);
+        if indices.len() >= lods.last().unwrap_or(&mesh.indices).len() {
+            println!("lod level={} cannot simplify further", level);
+            break;
+        }
+        // all positions may be the same (or there are none)
+        let relative = if diagonal > 0.0 { error / diagonal } else { 0.0 };
+        println!("lod level={} triangles={} error={} relative={}", level, indices.len() / 3, error, relative);
+        lods.push(indices);
+    }
+    timers.lods.end();
+
+    // optimize meshes (tipsify), then vertices in order of the optimized triangles
+    if matches.is_present("optimize") {
+        timers.optimize.start();
+        let vertex_count = mesh.vertex_count();
+        for (level, indices) in std::iter::once(&mut mesh.indices).chain(lods.iter_mut()).enumerate() {
+            let before = cache_stats(indices, vertex_count, VERTEX_CACHE_SIZE);
+            *indices = optimize_vertex_cache(indices, vertex_count, VERTEX_CACHE_SIZE);
+            let after = cache_stats(indices, vertex_count, VERTEX_CACHE_SIZE);
+            println!("optimize level={} acmr={}->{} atvr={}->{}", level, before.acmr, after.acmr, before.atvr, after.atvr);
+        }
+        mesh.optimize_vertex_fetch(&mut lods);
+        timers.optimize.end();
+    }
+
+    // compress and save
+    timers.save.start();
+    let (raw, stored) = if separate_lods {
+        let mut raw = save(&output, mesh.to_geometry(), compression);
+        for (level, indices) in lods.iter().enumerate() {
+            let lod = Mesh { indices: indices.clone(), ..mesh.clone() }.compact();
+            let size = save(&lod_path(&output, level + 1), lod.to_geometry(), compression);
+            raw = (raw.0 + size.0, raw.1 + size.1);
+        }
+        raw
+    } else {
+        save(&output, mesh.to_geometry_with_lods(&lods), compression)
+    };
+    timers.save.end();
+
+    println!("raw={} compressed={} ratio={}", raw, stored, 100.0 * stored as f32 / raw as f32);
     println!("time load={}ms", timers.load.total_time().as_millis());
+    println!("time index={}ms", timers.index.total_time().as_millis());
     println!("time lods={}ms", timers.lods.total_time().as_millis());
     println!("time normalize={}ms", timers.normalize.total_time().as_millis());
     println!("time optimize={}ms", timers.optimize.total_time().as_millis());
-    println!("time lz4={}ms", timers.lz4.total_time().as_millis());
     println!("time save={}ms", timers.save.total_time().as_millis());
 }
+
+/// Writes the geometry payload to file and returns its raw and stored size.
+fn save(path: &Path, payload: Vec<u8>, compression: Compression) -> (usize, usize) {
+    let raw = payload.len();
+    let bf_header = BfWriter::new(Kind::Geometry, BfGeometryAdditional.into_u64())
+        .compression(compression)
+        .payload(payload)
+        .finish(path)
+        .map_err(|e| panic!("cannot write output file: {}", e))
+        .unwrap();
+    (raw, bf_header.stored_size() as usize)
+}
+
+/// Returns path of the sibling file with specified LOD level (`name_lod1.bf`).
+fn lod_path(output: &Path, level: usize) -> PathBuf {
+    let stem = output.file_stem()
+        .expect("output file is not a valid file")
+        .to_string_lossy();
+    output.with_file_name(format!("{}_lod{}.bf", stem, level))
+}
+
+/// Returns length of the diagonal of the bounding box of the positions.
+fn bounds_diagonal(positions: &[[f32; 3]]) -> f32 {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for p in positions.iter() {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(p[axis]);
+            max[axis] = max[axis].max(p[axis]);
+        }
+    }
+    (0..3).map(|a| (max[a] - min[a]).max(0.0).powi(2)).sum::<f32>().sqrt()
+}
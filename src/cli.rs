@@ -1,5 +1,7 @@
+use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
-use clap::ArgMatches;
+use clap::{Arg, ArgMatches};
+use crate::bf::Compression;
 
 /// Derives output path from input path by changing the file's extension.
 pub fn derive_output_from(input: &str) -> PathBuf {
@@ -23,3 +25,22 @@ pub fn derive_input_and_output(matches: &ArgMatches) -> (PathBuf, PathBuf) {
     let input = PathBuf::from(input);
     (input, output)
 }
+
+/// Creates the argument for selecting compression of the payload shared by
+/// the tools producing BF files.
+pub fn codec_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("codec")
+        .long("codec")
+        .value_name("CODEC")
+        .help("One of: none, lz4, lz4hc, zstd (zstd is intended for distribution builds)")
+        .default_value("lz4hc")
+        .takes_value(true)
+}
+
+/// Returns the compression selected by the argument created with `codec_arg`.
+pub fn derive_compression(matches: &ArgMatches) -> Compression {
+    let codec = matches.value_of("codec").unwrap();
+    Compression::try_from(codec)
+        .map_err(|_| panic!("invalid codec specified: {}", codec))
+        .unwrap()
+}
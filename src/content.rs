@@ -1,24 +1,80 @@
+use std::collections::HashMap;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::fs::{read_to_string, File};
+use std::fs::read;
+use crate::bf::{load_bf_from_bytes_verified, load_vfs_from_bytes, decompress_vfs_entry, AdditionalData, BfVfsAdditional, Kind};
 
 /// Content loading error type (with optional message).
+#[derive(Debug)]
 pub enum Error {
     NotFound,
     IOError(String),
+    InvalidArchive(String),
 }
 
-pub struct Content {
-    roots: Vec<PathBuf>,
+/// One root of the content system. Either a directory in the file system or
+/// VFS archive loaded into memory.
+enum Root {
+    Directory(PathBuf),
+    Archive(Archive),
+}
+
+/// Loaded VFS archive. Entries are stored as ranges into the payload so the
+/// lookups do not need to re-parse the entry table.
+struct Archive {
+    payload: Vec<u8>,
+    entries: HashMap<String, Range<usize>>,
+    compressed_entries: bool,
 }
 
-impl Default for Content {
-    fn default() -> Self {
-        Content {
-            roots: vec![],
+impl Archive {
+    fn open(path: &Path) -> Result<Self, Error> {
+        let bytes = read(path)
+            .map_err(|e| Error::IOError(e.to_string()))?;
+        let file = load_bf_from_bytes_verified(&bytes)
+            .map_err(|e| Error::InvalidArchive(e.to_string()))?;
+
+        if file.header.kind != Kind::VirtualFileSystem as u8 {
+            return Err(Error::InvalidArchive(format!("not a vfs archive: kind={}", file.header.kind)));
+        }
+
+        let payload = file.payload()
+            .map_err(|e| Error::InvalidArchive(e.to_string()))?
+            .into_owned();
+
+        let entries = load_vfs_from_bytes(&payload)
+            .map_err(|e| Error::InvalidArchive(e.to_string()))?
+            .entries()
+            .iter()
+            .map(|e| (e.name().to_owned(), e.offset()..e.offset() + e.data().len()))
+            .collect();
+
+        Ok(Archive {
+            payload,
+            entries,
+            compressed_entries: BfVfsAdditional::from_u64(file.header.additional).compressed_entries,
+        })
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    fn load(&self, path: &str) -> Option<Result<Vec<u8>, Error>> {
+        let data = &self.payload[self.entries.get(path)?.clone()];
+        if self.compressed_entries {
+            Some(decompress_vfs_entry(data).map_err(|e| Error::IOError(e.to_string())))
+        } else {
+            Some(Ok(data.to_vec()))
         }
     }
 }
 
+#[derive(Default)]
+pub struct Content {
+    roots: Vec<Root>,
+}
+
 impl Content {
     pub fn new() -> Self {
         Content::default()
@@ -27,59 +83,62 @@ impl Content {
     /// Adds a root the the content system. If the path is link to folder it will
     /// be used as normal file system. If the path points to BF archive, the archive
     /// will be loaded as VFS (virtual file system).
-    pub fn add_root(&mut self, root: PathBuf) {
-        // todo: vfs
-        self.roots.push(root)
-    }
-
-    /// Resolves specified path in string to PathBuf that can be used in other operations.
-    fn find_file(&self, path: &str) -> Option<PathBuf> {
-        for p in self.roots.iter() {
-            let relative = p.join(Path::new(path));
-            if relative.exists() {
-                return Some(relative);
-            }
+    pub fn add_root(&mut self, root: PathBuf) -> Result<(), Error> {
+        if root.is_file() {
+            self.roots.push(Root::Archive(Archive::open(&root)?));
+        } else {
+            self.roots.push(Root::Directory(root));
         }
-        None
+        Ok(())
     }
 
     /// Returns true if file specified by the path exists, false otherwise.
     pub fn exists(&self, path: &str) -> bool {
-        return self.find_file(path).is_some();
+        self.roots.iter().any(|root| match root {
+            Root::Directory(p) => p.join(Path::new(path)).exists(),
+            Root::Archive(a) => a.contains(path),
+        })
     }
 
     /// Loads file specified by path to String or returns Error if file does not
     /// exists or there is other problem with reading the file.
     pub fn load_utf8(&self, path: &str) -> Result<String, Error> {
-        let resolved = self.find_file(path)
-            .ok_or(Error::NotFound)?;
-
-        read_to_string(resolved)
+        String::from_utf8(self.load_binary(path)?)
             .map_err(|e| Error::IOError(e.to_string()))
     }
 
     /// Loads file specified by path to Vec<u8> or returns Error if file does not
     /// exists or there is other problem with reading the file.
-    pub fn load_binary(&self, path: &str) -> Result<File, Error> {
-        let resolved = self.find_file(path)
-            .ok_or(Error::NotFound)?;
-
-        File::open(resolved)
-            .map_err(|e| Error::IOError(e.to_string()))
+    pub fn load_binary(&self, path: &str) -> Result<Vec<u8>, Error> {
+        for root in self.roots.iter() {
+            match root {
+                Root::Directory(p) => {
+                    let relative = p.join(Path::new(path));
+                    if relative.exists() {
+                        return read(relative)
+                            .map_err(|e| Error::IOError(e.to_string()));
+                    }
+                }
+                Root::Archive(a) => if let Some(result) = a.load(path) {
+                    return result;
+                }
+            }
+        }
+        Err(Error::NotFound)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::content::Content;
+    use crate::bf::{pack_directory, BfVfsBuilder, VfsCompression};
     use std::path::Path;
-    use std::io::Read;
 
     #[test]
     fn exists() {
         let mut content = Content::default();
 
-        content.add_root(Path::new("resources").to_owned());
+        content.add_root(Path::new("resources").to_owned()).ok().unwrap();
 
         assert!(content.exists("text_file.txt"));
         assert!(!content.exists("non_existing.txt"));
@@ -89,7 +148,7 @@ mod tests {
     fn read_utf8() {
         let mut content = Content::default();
 
-        content.add_root(Path::new("resources").to_owned());
+        content.add_root(Path::new("resources").to_owned()).ok().unwrap();
 
         assert_eq!(content.load_utf8("text_file.txt").ok().unwrap(), "test text file");
         assert!(content.load_utf8("non_existing.txt").is_err());
@@ -99,13 +158,46 @@ mod tests {
     fn read_binary() {
         let mut content = Content::default();
 
-        content.add_root(Path::new("resources").to_owned());
+        content.add_root(Path::new("resources").to_owned()).ok().unwrap();
 
-        let mut read = content.load_binary("text_file.txt").ok().unwrap();
-        let mut contents = [0u8; 14];
-        read.read_exact(&mut contents).ok();
+        let contents = content.load_binary("text_file.txt").ok().unwrap();
 
         assert_eq!(&contents, b"test text file");
         assert!(content.load_binary("non_existing.txt").is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn archive_root() {
+        let archive = std::env::temp_dir().join("vk_test_content_archive_root.bf");
+        pack_directory(Path::new("resources"), &archive).ok().unwrap();
+
+        let mut content = Content::default();
+        content.add_root(archive.clone()).ok().unwrap();
+
+        assert!(content.exists("text_file.txt"));
+        assert!(!content.exists("non_existing.txt"));
+        assert_eq!(content.load_utf8("text_file.txt").ok().unwrap(), "test text file");
+        assert_eq!(&content.load_binary("text_file.txt").ok().unwrap(), b"test text file");
+        assert!(content.load_binary("non_existing.txt").is_err());
+
+        std::fs::remove_file(archive).ok();
+    }
+
+    #[test]
+    fn compressed_archive_roots() {
+        for (name, compression) in &[("archive", VfsCompression::Archive), ("entries", VfsCompression::Entries)] {
+            let archive = std::env::temp_dir().join(format!("vk_test_content_compressed_{}.bf", name));
+            let mut builder = BfVfsBuilder::new();
+            builder.add_directory(Path::new("resources")).ok().unwrap();
+            builder.save(&archive, *compression).ok().unwrap();
+
+            let mut content = Content::default();
+            content.add_root(archive.clone()).ok().unwrap();
+
+            assert!(content.exists("text_file.txt"));
+            assert_eq!(content.load_utf8("text_file.txt").ok().unwrap(), "test text file");
+
+            std::fs::remove_file(archive).ok();
+        }
+    }
+}
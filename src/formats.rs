@@ -0,0 +1,279 @@
+use std::borrow::Cow;
+
+use gfx_hal::{Backend, Features};
+use gfx_hal::adapter::PhysicalDevice;
+use gfx_hal::format::{Format, ImageFeature, Properties};
+
+use crate::bc::{decode_bc6h, encode_rgba16f};
+use crate::bf::{BfImage, BfImageFormat, Error, decode_rgba8};
+
+/// Returns the format used to sample images stored in specified BF format.
+pub fn hal_format(format: BfImageFormat) -> Format {
+    match format {
+        BfImageFormat::Dxt1 => Format::Bc1RgbUnorm,
+        BfImageFormat::Dxt3 => Format::Bc2Unorm,
+        BfImageFormat::Dxt5 => Format::Bc3Unorm,
+        BfImageFormat::Rgb8 => Format::Rgb8Unorm,
+        BfImageFormat::Rgba8 => Format::Rgba8Unorm,
+        BfImageFormat::SrgbDxt1 => Format::Bc1RgbSrgb,
+        BfImageFormat::SrgbDxt3 => Format::Bc2Srgb,
+        BfImageFormat::SrgbDxt5 => Format::Bc3Srgb,
+        BfImageFormat::Srgb8 => Format::Rgb8Srgb,
+        BfImageFormat::Srgb8A8 => Format::Rgba8Srgb,
+        BfImageFormat::Bc4 => Format::Bc4Unorm,
+        BfImageFormat::Bc5 => Format::Bc5Unorm,
+        BfImageFormat::Bc6h => Format::Bc6hUfloat,
+        BfImageFormat::Bc7 => Format::Bc7Unorm,
+        BfImageFormat::SrgbBc7 => Format::Bc7Srgb,
+        BfImageFormat::Rgba16f => Format::Rgba16Sfloat,
+    }
+}
+
+/// Returns the format images should be converted to when specified format
+/// cannot be sampled by the device. Block compressed formats fall back to
+/// uncompressed formats with the same color space, three channel formats
+/// to four channel formats. Returns `None` for formats which every device
+/// has to support.
+pub fn fallback_format(format: BfImageFormat) -> Option<BfImageFormat> {
+    match format {
+        BfImageFormat::Dxt1 | BfImageFormat::Dxt3 | BfImageFormat::Dxt5 => Some(BfImageFormat::Rgba8),
+        BfImageFormat::Bc4 | BfImageFormat::Bc5 | BfImageFormat::Bc7 => Some(BfImageFormat::Rgba8),
+        BfImageFormat::SrgbDxt1 | BfImageFormat::SrgbDxt3 | BfImageFormat::SrgbDxt5 => Some(BfImageFormat::Srgb8A8),
+        BfImageFormat::SrgbBc7 => Some(BfImageFormat::Srgb8A8),
+        BfImageFormat::Rgb8 => Some(BfImageFormat::Rgba8),
+        BfImageFormat::Srgb8 => Some(BfImageFormat::Srgb8A8),
+        BfImageFormat::Bc6h => Some(BfImageFormat::Rgba16f),
+        BfImageFormat::Rgba16f => Some(BfImageFormat::Rgba8),
+        BfImageFormat::Rgba8 | BfImageFormat::Srgb8A8 => None,
+    }
+}
+
+/// Set of BF image formats which can be sampled by a device.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct FormatSupport {
+    supported: u32,
+}
+
+impl FormatSupport {
+    /// Queries which formats can be sampled by the physical device when opened
+    /// with specified features.
+    pub fn query<B: Backend>(physical_device: &B::PhysicalDevice, features: Features) -> Self {
+        Self::from_properties(features, |f| physical_device.format_properties(Some(f)))
+    }
+
+    /// Creates the set from enabled device features and function returning
+    /// properties of each format. Block compressed formats are only supported
+    /// when the `FORMAT_BC` feature is enabled.
+    pub fn from_properties<F: Fn(Format) -> Properties>(features: Features, properties: F) -> Self {
+        let mut supported = 0;
+        for format in BfImageFormat::ALL.iter() {
+            if format.is_block_compressed() && !features.contains(Features::FORMAT_BC) {
+                continue;
+            }
+
+            if properties(hal_format(*format)).optimal_tiling.contains(ImageFeature::SAMPLED) {
+                supported |= 1 << *format as u32;
+            }
+        }
+        FormatSupport { supported }
+    }
+
+    /// Returns true if images of specified format can be sampled.
+    pub fn is_supported(&self, format: BfImageFormat) -> bool {
+        self.supported & (1 << format as u32) != 0
+    }
+
+    /// Returns iterator over all supported formats.
+    pub fn supported(&self) -> impl Iterator<Item=BfImageFormat> + '_ {
+        BfImageFormat::ALL.iter().copied().filter(move |f| self.is_supported(*f))
+    }
+
+    /// Returns the format images of specified format should be uploaded in. That
+    /// is the format itself when it is supported, otherwise the first supported
+    /// format in its fallback chain. Returns `None` if there is no such format.
+    pub fn select(&self, format: BfImageFormat) -> Option<BfImageFormat> {
+        let mut current = Some(format);
+        while let Some(format) = current {
+            if self.is_supported(format) {
+                return Some(format);
+            }
+            current = fallback_format(format);
+        }
+        None
+    }
+
+    /// Prepares all mip-maps of the image (from its base level) for upload in
+    /// format selected by `select`, converting each slice if the format of the
+    /// image is not supported.
+    pub fn prepare<'a>(&self, image: &'a BfImage<'_>) -> Result<DeviceImage<'a>, Error> {
+        let format = self.select(image.format())
+            .ok_or(Error::UnsupportedImageFormat(image.format() as u8))?;
+
+        let mut mips = Vec::with_capacity(image.mip_count() - image.base_level());
+        for level in image.base_level()..image.mip_count() {
+            let (width, height, data) = image.mip(level).expect("level is in range");
+            if format == image.format() {
+                mips.push((width, height, Cow::Borrowed(data)));
+                continue;
+            }
+
+            let mut converted = Vec::with_capacity(image.slice_count(level) * format.size_of(width, height));
+            for index in 0..image.slice_count(level) {
+                let slice = image.slice(level, index).expect("slice is in range");
+                converted.extend_from_slice(&convert_image(image.format(), format, width, height, slice)?);
+            }
+            mips.push((width, height, Cow::Owned(converted)));
+        }
+
+        Ok(DeviceImage { format, mips })
+    }
+}
+
+/// Mip-maps of an image in format that can be sampled by the device.
+#[derive(Debug)]
+pub struct DeviceImage<'a> {
+    /// Format of the data, either format of the image or its fallback.
+    pub format: BfImageFormat,
+    /// Width, height and data (all layers and depth slices) of each mip-map
+    /// starting at the base level of the image.
+    pub mips: Vec<(usize, usize, Cow<'a, [u8]>)>,
+}
+
+/// Converts one 2D slice of image stored in `format` to `target` format which
+/// was selected by `FormatSupport::select`. The data is borrowed when no
+/// conversion is needed.
+pub fn convert_image(format: BfImageFormat, target: BfImageFormat, width: usize, height: usize, data: &[u8]) -> Result<Cow<'_, [u8]>, Error> {
+    let size = format.size_of(width, height);
+    if data.len() < size {
+        return Err(Error::NotEnoughData { expected: size, actual: data.len() });
+    }
+
+    match target {
+        _ if target == format => Ok(Cow::Borrowed(&data[..size])),
+        BfImageFormat::Rgba8 | BfImageFormat::Srgb8A8 => {
+            decode_rgba8(format, width, height, data, target.color_space()).map(Cow::Owned)
+        }
+        BfImageFormat::Rgba16f if format == BfImageFormat::Bc6h => {
+            Ok(Cow::Owned(encode_rgba16f(&decode_bc6h(&data[..size], width, height))))
+        }
+        _ => Err(Error::InvalidImageFormat(target as u8)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use gfx_hal::Features;
+    use matches::assert_matches;
+    use gfx_hal::format::{Format, ImageFeature, Properties};
+    use crate::bf::{AdditionalData, BfImage, BfImageAdditional, BfImageFormat, BfWriter, Error, ImageDimension, Kind, load_bf_from_bytes};
+    use crate::formats::{hal_format, fallback_format, FormatSupport, convert_image};
+
+    fn sampled(formats: &[Format]) -> impl Fn(Format) -> Properties + '_ {
+        move |f| Properties {
+            optimal_tiling: if formats.contains(&f) { ImageFeature::SAMPLED } else { ImageFeature::empty() },
+            ..Properties::default()
+        }
+    }
+
+    #[test]
+    fn hal_formats() {
+        assert_eq!(hal_format(BfImageFormat::Dxt1), Format::Bc1RgbUnorm);
+        assert_eq!(hal_format(BfImageFormat::SrgbDxt5), Format::Bc3Srgb);
+        assert_eq!(hal_format(BfImageFormat::Bc6h), Format::Bc6hUfloat);
+        assert_eq!(hal_format(BfImageFormat::Rgba16f), Format::Rgba16Sfloat);
+
+        for format in BfImageFormat::ALL.iter() {
+            let hal = hal_format(*format);
+            let srgb = hal.base_format().1 == gfx_hal::format::ChannelType::Srgb;
+            assert_eq!(srgb, format.color_space() == crate::bf::ColorSpace::Srgb, "{:?}", format);
+            assert_eq!(hal.surface_desc().is_compressed(), format.is_block_compressed(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn fallbacks_end_in_mandatory_formats() {
+        for format in BfImageFormat::ALL.iter() {
+            let mut current = *format;
+            while let Some(fallback) = fallback_format(current) {
+                assert!(!fallback.is_block_compressed());
+                assert!(fallback.color_space() == format.color_space() || *format == BfImageFormat::Bc6h);
+                current = fallback;
+            }
+            assert!(current == BfImageFormat::Rgba8 || current == BfImageFormat::Srgb8A8);
+        }
+    }
+
+    #[test]
+    fn support_without_bc_feature() {
+        let all: Vec<Format> = BfImageFormat::ALL.iter().map(|f| hal_format(*f)).collect();
+        let support = FormatSupport::from_properties(Features::empty(), sampled(&all));
+
+        assert!(!support.is_supported(BfImageFormat::Dxt1));
+        assert!(support.is_supported(BfImageFormat::Rgba16f));
+        assert_eq!(support.select(BfImageFormat::Dxt1), Some(BfImageFormat::Rgba8));
+        assert_eq!(support.select(BfImageFormat::SrgbBc7), Some(BfImageFormat::Srgb8A8));
+        assert_eq!(support.select(BfImageFormat::Bc6h), Some(BfImageFormat::Rgba16f));
+        assert_eq!(support.supported().count(), 5);
+
+        let support = FormatSupport::from_properties(Features::FORMAT_BC, sampled(&all));
+        assert_eq!(support.select(BfImageFormat::Dxt1), Some(BfImageFormat::Dxt1));
+        assert_eq!(support.supported().count(), BfImageFormat::ALL.len());
+    }
+
+    #[test]
+    fn support_fallback_chain() {
+        let support = FormatSupport::from_properties(Features::FORMAT_BC, sampled(&[Format::Rgba8Unorm, Format::Bc1RgbUnorm]));
+
+        assert_eq!(support.select(BfImageFormat::Dxt1), Some(BfImageFormat::Dxt1));
+        assert_eq!(support.select(BfImageFormat::Rgb8), Some(BfImageFormat::Rgba8));
+        assert_eq!(support.select(BfImageFormat::Bc6h), Some(BfImageFormat::Rgba8));
+        assert_eq!(support.select(BfImageFormat::SrgbDxt1), None);
+        assert_eq!(FormatSupport::default().select(BfImageFormat::Rgba8), None);
+    }
+
+    #[test]
+    fn convert_to_fallbacks() {
+        let rgba = [10, 20, 30, 40];
+        assert_eq!(convert_image(BfImageFormat::Rgba8, BfImageFormat::Rgba8, 1, 1, &rgba).ok().unwrap().into_owned(), rgba.to_vec());
+        assert_eq!(convert_image(BfImageFormat::Rgb8, BfImageFormat::Rgba8, 1, 1, &rgba[..3]).ok().unwrap().into_owned(), vec![10, 20, 30, 255]);
+
+        let block = crate::bc::encode_bc6h(&[1.0; 64], 4, 4);
+        let converted = convert_image(BfImageFormat::Bc6h, BfImageFormat::Rgba16f, 4, 4, &block).ok().unwrap();
+        assert_eq!(converted.len(), 4 * 4 * 8);
+        assert_eq!(convert_image(BfImageFormat::Bc6h, BfImageFormat::Rgba8, 4, 4, &block).ok().unwrap().len(), 4 * 4 * 4);
+
+        assert!(convert_image(BfImageFormat::Rgba8, BfImageFormat::Dxt1, 4, 4, &[0; 64]).is_err());
+        assert!(convert_image(BfImageFormat::Dxt1, BfImageFormat::Rgba8, 4, 4, &[0; 4]).is_err());
+    }
+
+    #[test]
+    fn prepare_with_fallback() {
+        // array of two 2x2 layers with two mip-maps
+        let mut additional = BfImageAdditional::new(2, 2, BfImageFormat::Rgb8 as u8);
+        additional.dimension = ImageDimension::Array;
+        additional.depth = 2;
+        let payload: Vec<u8> = (0..30).collect();
+        let bytes = BfWriter::new(Kind::Image, additional.into_u64())
+            .payload(payload.clone())
+            .into_bytes()
+            .unwrap();
+        let file = load_bf_from_bytes(&bytes).ok().unwrap();
+        let image = BfImage::new(&file).ok().unwrap();
+
+        let support = FormatSupport::from_properties(Features::empty(), sampled(&[Format::Rgb8Unorm, Format::Rgba8Unorm]));
+        let prepared = support.prepare(&image).ok().unwrap();
+        assert_eq!(prepared.format, BfImageFormat::Rgb8);
+        assert!(prepared.mips.iter().all(|(_, _, data)| matches!(data, Cow::Borrowed(_))));
+        assert_eq!(prepared.mips[1].2.as_ref(), &payload[24..]);
+
+        let support = FormatSupport::from_properties(Features::empty(), sampled(&[Format::Rgba8Unorm]));
+        let prepared = support.prepare(&image).ok().unwrap();
+        assert_eq!(prepared.format, BfImageFormat::Rgba8);
+        assert_eq!(prepared.mips.iter().map(|(w, h, d)| (*w, *h, d.len())).collect::<Vec<_>>(), vec![(2, 2, 32), (1, 1, 8)]);
+        assert_eq!(&prepared.mips[0].2[12..20], &[9, 10, 11, 255, 12, 13, 14, 255]);
+        assert_eq!(prepared.mips[1].2.as_ref(), &[24, 25, 26, 255, 27, 28, 29, 255]);
+
+        assert_matches!(FormatSupport::default().prepare(&image), Err(Error::UnsupportedImageFormat(f)) if f == BfImageFormat::Rgb8 as u8);
+    }
+}
@@ -1,8 +1,9 @@
-#[macro_use]
-extern crate zerocopy_derive;
-
 // Export all the modules
 pub mod bf;
 pub mod content;
 pub mod perf;
-pub mod cli;
\ No newline at end of file
+pub mod cli;
+pub mod formats;
+pub mod bc;
+pub mod mesh;
+pub mod mikktspace;
@@ -6,11 +6,13 @@
 use gfx_backend_vulkan as back;
 
 use arrayvec::ArrayVec;
-use gfx_hal::adapter::PhysicalDevice;
-use gfx_hal::command::{ClearColor, ClearValue, CommandBuffer, MultiShot, Primary};
+use gfx_hal::adapter::{MemoryTypeId, PhysicalDevice};
+use gfx_hal::buffer;
+use gfx_hal::command::{BufferImageCopy, ClearColor, ClearValue, CommandBuffer, MultiShot, OneShot, Primary};
 use gfx_hal::device::Device;
 use gfx_hal::format::{Aspects, ChannelType, Format, Swizzle};
-use gfx_hal::image::{Extent, Layout, SubresourceRange, Usage, ViewKind};
+use gfx_hal::image::{Access, Extent, Kind, Layout, Offset, SubresourceLayers, SubresourceRange, Tiling, Usage, ViewCapabilities, ViewKind};
+use gfx_hal::memory::{Barrier, Dependencies, Properties};
 use gfx_hal::pass::{Attachment, AttachmentLoadOp, AttachmentOps, AttachmentStoreOp, SubpassDesc};
 use gfx_hal::pool::CommandPoolCreateFlags;
 use gfx_hal::pso::{PipelineStage, Rect};
@@ -26,6 +28,8 @@ use std::mem::ManuallyDrop;
 use std::time::Instant;
 use winit::dpi::LogicalSize;
 use winit::{Event, EventsLoop, Window, WindowBuilder, WindowEvent};
+use vk_test::bf::{load_bf_from_bytes, BfImage, ImageDimension};
+use vk_test::formats::{hal_format, FormatSupport};
 
 #[derive(Debug)]
 struct WinitState {
@@ -58,10 +62,20 @@ impl Default for WinitState {
     }
 }
 
+/// Sampled image uploaded to the device.
+#[derive(Debug)]
+struct Texture {
+    image: <back::Backend as Backend>::Image,
+    memory: <back::Backend as Backend>::Memory,
+    view: <back::Backend as Backend>::ImageView,
+}
+
 #[derive(Debug)]
 struct HalState {
     current_frame: usize,
     frames_in_flight: usize,
+    image_formats: FormatSupport,
+    textures: Vec<Texture>,
     in_flight_fences: Vec<<back::Backend as Backend>::Fence>,
     render_finished_semaphores: Vec<<back::Backend as Backend>::Semaphore>,
     image_available_semaphores: Vec<<back::Backend as Backend>::Semaphore>,
@@ -74,7 +88,7 @@ struct HalState {
     queue_group: ManuallyDrop<QueueGroup<back::Backend, Graphics>>,
     swapchain: ManuallyDrop<<back::Backend as Backend>::Swapchain>,
     device: ManuallyDrop<back::Device>,
-    _adapter: Adapter<back::Backend>,
+    adapter: Adapter<back::Backend>,
     _surface: <back::Backend as Backend>::Surface,
     _instance: ManuallyDrop<back::Instance>,
 }
@@ -95,6 +109,11 @@ impl HalState {
             })
             .ok_or("No suitable adapter found!")?;
 
+        /* Enable block compressed formats when the adapter supports them. */
+        let features = adapter.physical_device.features() & Features::FORMAT_BC;
+        let image_formats = FormatSupport::query::<back::Backend>(&adapter.physical_device, features);
+        info!("Supported image formats: {:?}", image_formats.supported().collect::<Vec<_>>());
+
         let (device, queue_group) = {
             let queue_family = &adapter
                 .queue_families
@@ -105,7 +124,7 @@ impl HalState {
                 let physical_device = &adapter.physical_device;
 
                 physical_device
-                    .open(&[(&queue_family, &[1.0; 1])], Features::empty())
+                    .open(&[(&queue_family, &[1.0; 1])], features)
                     .map_err(|_| "Couldn't open the PhysicalDevice!")?
             };
             let queue_group = queues
@@ -297,7 +316,7 @@ impl HalState {
         Ok(Self {
             _instance: ManuallyDrop::new(instance),
             _surface: surface,
-            _adapter: adapter,
+            adapter,
             device: ManuallyDrop::new(device),
             queue_group: ManuallyDrop::new(queue_group),
             swapchain: ManuallyDrop::new(swapchain),
@@ -312,9 +331,176 @@ impl HalState {
             in_flight_fences,
             frames_in_flight,
             current_frame: 0,
+            image_formats,
+            textures: vec![],
         })
     }
 
+    /// Returns the first memory type allowed by `type_mask` which has all specified properties.
+    fn memory_type(&self, type_mask: u64, properties: Properties) -> Result<MemoryTypeId, &'static str> {
+        self.adapter
+            .physical_device
+            .memory_properties()
+            .memory_types
+            .iter()
+            .enumerate()
+            .find(|(id, memory_type)| type_mask & (1 << id) != 0 && memory_type.properties.contains(properties))
+            .map(|(id, _)| MemoryTypeId(id))
+            .ok_or("Couldn't find a suitable memory type!")
+    }
+
+    /// Uploads all mip-maps of the image to a new sampled texture. Images in formats
+    /// the device cannot sample are converted to their fallback format first.
+    pub fn upload_image(&mut self, image: &BfImage) -> Result<(), &'static str> {
+        let prepared = self.image_formats
+            .prepare(image)
+            .map_err(|_| "Image cannot be converted to a format supported by the device!")?;
+        let format = hal_format(prepared.format);
+        let (view_kind, view_caps) = match image.dimension() {
+            ImageDimension::D2 => (ViewKind::D2, ViewCapabilities::empty()),
+            ImageDimension::Array => (ViewKind::D2Array, ViewCapabilities::empty()),
+            ImageDimension::Cube if image.layer_count() == 6 => (ViewKind::Cube, ViewCapabilities::KIND_CUBE),
+            ImageDimension::Cube => (ViewKind::CubeArray, ViewCapabilities::KIND_CUBE),
+            ImageDimension::D3 => return Err("Volume images are not supported yet!"),
+        };
+        let (width, height, _) = prepared.mips[0];
+        let levels = prepared.mips.len() as u8;
+        let layers = image.layer_count() as u16;
+        let range = SubresourceRange {
+            aspects: Aspects::COLOR,
+            levels: 0..levels,
+            layers: 0..layers,
+        };
+
+        /* Offsets of mip-maps in the staging buffer must be multiple of 4 and of the texel
+           block size, 48 is a multiple of block sizes of all the formats. */
+        let mut offsets = vec![];
+        let mut size = 0;
+        for (_, _, data) in prepared.mips.iter() {
+            offsets.push(size);
+            size = (size + data.len()).div_ceil(48) * 48;
+        }
+
+        unsafe {
+            // WRITE THE DATA TO STAGING BUFFER
+            let mut staging = self
+                .device
+                .create_buffer(size as u64, buffer::Usage::TRANSFER_SRC)
+                .map_err(|_| "Couldn't create a staging buffer!")?;
+            let requirements = self.device.get_buffer_requirements(&staging);
+            let memory_type = self.memory_type(requirements.type_mask, Properties::CPU_VISIBLE | Properties::COHERENT)?;
+            let staging_memory = self
+                .device
+                .allocate_memory(memory_type, requirements.size)
+                .map_err(|_| "Couldn't allocate staging buffer memory!")?;
+            self.device
+                .bind_buffer_memory(&staging_memory, 0, &mut staging)
+                .map_err(|_| "Couldn't bind the staging buffer memory!")?;
+            let mut data_target = self
+                .device
+                .acquire_mapping_writer::<u8>(&staging_memory, 0..requirements.size)
+                .map_err(|_| "Failed to acquire a memory writer!")?;
+            for ((_, _, data), offset) in prepared.mips.iter().zip(offsets.iter()) {
+                data_target[*offset..*offset + data.len()].copy_from_slice(data);
+            }
+            self.device
+                .release_mapping_writer(data_target)
+                .map_err(|_| "Couldn't release the mapping writer!")?;
+
+            // CREATE THE IMAGE
+            let mut texture = self
+                .device
+                .create_image(
+                    Kind::D2(width as u32, height as u32, layers, 1),
+                    levels,
+                    format,
+                    Tiling::Optimal,
+                    Usage::TRANSFER_DST | Usage::SAMPLED,
+                    view_caps,
+                )
+                .map_err(|_| "Couldn't create the image!")?;
+            let requirements = self.device.get_image_requirements(&texture);
+            let memory_type = self.memory_type(requirements.type_mask, Properties::DEVICE_LOCAL)?;
+            let memory = self
+                .device
+                .allocate_memory(memory_type, requirements.size)
+                .map_err(|_| "Couldn't allocate image memory!")?;
+            self.device
+                .bind_image_memory(&memory, 0, &mut texture)
+                .map_err(|_| "Couldn't bind the image memory!")?;
+
+            // COPY THE MIP-MAPS
+            let mut buffer = self.command_pool.acquire_command_buffer::<OneShot>();
+            buffer.begin();
+            buffer.pipeline_barrier(
+                PipelineStage::TOP_OF_PIPE..PipelineStage::TRANSFER,
+                Dependencies::empty(),
+                &[Barrier::Image {
+                    states: (Access::empty(), Layout::Undefined)..(Access::TRANSFER_WRITE, Layout::TransferDstOptimal),
+                    target: &texture,
+                    families: None,
+                    range: range.clone(),
+                }],
+            );
+            for (level, ((w, h, _), offset)) in prepared.mips.iter().zip(offsets.iter()).enumerate() {
+                buffer.copy_buffer_to_image(
+                    &staging,
+                    &texture,
+                    Layout::TransferDstOptimal,
+                    &[BufferImageCopy {
+                        buffer_offset: *offset as u64,
+                        buffer_width: 0,
+                        buffer_height: 0,
+                        image_layers: SubresourceLayers {
+                            aspects: Aspects::COLOR,
+                            level: level as u8,
+                            layers: 0..layers,
+                        },
+                        image_offset: Offset::ZERO,
+                        image_extent: Extent {
+                            width: *w as u32,
+                            height: *h as u32,
+                            depth: 1,
+                        },
+                    }],
+                );
+            }
+            buffer.pipeline_barrier(
+                PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+                Dependencies::empty(),
+                &[Barrier::Image {
+                    states: (Access::TRANSFER_WRITE, Layout::TransferDstOptimal)..(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+                    target: &texture,
+                    families: None,
+                    range: range.clone(),
+                }],
+            );
+            buffer.finish();
+
+            let fence = self
+                .device
+                .create_fence(false)
+                .map_err(|_| "Cannot create a fence!")?;
+            self.queue_group.queues[0].submit_nosemaphores(Some(&buffer), Some(&fence));
+            self.device
+                .wait_for_fence(&fence, u64::MAX)
+                .map_err(|_| "Failed to wait on the fence!")?;
+            self.device.destroy_fence(fence);
+            self.command_pool.free(Some(buffer));
+            self.device.destroy_buffer(staging);
+            self.device.free_memory(staging_memory);
+
+            let view = self
+                .device
+                .create_image_view(&texture, view_kind, format, Swizzle::NO, range)
+                .map_err(|_| "Couldn't create the image_view for the image!")?;
+            self.textures.push(Texture { image: texture, memory, view });
+        }
+
+        info!("Uploaded {}x{} image with {} mip-maps as {:?}", width, height, levels, prepared.format);
+        Ok(())
+    }
+
     /*
     fn create_pipeline(
         device: &mut back::Device, extent: Extent2D, render_pass: &<back::Backend as Backend>::RenderPass,
@@ -499,6 +685,12 @@ impl Drop for HalState {
                 self.device.destroy_image_view(image_view);
             }
 
+            for texture in self.textures.drain(..) {
+                self.device.destroy_image_view(texture.view);
+                self.device.destroy_image(texture.image);
+                self.device.free_memory(texture.memory);
+            }
+
             // LAST RESORT STYLE CODE, NOT TO BE IMITATED LIGHTLY
             use core::ptr::read;
             self.device.destroy_command_pool(
@@ -602,6 +794,18 @@ fn main() {
         start.elapsed().as_millis()
     );
 
+    /* Upload the image passed as the first argument, converted if the device cannot sample it. */
+    if let Some(path) = std::env::args().nth(1) {
+        let bytes = std::fs::read(&path).expect("cannot read image file");
+        let file = load_bf_from_bytes(&bytes)
+            .map_err(|e| panic!("cannot load image file: {}", e))
+            .unwrap();
+        let image = BfImage::new(&file)
+            .map_err(|e| panic!("cannot load image file: {}", e))
+            .unwrap();
+        hal_state.upload_image(&image).expect("cannot upload image");
+    }
+
     let mut local_state = LocalState::default();
 
     loop {
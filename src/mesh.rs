@@ -0,0 +1,1233 @@
+//! Processing of triangle meshes before they are stored as Geometry kind.
+
+use std::collections::HashMap;
+
+use wavefront_obj::obj::{Object, ObjSet, Primitive, Shape, VTNIndex};
+
+use crate::bf::{BfGeometryBuilder, GeometryListType};
+use crate::mikktspace;
+
+/// Indexed triangle mesh with non-interleaved vertex attributes. Each vertex
+/// attribute that is present has the same length as `positions`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Option<Vec<[f32; 3]>>,
+    pub uvs: Option<Vec<[f32; 2]>>,
+    /// Tangents with sign of the bitangent in `w` (see `generate_tangents`).
+    pub tangents: Option<Vec<[f32; 4]>>,
+    /// Three indices per triangle, counter-clockwise.
+    pub indices: Vec<u32>,
+}
+
+/// Which triangles are smoothed together when normals are generated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalSmoothing {
+    /// Each triangle has its own normal.
+    Flat,
+    /// All triangles around a vertex are smoothed.
+    Smooth,
+    /// Triangles are smoothed when the angle between them is at most the
+    /// specified angle (in degrees).
+    Crease(f32),
+}
+
+impl Mesh {
+    /// Converts all objects of the OBJ file into one indexed mesh. Corners of
+    /// faces with the same position, normal and uv share the vertex. Polygons
+    /// are already triangulated by the parser, points and lines are skipped.
+    ///
+    /// Normals (and uvs) are stored when at least one corner specifies them,
+    /// the corners without them get zeros.
+    pub fn from_obj(obj: &ObjSet) -> Self {
+        let triangles = || obj_triangles(obj).map(|(o, t, _)| (o, t));
+
+        let has_normals = triangles().any(|(_, t)| t.iter().any(|c| c.2.is_some()));
+        let has_uvs = triangles().any(|(_, t)| t.iter().any(|c| c.1.is_some()));
+
+        let mut mesh = Mesh {
+            normals: if has_normals { Some(vec![]) } else { None },
+            uvs: if has_uvs { Some(vec![]) } else { None },
+            ..Mesh::default()
+        };
+
+        // vertices are compared by the bits of their values
+        let mut unique: HashMap<[u32; 8], u32> = HashMap::new();
+        for (object, triangle) in triangles() {
+            for (v, t, n) in triangle.iter() {
+                let p = &object.vertices[*v];
+                let position = [p.x as f32, p.y as f32, p.z as f32];
+                let normal = n.map(|n| &object.normals[n]).map_or([0.0; 3], |n| [n.x as f32, n.y as f32, n.z as f32]);
+                let uv = t.map(|t| &object.tex_vertices[t]).map_or([0.0; 2], |t| [t.u as f32, t.v as f32]);
+
+                let key = [
+                    position[0].to_bits(), position[1].to_bits(), position[2].to_bits(),
+                    normal[0].to_bits(), normal[1].to_bits(), normal[2].to_bits(),
+                    uv[0].to_bits(), uv[1].to_bits(),
+                ];
+                let next = mesh.positions.len() as u32;
+                let index = *unique.entry(key).or_insert(next);
+                if index == next {
+                    mesh.positions.push(position);
+                    if let Some(normals) = mesh.normals.as_mut() { normals.push(normal); }
+                    if let Some(uvs) = mesh.uvs.as_mut() { uvs.push(uv); }
+                }
+                mesh.indices.push(index);
+            }
+        }
+
+        mesh
+    }
+
+    /// Returns smoothing groups of each triangle of the mesh created by
+    /// `from_obj` from the same file. Group `0` is the same as `s off`.
+    pub fn obj_smoothing_groups(obj: &ObjSet) -> Vec<Vec<u32>> {
+        obj_triangles(obj)
+            .map(|(_, _, s)| s.smoothing_groups.iter().copied().filter(|g| *g != 0).collect())
+            .collect()
+    }
+
+    #[inline]
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    #[inline]
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Replaces normals of the mesh with normals computed from its triangles.
+    ///
+    /// Normal of each corner is the average of normals of the triangles
+    /// around the same position (weighted by the angle of the triangle at
+    /// the position) that are smoothed with the triangle of the corner.
+    /// Triangles are smoothed when they are in the same smoothing group (if
+    /// `smoothing_groups` of triangles are specified and any triangle has one)
+    /// and their angle is allowed by `smoothing`. Vertices are split along
+    /// hard edges and merged when they only differed by the old normal.
+    /// Tangents are removed, vertices not used by any triangle are removed.
+    pub fn generate_normals(&mut self, smoothing: NormalSmoothing, smoothing_groups: Option<&[Vec<u32>]>) {
+        let positions = &self.positions;
+        let groups = smoothing_groups.filter(|g| g.iter().any(|g| !g.is_empty()));
+        if let Some(groups) = groups {
+            assert_eq!(groups.len(), self.triangle_count(), "smoothing groups must be specified for each triangle");
+        }
+        let min_cos = match smoothing {
+            NormalSmoothing::Flat => f32::INFINITY,
+            NormalSmoothing::Smooth => f32::NEG_INFINITY,
+            NormalSmoothing::Crease(degrees) => degrees.to_radians().cos(),
+        };
+
+        let face_normals: Vec<[f32; 3]> = self.indices.chunks_exact(3)
+            .map(|t| {
+                let (p0, p1, p2) = (positions[t[0] as usize], positions[t[1] as usize], positions[t[2] as usize]);
+                normalize(cross(sub(p1, p0), sub(p2, p0)))
+            })
+            .collect();
+        let corner_angle = |corner: usize| {
+            let (triangle, i) = (corner / 3, corner % 3);
+            let p = |k: usize| positions[self.indices[triangle * 3 + k] as usize];
+            let (v1, v2) = (normalize(sub(p((i + 2) % 3), p(i))), normalize(sub(p((i + 1) % 3), p(i))));
+            dot(v1, v2).clamp(-1.0, 1.0).acos()
+        };
+
+        // corners around each position (vertices with different uvs share the position)
+        let mut around: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (corner, index) in self.indices.iter().enumerate() {
+            let p = positions[*index as usize];
+            around.entry([p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]).or_default().push(corner);
+        }
+
+        let smoothed = |a: usize, b: usize| {
+            let (fa, fb) = (face_normals[a], face_normals[b]);
+            let degenerate = dot(fa, fa) == 0.0 || dot(fb, fb) == 0.0;
+            a == b || (groups.is_none_or(|g| g[a].iter().any(|x| g[b].contains(x)))
+                && (degenerate || dot(fa, fb) >= min_cos))
+        };
+
+        let mut normals = vec![[0.0; 3]; self.indices.len()];
+        for corners in around.values() {
+            for &corner in corners.iter() {
+                let sum = corners.iter()
+                    .filter(|c| smoothed(corner / 3, **c / 3))
+                    .fold([0.0; 3], |sum, c| add(sum, scale(face_normals[c / 3], corner_angle(*c))));
+                normals[corner] = normalize(sum);
+            }
+        }
+
+        // vertices are split (and merged) by the new normals
+        let mut unique: HashMap<[u32; 8], u32> = HashMap::new();
+        let mut mesh = Mesh {
+            normals: Some(vec![]),
+            uvs: self.uvs.as_ref().map(|_| vec![]),
+            ..Mesh::default()
+        };
+        for (corner, index) in self.indices.iter().enumerate() {
+            let (position, normal) = (positions[*index as usize], normals[corner]);
+            let uv = self.uvs.as_ref().map_or([0.0; 2], |u| u[*index as usize]);
+            let key = [
+                position[0].to_bits(), position[1].to_bits(), position[2].to_bits(),
+                normal[0].to_bits(), normal[1].to_bits(), normal[2].to_bits(),
+                uv[0].to_bits(), uv[1].to_bits(),
+            ];
+
+            let next = mesh.positions.len() as u32;
+            let new = *unique.entry(key).or_insert(next);
+            if new == next {
+                mesh.positions.push(position);
+                mesh.normals.as_mut().unwrap().push(normal);
+                if let Some(uvs) = mesh.uvs.as_mut() { uvs.push(uv); }
+            }
+            mesh.indices.push(new);
+        }
+
+        *self = mesh;
+    }
+
+    /// Generates tangents the same way as MikkTSpace (with its default angular
+    /// threshold, see `mikktspace` module) so normal maps baked with it are
+    /// reproduced. Does nothing when the mesh does not have normals and uvs.
+    ///
+    /// Tangent of each corner is the average of tangents of the triangles
+    /// around its vertex that are connected to it over shared edges and have
+    /// the same orientation in uv space (projected to the plane of the normal
+    /// and weighted by the angle of the triangle at the vertex). Triangles with
+    /// mirrored uvs have opposite sign of the bitangent (`bitangent = sign *
+    /// cross(normal, tangent)`). Vertices used by corners with different
+    /// tangents are split, vertices not used by any triangle are removed.
+    pub fn generate_tangents(&mut self) {
+        let (normals, uvs) = match (&self.normals, &self.uvs) {
+            (Some(normals), Some(uvs)) => (normals, uvs),
+            _ => return,
+        };
+        let tangents = mikktspace::generate(&self.positions, normals, uvs, &self.indices);
+
+        let mut unique: HashMap<(u32, [u32; 3], bool), u32> = HashMap::new();
+        let mut mesh = Mesh { tangents: Some(vec![]), normals: Some(vec![]), uvs: Some(vec![]), ..Mesh::default() };
+        for (index, (tangent, orientation)) in self.indices.iter().zip(tangents) {
+            let key = (*index, [tangent[0].to_bits(), tangent[1].to_bits(), tangent[2].to_bits()], orientation);
+            let next = mesh.positions.len() as u32;
+            let new = *unique.entry(key).or_insert(next);
+            if new == next {
+                let sign = if orientation { 1.0 } else { -1.0 };
+                mesh.positions.push(self.positions[*index as usize]);
+                mesh.normals.as_mut().unwrap().push(normals[*index as usize]);
+                mesh.uvs.as_mut().unwrap().push(uvs[*index as usize]);
+                mesh.tangents.as_mut().unwrap().push([tangent[0], tangent[1], tangent[2], sign]);
+            }
+            mesh.indices.push(new);
+        }
+
+        *self = mesh;
+    }
+
+    /// Returns mesh with the same triangles but without the vertices that are
+    /// not referenced by the indices. Vertices keep their relative order.
+    pub fn compact(&self) -> Mesh {
+        let mut used = vec![false; self.vertex_count()];
+        for index in self.indices.iter() {
+            used[*index as usize] = true;
+        }
+
+        let mut remap = vec![0u32; self.vertex_count()];
+        let mut next = 0;
+        for (vertex, used) in used.iter().enumerate() {
+            if *used {
+                remap[vertex] = next;
+                next += 1;
+            }
+        }
+
+        let keep = |v: &usize| used[*v];
+        Mesh {
+            positions: (0..self.vertex_count()).filter(keep).map(|v| self.positions[v]).collect(),
+            normals: self.normals.as_ref().map(|n| (0..n.len()).filter(keep).map(|v| n[v]).collect()),
+            uvs: self.uvs.as_ref().map(|u| (0..u.len()).filter(keep).map(|v| u[v]).collect()),
+            tangents: self.tangents.as_ref().map(|t| (0..t.len()).filter(keep).map(|v| t[v]).collect()),
+            indices: self.indices.iter().map(|i| remap[*i as usize]).collect(),
+        }
+    }
+
+    /// Simplifies the mesh with quadric error metric until it has at most
+    /// `target_triangles` triangles or no more edges can be collapsed.
+    ///
+    /// Edges are collapsed into one of their existing vertices so the returned
+    /// indices refer to vertices of this mesh (all LOD levels can share the
+    /// vertex buffers). Vertices on borders of the mesh and on attribute seams
+    /// (vertices with the same position but different normal or uv) never move.
+    ///
+    /// Returns the indices and the error as root mean squared distance of the
+    /// moved vertices from the planes of the original triangles around them.
+    pub fn simplify(&self, target_triangles: usize) -> (Vec<u32>, f32) {
+        simplify(&self.positions, &self.indices, target_triangles)
+    }
+
+    /// Reorders vertices in the order they are first used by the indices so
+    /// vertex buffers are read sequentially. Indices of the mesh and of the
+    /// `lods` (which share the vertices) are updated, unused vertices are moved
+    /// to the end.
+    pub fn optimize_vertex_fetch(&mut self, lods: &mut [Vec<u32>]) {
+        const UNUSED: u32 = u32::MAX;
+        let mut remap = vec![UNUSED; self.vertex_count()];
+        let mut next = 0;
+        for index in self.indices.iter().chain(lods.iter().flatten()) {
+            if remap[*index as usize] == UNUSED {
+                remap[*index as usize] = next;
+                next += 1;
+            }
+        }
+        for r in remap.iter_mut().filter(|r| **r == UNUSED) {
+            *r = next;
+            next += 1;
+        }
+
+        fn reorder<T: Copy + Default>(data: &[T], remap: &[u32]) -> Vec<T> {
+            let mut result = vec![T::default(); data.len()];
+            for (old, new) in remap.iter().enumerate() {
+                result[*new as usize] = data[old];
+            }
+            result
+        }
+
+        self.positions = reorder(&self.positions, &remap);
+        self.normals = self.normals.as_ref().map(|n| reorder(n, &remap));
+        self.uvs = self.uvs.as_ref().map(|u| reorder(u, &remap));
+        self.tangents = self.tangents.as_ref().map(|t| reorder(t, &remap));
+        for index in self.indices.iter_mut().chain(lods.iter_mut().flatten()) {
+            *index = remap[*index as usize];
+        }
+    }
+
+    /// Serializes the mesh to payload of Geometry kind with the smallest index
+    /// type that can hold all indices.
+    pub fn to_geometry(&self) -> Vec<u8> {
+        self.to_geometry_with_lods(&[])
+    }
+
+    /// Serializes the mesh to payload of Geometry kind with indices of the mesh
+    /// as LOD 0 and `lods` as the following levels (sharing the vertices).
+    pub fn to_geometry_with_lods(&self, lods: &[Vec<u32>]) -> Vec<u8> {
+        let mut builder = BfGeometryBuilder::new()
+            .list(GeometryListType::Positions, &self.positions);
+        if let Some(normals) = &self.normals {
+            builder = builder.list(GeometryListType::Normals, normals);
+        }
+        if let Some(uvs) = &self.uvs {
+            builder = builder.list(GeometryListType::UV1, uvs);
+        }
+        if let Some(tangents) = &self.tangents {
+            builder = builder.list(GeometryListType::SignedTangents, tangents);
+        }
+        builder = builder.indices(&self.indices);
+        for (level, indices) in lods.iter().enumerate() {
+            builder = builder.lod_indices(level as u16 + 1, indices);
+        }
+        builder.build()
+    }
+}
+
+/// Size of the simulated FIFO post-transform vertex cache.
+pub const VERTEX_CACHE_SIZE: usize = 16;
+
+/// Statistics of post-transform vertex cache efficiency of list of triangles.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CacheStats {
+    /// Average cache miss ratio, transformed vertices per triangle (from 0.5 to 3).
+    pub acmr: f32,
+    /// Average transform to vertex ratio, transformed vertices per used vertex (1 is optimal).
+    pub atvr: f32,
+}
+
+/// Simulates FIFO vertex cache with `cache_size` entries while drawing the triangles.
+pub fn cache_stats(indices: &[u32], vertex_count: usize, cache_size: usize) -> CacheStats {
+    let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+    let mut used = vec![false; vertex_count];
+    let mut misses = 0;
+    for index in indices.iter() {
+        used[*index as usize] = true;
+        if !cache.contains(index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(*index);
+        }
+    }
+
+    let triangles = (indices.len() / 3).max(1);
+    let vertices = used.iter().filter(|x| **x).count().max(1);
+    CacheStats {
+        acmr: misses as f32 / triangles as f32,
+        atvr: misses as f32 / vertices as f32,
+    }
+}
+
+/// Reorders triangles for post-transform vertex cache of `cache_size` entries
+/// with Tipsify algorithm (Sander, Nehab, Barczak: Fast Triangle Reordering for
+/// Vertex Locality and Reduced Overdraw). Triangles keep their winding.
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize, cache_size: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+
+    // triangles around each vertex
+    let mut live = vec![0usize; vertex_count];
+    for index in indices.iter() {
+        live[*index as usize] += 1;
+    }
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for v in 0..vertex_count {
+        offsets[v + 1] = offsets[v] + live[v];
+    }
+    let mut adjacency = vec![0usize; indices.len()];
+    let mut filled = offsets.clone();
+    for (i, index) in indices.iter().enumerate() {
+        adjacency[filled[*index as usize]] = i / 3;
+        filled[*index as usize] += 1;
+    }
+
+    let mut cache_time = vec![0usize; vertex_count];
+    let mut time = cache_size + 1;
+    let mut emitted = vec![false; triangle_count];
+    let mut dead_end: Vec<u32> = vec![];
+    let mut cursor = 0;
+    let mut result = Vec::with_capacity(indices.len());
+
+    let mut fanning = (0..vertex_count).find(|v| live[*v] > 0);
+    while let Some(vertex) = fanning {
+        let mut candidates = vec![];
+        for t in adjacency[offsets[vertex]..offsets[vertex + 1]].iter() {
+            if emitted[*t] {
+                continue;
+            }
+            for v in indices[t * 3..t * 3 + 3].iter() {
+                result.push(*v);
+                dead_end.push(*v);
+                candidates.push(*v);
+                live[*v as usize] -= 1;
+                if time - cache_time[*v as usize] > cache_size {
+                    cache_time[*v as usize] = time;
+                    time += 1;
+                }
+            }
+            emitted[*t] = true;
+        }
+
+        // prefer vertices that stay in cache while their remaining triangles are emitted
+        let mut best = None;
+        let mut priority = 0;
+        for v in candidates.iter().map(|v| *v as usize).filter(|v| live[*v] > 0) {
+            let age = time - cache_time[v];
+            if age + 2 * live[v] <= cache_size && (best.is_none() || age > priority) {
+                best = Some(v);
+                priority = age;
+            }
+        }
+
+        fanning = best.or_else(|| {
+            while let Some(v) = dead_end.pop() {
+                if live[v as usize] > 0 {
+                    return Some(v as usize);
+                }
+            }
+            while cursor < vertex_count {
+                if live[cursor] > 0 {
+                    return Some(cursor);
+                }
+                cursor += 1;
+            }
+            None
+        });
+    }
+
+    result
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+/// Returns the vector with unit length or zero vector if it has zero length.
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = dot(a, a).sqrt();
+    if length > 0.0 { scale(a, 1.0 / length) } else { [0.0; 3] }
+}
+
+/// Returns all triangles of the OBJ file (with their object and shape) in
+/// the order they are stored in the mesh.
+fn obj_triangles(obj: &ObjSet) -> impl Iterator<Item = (&Object, [&VTNIndex; 3], &Shape)> {
+    obj.objects.iter()
+        .flat_map(|o| o.geometry.iter().flat_map(move |g| g.shapes.iter().map(move |s| (o, s))))
+        .filter_map(|(o, s)| match &s.primitive {
+            Primitive::Triangle(a, b, c) => Some((o, [a, b, c], s)),
+            _ => None,
+        })
+}
+
+/// Returns map from each vertex to the first vertex with the same position.
+fn position_remap(positions: &[[f32; 3]]) -> Vec<u32> {
+    let mut first: HashMap<[u32; 3], u32> = HashMap::new();
+    positions.iter()
+        .enumerate()
+        .map(|(v, p)| *first.entry([p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]).or_insert(v as u32))
+        .collect()
+}
+
+/// Error quadric (symmetric 4x4 matrix stored as 10 coefficients) of the sum
+/// of squared distances to a set of planes weighted by area of the triangles.
+#[derive(Debug, Copy, Clone, Default)]
+struct Quadric {
+    m: [f64; 10],
+    weight: f64,
+}
+
+impl Quadric {
+    fn from_triangle(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> Self {
+        let n = cross(sub(b, a), sub(c, a));
+        let length = dot(n, n).sqrt() as f64;
+        if length == 0.0 {
+            return Quadric::default();
+        }
+
+        let (x, y, z) = (n[0] as f64 / length, n[1] as f64 / length, n[2] as f64 / length);
+        let d = -(x * a[0] as f64 + y * a[1] as f64 + z * a[2] as f64);
+        let weight = length * 0.5;
+        Quadric {
+            m: [x * x, x * y, x * z, x * d, y * y, y * z, y * d, z * z, z * d, d * d].map(|v| v * weight),
+            weight,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.m.iter_mut().zip(other.m.iter()) {
+            *a += b;
+        }
+        self.weight += other.weight;
+    }
+
+    /// Returns the mean squared distance of point `p` from the planes.
+    fn error(&self, p: [f32; 3]) -> f64 {
+        let (x, y, z) = (p[0] as f64, p[1] as f64, p[2] as f64);
+        let m = &self.m;
+        let sum = m[0] * x * x + 2.0 * m[1] * x * y + 2.0 * m[2] * x * z + 2.0 * m[3] * x
+            + m[4] * y * y + 2.0 * m[5] * y * z + 2.0 * m[6] * y
+            + m[7] * z * z + 2.0 * m[8] * z
+            + m[9];
+        if self.weight == 0.0 {
+            return 0.0;
+        }
+        // rounding can make the error slightly negative, nan is kept
+        let error = sum / self.weight;
+        if error < 0.0 { 0.0 } else { error }
+    }
+}
+
+/// Simplifies the triangles with greedy half-edge collapses ordered by the
+/// quadric error. Collapses are performed in passes, each vertex can be part
+/// of only one collapse in each pass so adjacency stays valid.
+fn simplify(positions: &[[f32; 3]], indices: &[u32], target_triangles: usize) -> (Vec<u32>, f32) {
+    let vertex_count = positions.len();
+    let remap = position_remap(positions);
+
+    // seams: more vertices share one position
+    let mut locked = vec![false; vertex_count];
+    let mut group_size = vec![0u32; vertex_count];
+    for v in remap.iter() {
+        group_size[*v as usize] += 1;
+    }
+
+    // borders: edges (between positions) used by only one triangle
+    let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+    for t in indices.chunks_exact(3) {
+        for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])].iter() {
+            let (a, b) = (remap[*a as usize], remap[*b as usize]);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    for ((a, b), count) in edges.iter() {
+        if *count == 1 {
+            locked[*a as usize] = true;
+            locked[*b as usize] = true;
+        }
+    }
+    for v in 0..vertex_count {
+        let group = remap[v] as usize;
+        if group_size[group] > 1 || locked[group] {
+            locked[v] = true;
+        }
+    }
+
+    let mut quadrics = vec![Quadric::default(); vertex_count];
+    for t in indices.chunks_exact(3) {
+        let quadric = Quadric::from_triangle(positions[t[0] as usize], positions[t[1] as usize], positions[t[2] as usize]);
+        for v in t.iter() {
+            quadrics[remap[*v as usize] as usize].add(&quadric);
+        }
+    }
+
+    let mut triangles: Vec<[u32; 3]> = indices.chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]])
+        .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+        .collect();
+    let mut max_error = 0.0f64;
+
+    while triangles.len() > target_triangles {
+        let mut adjacency: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+        for (i, t) in triangles.iter().enumerate() {
+            for v in t.iter() {
+                adjacency[*v as usize].push(i);
+            }
+        }
+
+        // unlocked vertices (their group has only this vertex) collapse into neighbours
+        let mut candidates: Vec<(f64, u32, u32)> = vec![];
+        for t in triangles.iter() {
+            for (from, to) in [(t[0], t[1]), (t[1], t[0]), (t[1], t[2]), (t[2], t[1]), (t[2], t[0]), (t[0], t[2])].iter() {
+                if !locked[*from as usize] {
+                    let mut quadric = quadrics[*from as usize];
+                    quadric.add(&quadrics[remap[*to as usize] as usize]);
+                    // non-finite positions (nan, inf or overflow) make the error meaningless
+                    let error = quadric.error(positions[*to as usize]);
+                    if error.is_finite() {
+                        candidates.push((error, *from, *to));
+                    }
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut touched = vec![false; vertex_count];
+        let mut collapse: Vec<u32> = (0..vertex_count as u32).collect();
+        let mut remaining = triangles.len();
+        for (error, from, to) in candidates {
+            if remaining <= target_triangles {
+                break;
+            }
+            if touched[from as usize] || touched[to as usize] {
+                continue;
+            }
+
+            // moving the vertex must not flip any of the remaining triangles
+            let around = &adjacency[from as usize];
+            let flips = around.iter()
+                .map(|i| triangles[*i])
+                .filter(|t| !t.contains(&to))
+                .any(|t| {
+                    let p = |v: u32| positions[v as usize];
+                    let moved = |v: u32| if v == from { p(to) } else { p(v) };
+                    let before = cross(sub(p(t[1]), p(t[0])), sub(p(t[2]), p(t[0])));
+                    let after = cross(sub(moved(t[1]), moved(t[0])), sub(moved(t[2]), moved(t[0])));
+                    dot(before, after) <= 0.0
+                });
+            if flips {
+                continue;
+            }
+
+            collapse[from as usize] = to;
+            remaining -= around.iter().filter(|i| triangles[**i].contains(&to)).count();
+            max_error = max_error.max(error);
+            let quadric = quadrics[from as usize];
+            quadrics[remap[to as usize] as usize].add(&quadric);
+            for t in around.iter() {
+                for v in triangles[*t].iter() {
+                    touched[*v as usize] = true;
+                }
+            }
+        }
+
+        let before = triangles.len();
+        triangles = triangles.iter()
+            .map(|t| [collapse[t[0] as usize], collapse[t[1] as usize], collapse[t[2] as usize]])
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+            .collect();
+        if triangles.len() == before {
+            break;
+        }
+    }
+
+    (triangles.iter().flat_map(|t| t.iter().cloned()).collect(), max_error.sqrt() as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+    use wavefront_obj::obj::parse;
+
+    use crate::bf::{Indices, load_geometry_from_bytes};
+    use crate::mesh::{CacheStats, Mesh, NormalSmoothing, VERTEX_CACHE_SIZE, cache_stats, normalize, optimize_vertex_cache};
+
+    const QUAD: &str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 1 0 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 5/2/1 3/3/1 4/4/1
+l 1 2
+";
+
+    fn mesh(obj: &str) -> Mesh {
+        Mesh::from_obj(&parse(obj).ok().unwrap())
+    }
+
+    #[test]
+    fn obj_quad_is_triangulated_and_deduplicated() {
+        let mesh = mesh(QUAD);
+
+        // the duplicate position is merged, the line is skipped
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.triangle_count(), 2);
+        assert_eq!(mesh.normals, Some(vec![[0.0, 0.0, 1.0]; 4]));
+        assert_eq!(mesh.uvs.as_ref().map(|u| u.len()), Some(4));
+
+        // indices point to the same corners as in the file
+        for (index, corner) in mesh.indices.iter().zip([3, 0, 1, 3, 1, 2].iter()) {
+            assert_eq!(mesh.positions[*index as usize], [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]][*corner]);
+        }
+    }
+
+    #[test]
+    fn obj_different_attributes_are_not_merged() {
+        let mesh = mesh("
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0 0
+vt 1 1
+f 1/1 2/1 3/1
+f 1/2 3/1 2/1
+");
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.normals, None);
+        // the parser makes fans around the last corner of each face
+        assert_eq!(mesh.indices, vec![0, 1, 2, 2, 3, 0]);
+    }
+
+    #[test]
+    fn obj_multiple_objects() {
+        let mesh = mesh("
+o a
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+o b
+v 0 0 0
+v 0 1 0
+v 0 0 1
+f 4 5 6
+");
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 1, 0]);
+    }
+
+    #[test]
+    fn compact_removes_unused_vertices() {
+        let mesh = Mesh {
+            positions: vec![[0.0; 3], [1.0; 3], [2.0; 3], [3.0; 3]],
+            uvs: Some(vec![[0.0; 2], [1.0; 2], [2.0; 2], [3.0; 2]]),
+            indices: vec![3, 1, 3],
+            ..Mesh::default()
+        };
+        let compact = mesh.compact();
+        assert_eq!(compact.positions, vec![[1.0; 3], [3.0; 3]]);
+        assert_eq!(compact.uvs, Some(vec![[1.0; 2], [3.0; 2]]));
+        assert_eq!(compact.indices, vec![1, 0, 1]);
+    }
+
+    /// Creates grid of `n` x `n` quads in range 0 to 1 with height from `z`.
+    fn grid(n: u32, z: impl Fn(f32, f32) -> f32) -> Mesh {
+        let mut mesh = Mesh::default();
+        for y in 0..=n {
+            for x in 0..=n {
+                let (fx, fy) = (x as f32 / n as f32, y as f32 / n as f32);
+                mesh.positions.push([fx, fy, z(fx, fy)]);
+            }
+        }
+        for y in 0..n {
+            for x in 0..n {
+                let v = y * (n + 1) + x;
+                mesh.indices.extend_from_slice(&[v, v + 1, v + n + 2, v, v + n + 2, v + n + 1]);
+            }
+        }
+        mesh
+    }
+
+    fn area_z(mesh: &Mesh, indices: &[u32]) -> Vec<f32> {
+        indices.chunks_exact(3)
+            .map(|t| {
+                let p = |i: usize| mesh.positions[t[i] as usize];
+                0.5 * ((p(1)[0] - p(0)[0]) * (p(2)[1] - p(0)[1]) - (p(1)[1] - p(0)[1]) * (p(2)[0] - p(0)[0]))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn simplify_flat_grid_keeps_borders() {
+        let mesh = grid(8, |_, _| 0.0);
+        let (indices, error) = mesh.simplify(0);
+
+        assert!(indices.len() / 3 < mesh.triangle_count() / 2);
+        assert_eq!(error, 0.0);
+
+        // covers the same area without flipped triangles
+        let areas = area_z(&mesh, &indices);
+        assert!(areas.iter().all(|a| *a > 0.0));
+        assert!((areas.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+        // all border vertices are still used
+        for (v, p) in mesh.positions.iter().enumerate() {
+            if p[0] == 0.0 || p[0] == 1.0 || p[1] == 0.0 || p[1] == 1.0 {
+                assert!(indices.contains(&(v as u32)), "border vertex {} was removed", v);
+            }
+        }
+    }
+
+    #[test]
+    fn simplify_keeps_seams() {
+        // the middle column is split into two vertices (like uv seam)
+        let mut mesh = grid(8, |_, _| 0.0);
+        let seam: Vec<u32> = (0..mesh.vertex_count() as u32).filter(|v| mesh.positions[*v as usize][0] == 0.5).collect();
+        for v in seam.iter() {
+            let copy = mesh.positions.len() as u32;
+            mesh.positions.push(mesh.positions[*v as usize]);
+            let positions = &mesh.positions;
+            for t in mesh.indices.chunks_exact_mut(3) {
+                let right = t.iter().any(|i| positions[*i as usize][0] > 0.5);
+                for i in t.iter_mut().filter(|i| **i == *v && right) {
+                    *i = copy;
+                }
+            }
+        }
+
+        let (indices, _) = mesh.simplify(0);
+        assert!(indices.len() / 3 < mesh.triangle_count() / 2);
+        for v in seam.iter() {
+            assert!(indices.contains(v), "seam vertex {} was removed", v);
+        }
+    }
+
+    #[test]
+    fn simplify_curved_surface_has_error() {
+        let mesh = grid(16, |x, y| (x * 3.0).sin() * (y * 3.0).cos() * 0.2);
+        let (half, half_error) = mesh.simplify(mesh.triangle_count() / 2);
+        let (quarter, quarter_error) = mesh.simplify(mesh.triangle_count() / 4);
+
+        assert!(half.len() / 3 <= mesh.triangle_count() / 2);
+        assert!(quarter.len() / 3 <= mesh.triangle_count() / 4);
+        assert!(half_error > 0.0);
+        assert!(quarter_error >= half_error);
+        assert!(quarter_error < 0.2);
+
+        // nothing to do
+        assert_eq!(mesh.simplify(mesh.triangle_count()), (mesh.indices.clone(), 0.0));
+    }
+
+    #[test]
+    fn simplify_non_finite_positions() {
+        for value in [f32::NAN, f32::INFINITY, f32::MAX].iter() {
+            let mut mesh = grid(8, |_, _| 0.0);
+            mesh.positions[40][2] = *value;
+            let (indices, error) = mesh.simplify(0);
+
+            assert!(indices.len() / 3 < mesh.triangle_count() / 2);
+            assert!(error.is_finite(), "error {} with position {}", error, value);
+            assert!(indices.contains(&40), "vertex with position {} was collapsed", value);
+        }
+    }
+
+    /// Returns triangles rotated so the smallest index is first (keeps winding).
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles: Vec<[u32; 3]> = indices.chunks_exact(3)
+            .map(|t| {
+                let first = (0..3).min_by_key(|i| t[*i]).unwrap();
+                [t[first], t[(first + 1) % 3], t[(first + 2) % 3]]
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    /// Deterministically shuffles triangles of the mesh.
+    fn shuffled(mut indices: Vec<u32>) -> Vec<u32> {
+        let count = indices.len() / 3;
+        let mut state = 12345u32;
+        for i in (1..count).rev() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let j = (state >> 8) as usize % (i + 1);
+            for k in 0..3 {
+                indices.swap(i * 3 + k, j * 3 + k);
+            }
+        }
+        indices
+    }
+
+    #[test]
+    fn vertex_cache_keeps_triangles() {
+        let mesh = grid(24, |_, _| 0.0);
+        let indices = shuffled(mesh.indices.clone());
+        let optimized = optimize_vertex_cache(&indices, mesh.vertex_count(), VERTEX_CACHE_SIZE);
+
+        assert_eq!(sorted_triangles(&optimized), sorted_triangles(&indices));
+
+        let before = cache_stats(&indices, mesh.vertex_count(), VERTEX_CACHE_SIZE);
+        let after = cache_stats(&optimized, mesh.vertex_count(), VERTEX_CACHE_SIZE);
+        assert!(after.acmr < before.acmr);
+        assert!(after.acmr < 1.0, "acmr {} is too high", after.acmr);
+        assert!(after.atvr < before.atvr);
+        assert!(after.atvr >= 1.0);
+
+        // unused vertices and empty lists
+        assert_eq!(optimize_vertex_cache(&[4, 2, 3], 8, VERTEX_CACHE_SIZE), vec![4, 2, 3]);
+        assert!(optimize_vertex_cache(&[], 8, VERTEX_CACHE_SIZE).is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn vertex_cache_never_changes_triangles(
+            triangles in prop::collection::vec((0u32..40, 0u32..40, 0u32..40), 0..200),
+            cache_size in 3usize..32,
+        ) {
+            let indices: Vec<u32> = triangles.iter().flat_map(|t| vec![t.0, t.1, t.2]).collect();
+            let optimized = optimize_vertex_cache(&indices, 40, cache_size);
+            prop_assert_eq!(sorted_triangles(&optimized), sorted_triangles(&indices));
+        }
+    }
+
+    #[test]
+    fn cache_stats_of_triangles() {
+        // two triangles sharing an edge
+        let stats = cache_stats(&[0, 1, 2, 2, 1, 3], 4, VERTEX_CACHE_SIZE);
+        assert_eq!(stats, CacheStats { acmr: 2.0, atvr: 1.0 });
+
+        // cache of one vertex misses every time
+        let stats = cache_stats(&[0, 1, 2, 2, 1, 3], 4, 1);
+        assert_eq!(stats, CacheStats { acmr: 2.5, atvr: 1.25 });
+    }
+
+    #[test]
+    fn vertex_fetch_keeps_triangles() {
+        let mut mesh = mesh(QUAD);
+        mesh.uvs = Some(vec![[0.0, 0.0], [1.0, 0.0], [2.0, 0.0], [3.0, 0.0]]);
+        mesh.indices = vec![3, 1, 2];
+        let mut lods = vec![vec![2, 0, 3]];
+        let corners = |mesh: &Mesh, indices: &[u32]| indices.iter()
+            .map(|i| (mesh.positions[*i as usize], mesh.uvs.as_ref().unwrap()[*i as usize]))
+            .collect::<Vec<_>>();
+        let (before, before_lod) = (corners(&mesh, &mesh.indices), corners(&mesh, &lods[0]));
+
+        mesh.optimize_vertex_fetch(&mut lods);
+
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        assert_eq!(lods, vec![vec![2, 3, 0]]);
+        assert_eq!(corners(&mesh, &mesh.indices), before);
+        assert_eq!(corners(&mesh, &lods[0]), before_lod);
+    }
+
+    #[test]
+    fn tangents_follow_uvs() {
+        let mut mesh = mesh(QUAD);
+        mesh.generate_tangents();
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.tangents, Some(vec![[1.0, 0.0, 0.0, 1.0]; 4]));
+
+        // rotated uvs rotate the tangent, mirrored uvs flip the sign
+        let mut mesh = self::mesh(QUAD);
+        for uv in mesh.uvs.as_mut().unwrap().iter_mut() {
+            *uv = [uv[1], uv[0]];
+        }
+        mesh.generate_tangents();
+        assert_eq!(mesh.tangents, Some(vec![[0.0, 1.0, 0.0, -1.0]; 4]));
+
+        // nothing to do without uvs
+        let mut mesh = self::mesh("v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n");
+        let before = mesh.clone();
+        mesh.generate_tangents();
+        assert_eq!(mesh, before);
+    }
+
+    #[test]
+    fn tangents_split_mirrored_vertices() {
+        // left half of the quad has mirrored uvs (u decreases along x)
+        let mut mesh = mesh("
+v 0 0 0
+v 1 0 0
+v 2 0 0
+v 0 1 0
+v 1 1 0
+v 2 1 0
+vt 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vt 1 1
+vn 0 0 1
+f 1/1/1 2/2/1 5/5/1 4/4/1
+f 2/2/1 3/3/1 6/6/1 5/5/1
+");
+        assert_eq!(mesh.vertex_count(), 6);
+        mesh.generate_tangents();
+
+        // vertices of the middle edge are used with both signs
+        assert_eq!(mesh.vertex_count(), 8);
+        let tangents = mesh.tangents.as_ref().unwrap();
+        for (corner, index) in mesh.indices.iter().enumerate() {
+            let left = corner < 6;
+            let expected = if left { [-1.0, 0.0, 0.0, -1.0] } else { [1.0, 0.0, 0.0, 1.0] };
+            assert_eq!(tangents[*index as usize], expected);
+        }
+    }
+
+    /// Returns tangent of each corner computed by the reference implementation.
+    fn reference_tangents(mesh: &Mesh) -> Vec<[f32; 4]> {
+        struct Reference<'a>(&'a Mesh, Vec<[f32; 4]>);
+
+        impl ::mikktspace::Geometry for Reference<'_> {
+            fn num_faces(&self) -> usize {
+                self.0.triangle_count()
+            }
+
+            fn num_vertices_of_face(&self, _face: usize) -> usize {
+                3
+            }
+
+            fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+                self.0.positions[self.0.indices[face * 3 + vert] as usize]
+            }
+
+            fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+                self.0.normals.as_ref().unwrap()[self.0.indices[face * 3 + vert] as usize]
+            }
+
+            fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+                self.0.uvs.as_ref().unwrap()[self.0.indices[face * 3 + vert] as usize]
+            }
+
+            fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+                self.1[face * 3 + vert] = tangent;
+            }
+        }
+
+        let mut reference = Reference(mesh, vec![[0.0; 4]; mesh.indices.len()]);
+        ::mikktspace::generate_tangents(&mut reference);
+        reference.1
+    }
+
+    fn assert_reference_tangents(mut mesh: Mesh) -> Mesh {
+        let expected = reference_tangents(&mesh);
+        mesh.generate_tangents();
+        let tangents = mesh.tangents.as_ref().unwrap();
+        for (corner, index) in mesh.indices.iter().enumerate() {
+            assert_eq!(tangents[*index as usize], expected[corner], "corner {}", corner);
+        }
+        mesh
+    }
+
+    /// Returns fan of triangles around vertex `0` on a curved surface, uvs of
+    /// the triangles are alternately mirrored.
+    fn mirrored_fan(n: usize) -> Mesh {
+        let mut mesh = Mesh { positions: vec![[0.0, 0.0, 0.2]], normals: Some(vec![[0.0, 0.0, 1.0]]), uvs: Some(vec![[0.5, 0.5]]), ..Mesh::default() };
+        for v in 0..n {
+            let angle = v as f32 / n as f32 * std::f32::consts::PI * 2.0;
+            mesh.positions.push([angle.cos(), angle.sin(), 0.0]);
+            mesh.normals.as_mut().unwrap().push(normalize([angle.cos(), angle.sin(), 2.0]));
+            mesh.uvs.as_mut().unwrap().push(if v % 2 == 0 { [1.0, 0.5 + v as f32 * 0.01] } else { [0.5, 1.0] });
+            mesh.indices.extend_from_slice(&[0, v as u32 + 1, (v + 1) as u32 % n as u32 + 1]);
+        }
+        mesh
+    }
+
+    #[test]
+    fn tangents_match_reference_in_mirrored_fan() {
+        let mesh = assert_reference_tangents(mirrored_fan(6));
+
+        // triangles around the center are not connected to triangles with the same orientation
+        let center: HashSet<u32> = mesh.indices.iter().step_by(3).copied().collect();
+        assert_eq!(center.len(), 6);
+    }
+
+    #[test]
+    fn tangents_match_reference_in_bow_tie() {
+        // two triangles share only the vertex 0, their tangents are not averaged
+        let mesh = assert_reference_tangents(Mesh {
+            positions: vec![[0.0, 0.0, 0.0], [1.0, -0.5, 0.0], [1.0, 0.5, 0.0], [-1.0, 0.5, 0.0], [-1.0, -0.5, 0.0]],
+            normals: Some(vec![[0.0, 0.0, 1.0]; 5]),
+            uvs: Some(vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [0.0, 1.0], [-0.5, 0.0]]),
+            indices: vec![0, 1, 2, 0, 3, 4],
+            ..Mesh::default()
+        });
+        assert_eq!(mesh.vertex_count(), 6);
+    }
+
+    #[test]
+    fn tangents_match_reference_with_degenerate_triangles() {
+        let mut mesh = grid(4, |x, y| (x * 1.5).sin() * (y * 0.7).cos());
+        mesh.uvs = Some(mesh.positions.iter().map(|p| [(p[0] - 2.0).abs(), p[1] * 0.5]).collect());
+        mesh.normals = Some(mesh.positions.iter().map(|p| normalize([p[2], 0.3, 1.0])).collect());
+        // triangle with the same uvs in all corners and triangle with two identical positions
+        mesh.uvs.as_mut().unwrap()[6] = mesh.uvs.as_ref().unwrap()[1];
+        mesh.uvs.as_mut().unwrap()[7] = mesh.uvs.as_ref().unwrap()[1];
+        mesh.indices.extend_from_slice(&[1, 6, 7, 3, 3, 8]);
+        assert_reference_tangents(mesh);
+    }
+
+    proptest! {
+        #[test]
+        fn tangents_match_reference(
+            vertices in prop::collection::vec((0u8..4, 0u8..4, 0u8..3, 0u8..3, 0u8..3, 0u8..5), 3..24),
+            indices in prop::collection::vec(0usize..24, 3..96),
+        ) {
+            let mesh = Mesh {
+                positions: vertices.iter().map(|v| [v.0 as f32, v.1 as f32, (v.0 * v.1) as f32 * 0.25]).collect(),
+                normals: Some(vertices.iter().map(|v| normalize([v.2 as f32 - 1.0, v.3 as f32 - 1.0, 1.0])).collect()),
+                uvs: Some(vertices.iter().map(|v| [v.4 as f32 * 0.5, v.5 as f32 * 0.25]).collect()),
+                indices: indices.iter().take(indices.len() / 3 * 3).map(|i| (i % vertices.len()) as u32).collect(),
+                ..Mesh::default()
+            };
+            // reference implementation cannot handle meshes with only degenerate triangles
+            let positions = &mesh.positions;
+            prop_assume!(mesh.indices.chunks_exact(3).any(|t| {
+                let p = |k: usize| positions[t[k] as usize];
+                p(0) != p(1) && p(0) != p(2) && p(1) != p(2)
+            }));
+            assert_reference_tangents(mesh);
+        }
+    }
+
+    #[test]
+    fn tangents_are_orthogonal_to_normals() {
+        let mut mesh = grid(8, |x, y| (x * 2.0).sin() * y * 0.5);
+        mesh.uvs = Some(mesh.positions.iter().map(|p| [p[0], p[1]]).collect());
+        mesh.normals = Some(vec![[0.0, 0.0, 1.0]; mesh.vertex_count()]);
+        let triangles = mesh.triangle_count();
+        mesh.generate_tangents();
+
+        assert_eq!(mesh.triangle_count(), triangles);
+        for t in mesh.tangents.as_ref().unwrap().iter() {
+            assert!(t[2].abs() < 1e-6);
+            assert!(((t[0] * t[0] + t[1] * t[1]).sqrt() - 1.0).abs() < 1e-5);
+            assert!(t[0] > 0.5);
+            assert_eq!(t[3], 1.0);
+        }
+    }
+
+    const CUBE: &str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+s 1
+f 1 4 3 2
+f 5 6 7 8
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+s 2
+f 4 1 5 8
+";
+
+    fn normals_of(mesh: &Mesh) -> Vec<[f32; 3]> {
+        mesh.indices.iter().map(|i| mesh.normals.as_ref().unwrap()[*i as usize]).collect()
+    }
+
+    #[test]
+    fn normals_flat_smooth_and_crease() {
+        let mut flat = mesh(CUBE);
+        assert_eq!(flat.vertex_count(), 8);
+        flat.generate_normals(NormalSmoothing::Flat, None);
+        assert_eq!(flat.vertex_count(), 24);
+        assert_eq!(&normals_of(&flat)[..6], &[[0.0, 0.0, -1.0]; 6]);
+
+        let mut smooth = mesh(CUBE);
+        smooth.generate_normals(NormalSmoothing::Smooth, None);
+        assert_eq!(smooth.vertex_count(), 8);
+        let d = 1.0 / 3f32.sqrt();
+        let n = smooth.normals.as_ref().unwrap()[smooth.indices[0] as usize];
+        assert!(n.iter().all(|x| (x.abs() - d).abs() < 1e-6));
+
+        // edges of the cube have 90 degrees
+        let mut crease = mesh(CUBE);
+        crease.generate_normals(NormalSmoothing::Crease(60.0), None);
+        assert_eq!(crease, flat);
+        let mut crease = mesh(CUBE);
+        crease.generate_normals(NormalSmoothing::Crease(100.0), None);
+        assert_eq!(crease, smooth);
+    }
+
+    #[test]
+    fn normals_respect_smoothing_groups() {
+        let obj = parse(CUBE).ok().unwrap();
+        let groups = Mesh::obj_smoothing_groups(&obj);
+        assert_eq!(groups.len(), 12);
+        assert_eq!(groups[0], vec![1]);
+        assert_eq!(groups[11], vec![2]);
+
+        // the last face is alone in its group, its four vertices are split
+        let mut mesh = Mesh::from_obj(&obj);
+        mesh.generate_normals(NormalSmoothing::Smooth, Some(&groups));
+        assert_eq!(mesh.vertex_count(), 12);
+        assert_eq!(&normals_of(&mesh)[30..], &[[-1.0, 0.0, 0.0]; 6]);
+
+        // without any group only the angle is used
+        let mut none = Mesh::from_obj(&obj);
+        none.generate_normals(NormalSmoothing::Smooth, Some(&vec![vec![]; 12]));
+        assert_eq!(none.vertex_count(), 8);
+    }
+
+    #[test]
+    fn normals_replace_existing_normals() {
+        let mut mesh = mesh(QUAD);
+        let before = mesh.clone();
+        mesh.normals = Some(vec![[1.0, 0.0, 0.0]; 4]);
+        mesh.generate_normals(NormalSmoothing::Smooth, None);
+        assert_eq!(mesh, before);
+    }
+
+    #[test]
+    fn mesh_to_geometry() {
+        let mesh = mesh(QUAD);
+        let payload = mesh.to_geometry();
+        let geometry = load_geometry_from_bytes(&payload).ok().unwrap();
+
+        assert_eq!(geometry.positions().unwrap(), mesh.positions.as_slice());
+        assert_eq!(geometry.normals().unwrap(), mesh.normals.as_ref().unwrap().as_slice());
+        assert_eq!(geometry.uvs().unwrap(), mesh.uvs.as_ref().unwrap().as_slice());
+        assert!(geometry.signed_tangents().is_none());
+        match geometry.indices() {
+            Some(Indices::U8(indices)) => assert!(indices.iter().map(|x| *x as u32).eq(mesh.indices.iter().cloned())),
+            other => panic!("unexpected indices {:?}", other),
+        }
+
+        let payload = mesh.to_geometry_with_lods(&[vec![0, 1, 2]]);
+        let geometry = load_geometry_from_bytes(&payload).ok().unwrap();
+        assert_eq!(geometry.lod_count(), 2);
+        assert_matches!(geometry.lod(1), Some(Indices::U8(&[0, 1, 2])));
+    }
+}
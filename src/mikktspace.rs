@@ -0,0 +1,520 @@
+//! Tangent space generation compatible with MikkTSpace.
+//!
+//! This is a port of the reference implementation by Morten S. Mikkelsen
+//! (`genTangSpace` with the default angular threshold) for indexed triangle
+//! meshes. Normal maps are baked by most tools with tangents generated by it,
+//! so the structure and the order of operations (including the order dependent
+//! and quirky parts) follow the reference to produce the same results.
+
+use std::f32::consts::PI;
+
+/// Number of cells of the grid used to find identical vertices.
+const CELLS: usize = 2048;
+
+/// Default angular threshold of the reference implementation (in degrees).
+const ANGULAR_THRESHOLD: f32 = 180.0;
+
+const NONE: usize = usize::MAX;
+
+/// Tangent space of one triangle.
+struct Triangle {
+    /// Index of the triangle in the input.
+    face: usize,
+    /// Triangle has two identical positions.
+    degenerate: bool,
+    /// Tangent space of the triangle cannot be computed from its uvs, the
+    /// triangle joins group of any neighbor (and gets its orientation).
+    any: bool,
+    orientation: bool,
+    os: [f32; 3],
+    ot: [f32; 3],
+    neighbors: [usize; 3],
+    groups: [usize; 3],
+}
+
+/// Triangles around one vertex that are connected over shared edges and have
+/// the same orientation.
+struct Group {
+    vertex: usize,
+    orientation: bool,
+    triangles: Vec<usize>,
+}
+
+/// Geometry accessed by corners, corner `i` of triangle `t` is `t << 2 | i`.
+struct Geometry<'a> {
+    positions: &'a [[f32; 3]],
+    normals: &'a [[f32; 3]],
+    uvs: &'a [[f32; 2]],
+    indices: &'a [u32],
+}
+
+impl Geometry<'_> {
+    fn vertex(&self, corner: usize) -> usize {
+        self.indices[(corner >> 2) * 3 + (corner & 3)] as usize
+    }
+
+    fn position(&self, corner: usize) -> [f32; 3] {
+        self.positions[self.vertex(corner)]
+    }
+
+    fn normal(&self, corner: usize) -> [f32; 3] {
+        self.normals[self.vertex(corner)]
+    }
+
+    fn uv(&self, corner: usize) -> [f32; 2] {
+        self.uvs[self.vertex(corner)]
+    }
+}
+
+/// Returns tangent and its orientation (whether the bitangent is `cross(normal,
+/// tangent)`, otherwise it is negated) of each corner of the triangles.
+pub fn generate(positions: &[[f32; 3]], normals: &[[f32; 3]], uvs: &[[f32; 2]], indices: &[u32]) -> Vec<([f32; 3], bool)> {
+    let geometry = Geometry { positions, normals, uvs, indices };
+    let count = indices.len() / 3;
+    let threshold_cos = ((ANGULAR_THRESHOLD * PI / 180.0) as f64).cos() as f32;
+    let mut spaces = vec![([1.0, 0.0, 0.0], false); count * 3];
+    if count == 0 {
+        return spaces;
+    }
+
+    let mut list: Vec<usize> = (0..count * 3).map(|c| (c / 3) << 2 | (c % 3)).collect();
+    weld(&geometry, &mut list);
+
+    let mut triangles: Vec<Triangle> = (0..count)
+        .map(|t| {
+            let p = |i: usize| geometry.position(list[t * 3 + i]);
+            Triangle {
+                face: t,
+                degenerate: p(0) == p(1) || p(0) == p(2) || p(1) == p(2),
+                any: true,
+                orientation: false,
+                os: [0.0; 3],
+                ot: [0.0; 3],
+                neighbors: [NONE; 3],
+                groups: [NONE; 3],
+            }
+        })
+        .collect();
+    let good = triangles.iter().filter(|t| !t.degenerate).count();
+    move_degenerate_to_end(&mut triangles, &mut list, good);
+
+    for (t, triangle) in triangles.iter_mut().enumerate().take(good) {
+        init_triangle(&geometry, &list[t * 3..t * 3 + 3], triangle);
+    }
+    build_neighbors(&mut triangles[..good], &list);
+    let groups = build_groups(&mut triangles[..good], &list);
+
+    for (g, group) in groups.iter().enumerate() {
+        for &f in group.triangles.iter() {
+            let i = (0..3).find(|i| triangles[f].groups[*i] == g).unwrap();
+            let n = geometry.normal(list[f * 3 + i]);
+            let (os, ot) = (project(triangles[f].os, n), project(triangles[f].ot, n));
+
+            let mut members: Vec<usize> = group.triangles.iter()
+                .copied()
+                .filter(|t| {
+                    let (os2, ot2) = (project(triangles[*t].os, n), project(triangles[*t].ot, n));
+                    triangles[f].any || triangles[*t].any || triangles[f].face == triangles[*t].face
+                        || (dot(os, os2) > threshold_cos && dot(ot, ot2) > threshold_cos)
+                })
+                .collect();
+            members.sort_unstable();
+
+            let tangent = eval_tangent(&geometry, &triangles, &list, &members, group.vertex);
+            spaces[triangles[f].face * 3 + i] = (tangent, group.orientation);
+        }
+    }
+
+    // corners of degenerate triangles use tangent of the first corner with the same vertex
+    for t in good..count {
+        for i in 0..3 {
+            if let Some(j) = (0..good * 3).find(|j| list[*j] == list[t * 3 + i]) {
+                spaces[triangles[t].face * 3 + i] = spaces[triangles[j / 3].face * 3 + j % 3];
+            }
+        }
+    }
+
+    spaces
+}
+
+/// Replaces corners in `list` with the first corner (in the order of the grid
+/// cells) that has the same position, normal and uv.
+fn weld(geometry: &Geometry, list: &mut [usize]) {
+    let mut min = geometry.position(0);
+    let mut max = min;
+    for corner in list.iter().skip(1) {
+        let p = geometry.position(*corner);
+        for c in 0..3 {
+            if min[c] > p[c] {
+                min[c] = p[c];
+            } else if max[c] < p[c] {
+                max[c] = p[c];
+            }
+        }
+    }
+
+    let dim = sub(max, min);
+    let channel = if dim[1] > dim[0] && dim[1] > dim[2] {
+        1
+    } else if dim[2] > dim[0] {
+        2
+    } else {
+        0
+    };
+    let cell = |corner: usize| {
+        let index = (CELLS as f32 * ((geometry.position(corner)[channel] - min[channel]) / (max[channel] - min[channel]))) as isize;
+        index.clamp(0, CELLS as isize - 1) as usize
+    };
+
+    let mut cells: Vec<Vec<usize>> = vec![vec![]; CELLS];
+    for (i, corner) in list.iter().enumerate() {
+        cells[cell(*corner)].push(i);
+    }
+    for entries in cells.iter().filter(|e| e.len() >= 2) {
+        let mut vertices: Vec<([f32; 3], usize)> = entries.iter().map(|i| (geometry.position(list[*i]), *i)).collect();
+        let last = vertices.len() - 1;
+        merge_vertices(geometry, list, &mut vertices, 0, last);
+    }
+}
+
+/// Splits the vertices in half until all have the same position, then merges
+/// the identical ones.
+fn merge_vertices(geometry: &Geometry, list: &mut [usize], vertices: &mut [([f32; 3], usize)], left: usize, right: usize) {
+    let mut min = vertices[left].0;
+    let mut max = min;
+    for (p, _) in vertices[left + 1..=right].iter() {
+        for c in 0..3 {
+            if min[c] > p[c] {
+                min[c] = p[c];
+            } else if max[c] < p[c] {
+                max[c] = p[c];
+            }
+        }
+    }
+
+    let d = sub(max, min);
+    let channel = if d[1] > d[0] && d[1] > d[2] {
+        1
+    } else if d[2] > d[0] {
+        2
+    } else {
+        0
+    };
+    let separator = 0.5 * (max[channel] + min[channel]);
+
+    if separator >= max[channel] || separator <= min[channel] {
+        for l in left..=right {
+            let i = vertices[l].1;
+            let corner = list[i];
+            let same = (left..l).map(|l2| vertices[l2].1).find(|i2| {
+                let other = list[*i2];
+                geometry.position(corner) == geometry.position(other)
+                    && geometry.normal(corner) == geometry.normal(other)
+                    && geometry.uv(corner) == geometry.uv(other)
+            });
+            if let Some(i2) = same {
+                list[i] = list[i2];
+            }
+        }
+        return;
+    }
+
+    let (mut l, mut r) = (left as isize, right as isize);
+    let below = |vertices: &[([f32; 3], usize)], i: isize| vertices[i as usize].0[channel] < separator;
+    while l < r {
+        let (mut ready_left, mut ready_right) = (false, false);
+        while !ready_left && l < r {
+            ready_left = !below(vertices, l);
+            if !ready_left {
+                l += 1;
+            }
+        }
+        while !ready_right && l < r {
+            ready_right = below(vertices, r);
+            if !ready_right {
+                r -= 1;
+            }
+        }
+        if ready_left && ready_right {
+            vertices.swap(l as usize, r as usize);
+            l += 1;
+            r -= 1;
+        }
+    }
+    if l == r {
+        if below(vertices, r) {
+            l += 1;
+        } else {
+            r -= 1;
+        }
+    }
+
+    if (left as isize) < r {
+        merge_vertices(geometry, list, vertices, left, r as usize);
+    }
+    if l < right as isize {
+        merge_vertices(geometry, list, vertices, l as usize, right);
+    }
+}
+
+/// Moves the degenerate triangles after the `good` ones by swapping each of
+/// them with the next good triangle.
+fn move_degenerate_to_end(triangles: &mut [Triangle], list: &mut [usize], good: usize) {
+    let mut next = 1;
+    for t in 0..good {
+        if !triangles[t].degenerate {
+            next = next.max(t + 2);
+            continue;
+        }
+        while next < triangles.len() && triangles[next].degenerate {
+            next += 1;
+        }
+        if next >= triangles.len() {
+            break;
+        }
+        triangles.swap(t, next);
+        for i in 0..3 {
+            list.swap(t * 3 + i, next * 3 + i);
+        }
+        next += 1;
+    }
+}
+
+fn init_triangle(geometry: &Geometry, corners: &[usize], triangle: &mut Triangle) {
+    let (v1, v2, v3) = (geometry.position(corners[0]), geometry.position(corners[1]), geometry.position(corners[2]));
+    let (t1, t2, t3) = (geometry.uv(corners[0]), geometry.uv(corners[1]), geometry.uv(corners[2]));
+    let (t21x, t21y) = (t2[0] - t1[0], t2[1] - t1[1]);
+    let (t31x, t31y) = (t3[0] - t1[0], t3[1] - t1[1]);
+    let (d1, d2) = (sub(v2, v1), sub(v3, v1));
+
+    let signed_area = t21x * t31y - t21y * t31x;
+    let os = sub(scale(d1, t31y), scale(d2, t21y));
+    let ot = add(scale(d1, -t31x), scale(d2, t21x));
+    triangle.orientation = signed_area > 0.0;
+
+    if not_zero(signed_area) {
+        let s = if triangle.orientation { 1.0 } else { -1.0 };
+        let (length_os, length_ot) = (length(os), length(ot));
+        if not_zero(length_os) {
+            triangle.os = scale(os, s / length_os);
+        }
+        if not_zero(length_ot) {
+            triangle.ot = scale(ot, s / length_ot);
+        }
+        triangle.any = !(not_zero(length_os / signed_area.abs()) && not_zero(length_ot / signed_area.abs()));
+    }
+}
+
+/// Finds neighbors of the triangles (sharing an edge with opposite winding).
+/// Edges are sorted the same way as in the reference implementation, which
+/// decides which triangles are paired on edges used by more of them.
+fn build_neighbors(triangles: &mut [Triangle], list: &[usize]) {
+    const SEED: u32 = 39871946;
+
+    let mut edges: Vec<[usize; 3]> = (0..triangles.len() * 3)
+        .map(|c| {
+            let (i0, i1) = (list[c], list[c / 3 * 3 + (c % 3 + 1) % 3]);
+            [i0.min(i1), i0.max(i1), c / 3]
+        })
+        .collect();
+
+    if edges.is_empty() {
+        return;
+    }
+
+    let last = edges.len() - 1;
+    sort_edges(&mut edges, 0, last, 0, SEED);
+    // the last run of the same values is never sorted by the next channel
+    for channel in 1..3 {
+        let mut start = 0;
+        for i in 1..edges.len() {
+            if edges[start][..channel] != edges[i][..channel] {
+                sort_edges(&mut edges, start, i - 1, channel, SEED);
+                start = i;
+            }
+        }
+    }
+
+    for i in 0..edges.len() {
+        let [i0, i1, f] = edges[i];
+        let (a0, a1, edge_a) = find_edge(&list[f * 3..f * 3 + 3], i0, i1);
+        if triangles[f].neighbors[edge_a] != NONE {
+            continue;
+        }
+
+        let mut j = i + 1;
+        while j < edges.len() && edges[j][0] == i0 && edges[j][1] == i1 {
+            let t = edges[j][2];
+            let (b1, b0, edge_b) = find_edge(&list[t * 3..t * 3 + 3], i0, i1);
+            if a0 == b0 && a1 == b1 && triangles[t].neighbors[edge_b] == NONE {
+                triangles[f].neighbors[edge_a] = t;
+                triangles[t].neighbors[edge_b] = f;
+                break;
+            }
+            j += 1;
+        }
+    }
+}
+
+/// Returns the edge of triangle with vertices `i0` and `i1` (in the winding
+/// order of the triangle) and its number.
+fn find_edge(corners: &[usize], i0: usize, i1: usize) -> (usize, usize, usize) {
+    let has = |c: usize| corners[c] == i0 || corners[c] == i1;
+    if has(0) {
+        if has(1) {
+            (corners[0], corners[1], 0)
+        } else {
+            (corners[2], corners[0], 2)
+        }
+    } else {
+        (corners[1], corners[2], 1)
+    }
+}
+
+/// Quicksort of the edges by one channel with the pseudo-random pivot of the
+/// reference implementation.
+fn sort_edges(edges: &mut [[usize; 3]], left: usize, right: usize, channel: usize, seed: u32) {
+    let count = right - left + 1;
+    if count < 2 {
+        return;
+    }
+    if count == 2 {
+        if edges[left][channel] > edges[right][channel] {
+            edges.swap(left, right);
+        }
+        return;
+    }
+
+    let t = seed & 31;
+    let t = seed.rotate_left(t) | seed.rotate_right(32u32.wrapping_sub(t));
+    let seed = seed.wrapping_add(t).wrapping_add(3);
+
+    let (mut l, mut r) = (left as isize, right as isize);
+    let mid = edges[seed as usize % count + left][channel];
+    loop {
+        while edges[l as usize][channel] < mid {
+            l += 1;
+        }
+        while edges[r as usize][channel] > mid {
+            r -= 1;
+        }
+        if l <= r {
+            edges.swap(l as usize, r as usize);
+            l += 1;
+            r -= 1;
+        }
+        if l > r {
+            break;
+        }
+    }
+
+    if (left as isize) < r {
+        sort_edges(edges, left, r as usize, channel, seed);
+    }
+    if l < right as isize {
+        sort_edges(edges, l as usize, right, channel, seed);
+    }
+}
+
+/// Creates groups of triangles around each vertex, starting at triangles with
+/// valid tangent space (in order) and spreading to their neighbors.
+fn build_groups(triangles: &mut [Triangle], list: &[usize]) -> Vec<Group> {
+    let mut groups: Vec<Group> = vec![];
+    for t in 0..triangles.len() {
+        for i in 0..3 {
+            if triangles[t].any || triangles[t].groups[i] != NONE {
+                continue;
+            }
+            let g = groups.len();
+            groups.push(Group { vertex: list[t * 3 + i], orientation: triangles[t].orientation, triangles: vec![t] });
+            triangles[t].groups[i] = g;
+
+            let (left, right) = (triangles[t].neighbors[i], triangles[t].neighbors[(i + 2) % 3]);
+            if left != NONE {
+                assign(triangles, &mut groups[g], g, list, left);
+            }
+            if right != NONE {
+                assign(triangles, &mut groups[g], g, list, right);
+            }
+        }
+    }
+    groups
+}
+
+fn assign(triangles: &mut [Triangle], group: &mut Group, g: usize, list: &[usize], t: usize) {
+    let i = match (0..3).find(|i| list[t * 3 + i] == group.vertex) {
+        Some(i) => i,
+        None => return,
+    };
+    if triangles[t].groups[i] != NONE {
+        return;
+    }
+    // first group that reaches triangle without tangent space decides its orientation
+    if triangles[t].any && triangles[t].groups.iter().all(|g| *g == NONE) {
+        triangles[t].orientation = group.orientation;
+    }
+    if triangles[t].orientation != group.orientation {
+        return;
+    }
+
+    group.triangles.push(t);
+    triangles[t].groups[i] = g;
+    let (left, right) = (triangles[t].neighbors[i], triangles[t].neighbors[(i + 2) % 3]);
+    if left != NONE {
+        assign(triangles, group, g, list, left);
+    }
+    if right != NONE {
+        assign(triangles, group, g, list, right);
+    }
+}
+
+/// Returns average of tangents of the triangles at the vertex (projected to
+/// the plane of the normal) weighted by their angle at the vertex.
+fn eval_tangent(geometry: &Geometry, triangles: &[Triangle], list: &[usize], members: &[usize], vertex: usize) -> [f32; 3] {
+    let mut sum = [0.0; 3];
+    for &t in members.iter().filter(|t| !triangles[**t].any) {
+        let i = (0..3).find(|i| list[t * 3 + i] == vertex).unwrap();
+        let n = geometry.normal(list[t * 3 + i]);
+        let p = |k: usize| geometry.position(list[t * 3 + k]);
+        let (p0, p1, p2) = (p((i + 2) % 3), p(i), p((i + 1) % 3));
+
+        let (v1, v2) = (project(sub(p0, p1), n), project(sub(p2, p1), n));
+        let angle = (dot(v1, v2).clamp(-1.0, 1.0) as f64).acos() as f32;
+        sum = add(sum, scale(project(triangles[t].os, n), angle));
+    }
+    normalize(sum)
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(a: [f32; 3]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+fn not_zero(x: f32) -> bool {
+    x.abs() > f32::MIN_POSITIVE
+}
+
+/// Returns the vector with unit length, vectors with zero length are returned unchanged.
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    if a.iter().any(|x| not_zero(*x)) { scale(a, 1.0 / length(a)) } else { a }
+}
+
+/// Returns the vector projected to the plane with normal `n` with unit length.
+fn project(v: [f32; 3], n: [f32; 3]) -> [f32; 3] {
+    normalize(sub(v, scale(n, dot(n, v))))
+}